@@ -1,21 +1,9 @@
 use std::time::Instant;
 
-mod camera;
-mod color;
-mod graphics_primitives;
-mod math;
-mod mesh;
-mod rasterizer;
-mod render;
-mod texture;
-mod uniform;
-
-use math::WorldSpace;
-
-use crate::color::Color;
-use crate::graphics_primitives::VertexAttribute;
-use crate::render::*;
-use crate::uniform::Uniforms;
+use rusterizer::math::{self, WorldSpace};
+use rusterizer::{
+    camera, mesh, shaders, texture, FragmentShader, PipelineState, Renderer, Sampler,
+};
 
 const WIDTH: usize = 1280;
 const HEIGHT: usize = 720;
@@ -66,13 +54,9 @@ fn parse_args() -> Args {
 
 fn choose_shader(fs: FS) -> FragmentShader {
     match fs {
-        FS::Texture => |uniforms: &Uniforms, _: &rasterizer::FragCoords, attr: &VertexAttribute| {
-            uniforms.get_texture(0).sample(attr.uvs[0], attr.uvs[1])
-        },
-        FS::Color => |_: &Uniforms, _: &rasterizer::FragCoords, attr: &VertexAttribute| attr.color,
-        FS::Debug => |_: &Uniforms, frag_coords: &rasterizer::FragCoords, _: &VertexAttribute| {
-            Color::grayscale(frag_coords.depths[0])
-        },
+        FS::Texture => shaders::texture_fs,
+        FS::Color => shaders::color_fs,
+        FS::Debug => shaders::debug_fs,
     }
 }
 
@@ -141,21 +125,23 @@ fn main() {
         std::f32::consts::FRAC_PI_2,
     );
 
-    let tex = texture::Texture::from_png_file("images/checkerboard.png");
+    let tex = texture::Texture::from_png_file("images/checkerboard.png", texture::MipFilter::Box);
     renderer.uniforms().bind_texture(0, tex);
-
-    let vertex_shader = |uniforms: &Uniforms, vertex: &math::Point3D<math::WorldSpace>| {
-        uniforms.read_block().projection
-            * uniforms.read_block().view
-            * uniforms.read_block().world
-            * vertex.extend(1.0)
-    };
+    renderer.uniforms().bind_sampler(
+        0,
+        Sampler {
+            max_anisotropy: 16,
+            ..Default::default()
+        },
+    );
 
     let fragment_shader = choose_shader(args.fs);
+    let state = PipelineState::default();
     let (mut scene, update) = setup_scene(args.mode);
 
     let start = Instant::now();
     let mut now = Instant::now();
+    let mut n_screenshots = 0;
     loop {
         renderer.display_frame_time(&now.elapsed());
         now = Instant::now();
@@ -169,7 +155,7 @@ fn main() {
 
         for (mesh, mat) in scene.meshes.iter().zip(scene.matrices.iter()) {
             renderer.uniforms().write_block().world = *mat;
-            renderer.render(mesh, vertex_shader, fragment_shader);
+            renderer.render(mesh, &state, shaders::mvp_vs, fragment_shader);
         }
 
         match renderer.display() {
@@ -180,5 +166,18 @@ fn main() {
             Ok(false) => return,
             Ok(true) => (),
         }
+
+        if renderer
+            .target()
+            .window()
+            .is_key_pressed(minifb::Key::P, minifb::KeyRepeat::No)
+        {
+            let path = format!("screenshot_{}.png", n_screenshots);
+            match renderer.save_frame(&path) {
+                Ok(()) => println!("Saved {}", path),
+                Err(e) => println!("Failed to save {}: {}", path, e),
+            }
+            n_screenshots += 1;
+        }
     }
 }
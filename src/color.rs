@@ -2,8 +2,42 @@ use core::ops::Add;
 use core::ops::Div;
 use core::ops::Mul;
 use core::ops::Sub;
+use std::sync::OnceLock;
 
-#[derive(Debug, Copy, Clone, Default)]
+/// The sRGB transfer function, from encoded to linear
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The inverse sRGB transfer function, from linear to encoded
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Lookup table for 8-bit sRGB channels, e.g. of textures
+pub(crate) fn srgb8_to_linear(v: u8) -> f32 {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|i| srgb_to_linear(i as f32 / 255.0)))[v as usize]
+}
+
+// Same as rounding `linear_to_srgb(v) * 255`, clamped, without evaluating the transfer function:
+// the encoded value is the number of boundaries between two encoded values below v.
+pub(crate) fn linear_to_srgb8(v: f32) -> u8 {
+    static BOUNDARIES: OnceLock<[f32; 255]> = OnceLock::new();
+    let boundaries = BOUNDARIES
+        .get_or_init(|| std::array::from_fn(|i| srgb_to_linear((i as f32 + 0.5) / 255.0)));
+    boundaries.partition_point(|&b| b <= v) as u8
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -12,11 +46,57 @@ pub struct Color {
 }
 
 impl Color {
+    /// 8 bits per channel, clamped to [0, 1] and rounded
     pub fn to_argb(self) -> u32 {
-        ((self.a * 255.0) as u32) << 24
-            | ((self.r * 255.0) as u32) << 16
-            | ((self.g * 255.0) as u32) << 8
-            | ((self.b * 255.0) as u32)
+        let to_u8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u32;
+        to_u8(self.a) << 24 | to_u8(self.r) << 16 | to_u8(self.g) << 8 | to_u8(self.b)
+    }
+
+    pub fn from_argb(argb: u32) -> Self {
+        Color {
+            r: ((argb >> 16) & 0xFF) as f32 / 255.0,
+            g: ((argb >> 8) & 0xFF) as f32 / 255.0,
+            b: (argb & 0xFF) as f32 / 255.0,
+            a: (argb >> 24) as f32 / 255.0,
+        }
+    }
+
+    /// All channels clamped to [0, 1]
+    pub fn saturate(self) -> Self {
+        Color {
+            r: self.r.clamp(0.0, 1.0),
+            g: self.g.clamp(0.0, 1.0),
+            b: self.b.clamp(0.0, 1.0),
+            a: self.a.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn to_array(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    pub fn from_array([r, g, b, a]: [f32; 4]) -> Self {
+        Color { r, g, b, a }
+    }
+
+    /// The color channels decoded from sRGB to linear, alpha is kept as is
+    pub fn decode_srgb(self) -> Self {
+        Color {
+            r: srgb_to_linear(self.r),
+            g: srgb_to_linear(self.g),
+            b: srgb_to_linear(self.b),
+            a: self.a,
+        }
+    }
+
+    /// The color channels encoded from linear to sRGB, alpha is kept as is
+    pub fn encode_srgb(self) -> Self {
+        Color {
+            r: linear_to_srgb(self.r),
+            g: linear_to_srgb(self.g),
+            b: linear_to_srgb(self.b),
+            a: self.a,
+        }
     }
 
     pub fn from_rgba(rgba: [u8; 4]) -> Self {
@@ -153,4 +233,59 @@ mod test {
         };
         assert_eq!(c.to_argb(), 0xFF000000);
     }
+
+    #[test]
+    fn argb_roundtrip() {
+        for argb in [0xFFFFFFFF, 0x00000000, 0x80FF0000, 0xFF123456, 0x7F00FF00] {
+            assert_eq!(Color::from_argb(argb).to_argb(), argb, "{:x}", argb);
+        }
+        let c = Color::from_argb(0x80FF0000);
+        assert_eq!((c.r, c.g, c.b), (1.0, 0.0, 0.0));
+        assert_eq!(c.a, 128.0 / 255.0);
+    }
+
+    #[test]
+    fn srgb() {
+        for v in [0.0, 0.002, 0.01, 0.2, 0.5, 0.8, 1.0] {
+            assert!(
+                (srgb_to_linear(linear_to_srgb(v)) - v).abs() < 1e-6,
+                "{}",
+                v
+            );
+        }
+        assert!((linear_to_srgb(0.5) - 0.7354).abs() < 1e-4);
+        assert!((srgb_to_linear(0.5) - 0.2140).abs() < 1e-4);
+
+        let c = Color::grayscale(0.5).decode_srgb();
+        assert_eq!(c.a, 1.0);
+        assert!((c.encode_srgb().r - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn srgb8_tables() {
+        for i in 0..=255u8 {
+            let v = srgb_to_linear(i as f32 / 255.0);
+            assert_eq!(srgb8_to_linear(i), v);
+            assert_eq!(linear_to_srgb8(v), i);
+        }
+        // Rounded to the closest encoded value and clamped
+        for v in [-1.0, 0.0, 1e-4, 0.003, 0.05, 0.3, 0.7, 0.999, 1.0, 2.0] {
+            let expected = (linear_to_srgb(v).clamp(0.0, 1.0) * 255.0).round() as u8;
+            assert_eq!(linear_to_srgb8(v), expected, "{}", v);
+        }
+    }
+
+    #[test]
+    fn argb_clamps_and_rounds() {
+        // Out of range channels must not spill into their neighbours
+        let hdr = Color {
+            r: 4.0,
+            g: -1.0,
+            b: 0.5,
+            a: 1.5,
+        };
+        assert_eq!(hdr.to_argb(), 0xFFFF0080);
+        assert_eq!(Color::grayscale(0.999).to_argb(), 0xFFFFFFFF);
+        assert_eq!(Color::grayscale(0.001).to_argb(), 0xFF000000);
+    }
 }
@@ -0,0 +1,839 @@
+// Loader for glTF 2.0 scenes, both .gltf (JSON) and .glb (binary) files.
+// See https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+//
+// The default scene (or the first one if there is no default) is flattened: every node with a mesh
+// becomes an instance with its world matrix and every node with a camera becomes a camera with a
+// view matrix. Buffers and images are read from the GLB binary chunk, data URIs or files relative
+// to the glTF file. Only PNG images are supported and skins, morph targets, animations and
+// point/line primitives are ignored.
+//
+// glTF is right-handed with counter-clockwise front faces while WorldSpace is left-handed with
+// clockwise front faces. Like for OBJ files, z is negated and the winding order of each triangle
+// is reversed. Node matrices are converted with the same flip, i.e. S * M * S with
+// S = diag(1, 1, -1, 1). glTF camera space is the same as CameraSpace (x right, y up, looking down
+// negative z) so the view matrix is inverse(S * M * S) * S = S * inverse(M) if M is the
+// (converted) world matrix of the camera node.
+// Texture coordinates already have their origin in the upper left corner, same as `Texture`.
+
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+
+use crate::color::Color;
+use crate::graphics_primitives::VertexAttribute;
+use crate::math::{self, vec3, CameraSpace, ClipSpace, Mat4, Point3D, Vec3, WorldSpace};
+use crate::mesh::Mesh;
+use crate::texture::{MipFilter, Texture};
+
+#[derive(Debug)]
+pub enum GltfError {
+    Gltf(::gltf::Error),
+    Io(PathBuf, std::io::Error),
+    Base64(base64::DecodeError),
+    Png(png::DecodingError),
+    /// The file is valid glTF but uses something that the loader does not support
+    Unsupported(String),
+    /// Data that the glTF validation does not catch, e.g. out of bounds indices
+    Invalid(String),
+}
+
+impl std::fmt::Display for GltfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GltfError::Gltf(e) => write!(f, "{}", e),
+            GltfError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            GltfError::Base64(e) => write!(f, "Invalid data uri: {}", e),
+            GltfError::Png(e) => write!(f, "Invalid png: {}", e),
+            GltfError::Unsupported(message) => write!(f, "Unsupported: {}", message),
+            GltfError::Invalid(message) => write!(f, "Invalid: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Gltf(e) => Some(e),
+            GltfError::Io(_, e) => Some(e),
+            GltfError::Base64(e) => Some(e),
+            GltfError::Png(e) => Some(e),
+            GltfError::Unsupported(_) | GltfError::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<::gltf::Error> for GltfError {
+    fn from(e: ::gltf::Error) -> Self {
+        GltfError::Gltf(e)
+    }
+}
+
+pub struct GltfPrimitive {
+    /// The vertex colors are COLOR_0 (white if missing) multiplied with the base color factor
+    pub mesh: Mesh<WorldSpace>,
+    /// Per vertex normals, empty if the primitive has none.
+    pub normals: Vec<Vec3<WorldSpace>>,
+    /// Index into `GltfScene::textures`
+    pub base_color_texture: Option<usize>,
+}
+
+/// A glTF mesh, one `Mesh` per primitive as each primitive has its own material.
+pub struct GltfMesh {
+    pub name: String,
+    pub primitives: Vec<GltfPrimitive>,
+}
+
+/// A node that references a mesh, `world` includes the transforms of all parent nodes.
+pub struct GltfInstance {
+    /// Index into `GltfScene::meshes`
+    pub mesh: usize,
+    pub world: Mat4<WorldSpace>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GltfProjection {
+    Perspective {
+        yfov: f32,
+        /// width / height, the viewport is used if None
+        aspect_ratio: Option<f32>,
+        znear: f32,
+        /// Infinite projection if None
+        zfar: Option<f32>,
+    },
+    Orthographic {
+        xmag: f32,
+        ymag: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+pub struct GltfCamera {
+    /// The camera's name, or the node's if the camera has none
+    pub name: String,
+    pub view: Mat4<WorldSpace, CameraSpace>,
+    pub projection: GltfProjection,
+}
+
+impl GltfCamera {
+    /// `width` and `height` of the viewport are only used if the camera has no aspect ratio.
+    pub fn projection_matrix(&self, width: usize, height: usize) -> Mat4<CameraSpace, ClipSpace> {
+        match self.projection {
+            GltfProjection::Perspective {
+                yfov,
+                aspect_ratio,
+                znear,
+                zfar,
+            } => {
+                let aspect_ratio = aspect_ratio.unwrap_or(width as f32 / height as f32);
+                // math::project takes the horizontal fov and height / width
+                let xfov = 2.0 * ((yfov / 2.0).tan() * aspect_ratio).atan();
+                match zfar {
+                    Some(zfar) => math::project(znear, zfar, 1.0 / aspect_ratio, xfov),
+                    None => math::project_infinite(znear, 1.0 / aspect_ratio, xfov),
+                }
+            }
+            GltfProjection::Orthographic {
+                xmag,
+                ymag,
+                znear,
+                zfar,
+            } => math::orthographic(xmag, ymag, znear, zfar),
+        }
+    }
+}
+
+pub struct GltfScene {
+    pub meshes: Vec<GltfMesh>,
+    pub instances: Vec<GltfInstance>,
+    /// One per glTF image, in the same order
+    pub textures: Vec<Texture>,
+    pub cameras: Vec<GltfCamera>,
+}
+
+impl GltfScene {
+    pub fn base_color_texture(&self, primitive: &GltfPrimitive) -> Option<&Texture> {
+        primitive.base_color_texture.map(|i| &self.textures[i])
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, GltfError> {
+    std::fs::read(path).map_err(|e| GltfError::Io(path.to_path_buf(), e))
+}
+
+// Either a base64 data uri or a path relative to the glTF file
+fn read_uri(uri: &str, base_dir: &Path) -> Result<Vec<u8>, GltfError> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let Some((header, payload)) = data.split_once(',') else {
+            return Err(GltfError::Invalid(format!("Malformed data uri: {}", uri)));
+        };
+        if !header.ends_with(";base64") {
+            return Err(GltfError::Unsupported(format!(
+                "Data uri without base64 encoding: {}",
+                header
+            )));
+        }
+        return base64::engine::general_purpose::STANDARD
+            .decode(payload)
+            .map_err(GltfError::Base64);
+    }
+
+    if uri.contains("://") {
+        return Err(GltfError::Unsupported(format!("Uri scheme: {}", uri)));
+    }
+    read_file(&base_dir.join(percent_decode(uri)?))
+}
+
+// Relative uris are percent-encoded, e.g. spaces in file names are %20
+fn percent_decode(uri: &str) -> Result<String, GltfError> {
+    let invalid = || GltfError::Invalid(format!("Malformed percent-encoding: {}", uri));
+    let hex = |c: &u8| (*c as char).to_digit(16);
+    let mut bytes = Vec::with_capacity(uri.len());
+    let mut rest = uri.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let (Some(hi), Some(lo)) = (tail.first().and_then(hex), tail.get(1).and_then(hex))
+            else {
+                return Err(invalid());
+            };
+            bytes.push((hi * 16 + lo) as u8);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+fn load_buffers(gltf: &::gltf::Gltf, base_dir: &Path) -> Result<Vec<Vec<u8>>, GltfError> {
+    let mut buffers = Vec::new();
+    for buffer in gltf.document.buffers() {
+        let data = match buffer.source() {
+            ::gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .ok_or_else(|| GltfError::Invalid("GLB without a binary chunk".to_string()))?,
+            ::gltf::buffer::Source::Uri(uri) => read_uri(uri, base_dir)?,
+        };
+        // The GLB binary chunk may be padded, so only too short is an error
+        if data.len() < buffer.length() {
+            return Err(GltfError::Invalid(format!(
+                "Buffer {} has {} bytes, expected {}",
+                buffer.index(),
+                data.len(),
+                buffer.length()
+            )));
+        }
+        buffers.push(data);
+    }
+    Ok(buffers)
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn load_textures(
+    gltf: &::gltf::Gltf,
+    buffers: &[Vec<u8>],
+    base_dir: &Path,
+    mip_filter: MipFilter,
+) -> Result<Vec<Texture>, GltfError> {
+    let mut textures = Vec::new();
+    for image in gltf.document.images() {
+        let data = match image.source() {
+            ::gltf::image::Source::View { view, .. } => {
+                let buffer = &buffers[view.buffer().index()];
+                buffer
+                    .get(view.offset()..view.offset() + view.length())
+                    .ok_or_else(|| {
+                        GltfError::Invalid(format!(
+                            "Image {}: buffer view {} is out of bounds",
+                            image.index(),
+                            view.index()
+                        ))
+                    })?
+                    .to_vec()
+            }
+            ::gltf::image::Source::Uri { uri, .. } => read_uri(uri, base_dir)?,
+        };
+
+        // Check the data rather than the mime type as the latter is optional for uris
+        if !data.starts_with(PNG_SIGNATURE) {
+            return Err(GltfError::Unsupported(format!(
+                "Image {}: only png images are supported",
+                image.index()
+            )));
+        }
+        let texture = Texture::from_png_reader(data.as_slice(), mip_filter);
+        textures.push(texture.map_err(GltfError::Png)?);
+    }
+    Ok(textures)
+}
+
+fn triangle_list(mode: ::gltf::mesh::Mode, indices: Vec<usize>) -> Option<Vec<usize>> {
+    use ::gltf::mesh::Mode;
+    let n_triangles = indices.len().saturating_sub(2);
+    match mode {
+        Mode::Triangles => Some(indices),
+        // Every other triangle in a strip has its winding order reversed, swap two vertices to
+        // make them consistent.
+        Mode::TriangleStrip => Some(
+            (0..n_triangles)
+                .flat_map(|i| {
+                    if i % 2 == 0 {
+                        [indices[i], indices[i + 1], indices[i + 2]]
+                    } else {
+                        [indices[i + 1], indices[i], indices[i + 2]]
+                    }
+                })
+                .collect(),
+        ),
+        Mode::TriangleFan => Some(
+            (0..n_triangles)
+                .flat_map(|i| [indices[0], indices[i + 1], indices[i + 2]])
+                .collect(),
+        ),
+        Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => None,
+    }
+}
+
+fn load_primitive(
+    primitive: &::gltf::Primitive,
+    buffers: &[Vec<u8>],
+) -> Result<Option<GltfPrimitive>, GltfError> {
+    let pbr = primitive.material().pbr_metallic_roughness();
+    let base_color_info = pbr.base_color_texture();
+    let [r, g, b, a] = pbr.base_color_factor();
+
+    let reader = primitive.reader(|buffer| Some(buffers[buffer.index()].as_slice()));
+    let Some(positions) = reader.read_positions() else {
+        // Without positions there is nothing to draw, but a POSITION accessor that can't be read
+        // is an error
+        if primitive.get(&::gltf::Semantic::Positions).is_some() {
+            return Err(GltfError::Invalid(format!(
+                "The positions of primitive {} are outside of their buffer",
+                primitive.index()
+            )));
+        }
+        return Ok(None);
+    };
+    let vertices: Vec<Point3D<WorldSpace>> =
+        positions.map(|[x, y, z]| Point3D::new(x, y, -z)).collect();
+    let n_vertices = vertices.len();
+
+    let indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+        None => (0..n_vertices).collect(),
+    };
+    let Some(indices) = triangle_list(primitive.mode(), indices) else {
+        return Ok(None);
+    };
+    if indices.len() % 3 != 0 {
+        return Err(GltfError::Invalid(format!(
+            "Primitive {} has {} indices, not a multiple of 3",
+            primitive.index(),
+            indices.len()
+        )));
+    }
+    if let Some(&idx) = indices.iter().find(|&&i| i >= n_vertices) {
+        return Err(GltfError::Invalid(format!(
+            "Index {} out of bounds (have {} vertices)",
+            idx, n_vertices
+        )));
+    }
+    // Counter-clockwise to clockwise
+    let indices = indices
+        .chunks(3)
+        .flat_map(|tri| [tri[0], tri[2], tri[1]])
+        .collect();
+
+    let normals: Vec<_> = reader
+        .read_normals()
+        .map(|normals| normals.map(|[x, y, z]| vec3(x, y, -z)).collect())
+        .unwrap_or_default();
+
+    let tex_coord_set = base_color_info.as_ref().map_or(0, |info| info.tex_coord());
+    let uvs: Vec<[f32; 2]> = reader
+        .read_tex_coords(tex_coord_set)
+        .map(|uvs| uvs.into_f32().collect())
+        .unwrap_or_else(|| vec![[0.0, 0.0]; n_vertices]);
+    let colors: Vec<[f32; 4]> = reader
+        .read_colors(0)
+        .map(|colors| colors.into_rgba_f32().collect())
+        .unwrap_or_else(|| vec![[1.0; 4]; n_vertices]);
+
+    // The accessor counts are not checked against each other by the gltf crate. Normals are
+    // optional.
+    let counts = [("uvs", uvs.len()), ("colors", colors.len())];
+    let normals_count = (!normals.is_empty()).then_some(("normals", normals.len()));
+    for (name, len) in counts.into_iter().chain(normals_count) {
+        if len != n_vertices {
+            return Err(GltfError::Invalid(format!(
+                "Primitive {} has {} vertices but {} {}",
+                primitive.index(),
+                n_vertices,
+                len,
+                name
+            )));
+        }
+    }
+
+    let attributes = colors
+        .iter()
+        .zip(uvs.iter())
+        .map(|(c, &uvs)| VertexAttribute {
+            color: Color {
+                r: c[0] * r,
+                g: c[1] * g,
+                b: c[2] * b,
+                a: c[3] * a,
+            },
+            uvs,
+        })
+        .collect();
+
+    Ok(Some(GltfPrimitive {
+        mesh: Mesh {
+            vertices,
+            indices,
+            attributes,
+        },
+        normals,
+        base_color_texture: base_color_info.map(|info| info.texture().source().index()),
+    }))
+}
+
+// glTF matrices are column-major, Mat4 is row-major
+fn node_matrix(node: &::gltf::Node) -> Mat4<WorldSpace> {
+    let m = Mat4::<WorldSpace>::from_raw(&node.transform().matrix()).transpose();
+    // S * M * S negates the elements where exactly one of the row and column is z
+    let mut rows = [m.row(0), m.row(1), m.row(2), m.row(3)];
+    for (i, row) in rows.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            if (i == 2) != (j == 2) {
+                *v = -*v;
+            }
+        }
+    }
+    Mat4::from_raw(&rows)
+}
+
+fn camera_projection(camera: &::gltf::Camera) -> GltfProjection {
+    match camera.projection() {
+        ::gltf::camera::Projection::Perspective(p) => GltfProjection::Perspective {
+            yfov: p.yfov(),
+            aspect_ratio: p.aspect_ratio(),
+            znear: p.znear(),
+            zfar: p.zfar(),
+        },
+        ::gltf::camera::Projection::Orthographic(o) => GltfProjection::Orthographic {
+            xmag: o.xmag(),
+            ymag: o.ymag(),
+            znear: o.znear(),
+            zfar: o.zfar(),
+        },
+    }
+}
+
+fn visit_node(
+    node: &::gltf::Node,
+    parent: Mat4<WorldSpace>,
+    instances: &mut Vec<GltfInstance>,
+    cameras: &mut Vec<GltfCamera>,
+) -> Result<(), GltfError> {
+    let world = parent * node_matrix(node);
+
+    if let Some(mesh) = node.mesh() {
+        instances.push(GltfInstance {
+            mesh: mesh.index(),
+            world,
+        });
+    }
+
+    if let Some(camera) = node.camera() {
+        let Some(world_inv) = world.inverse() else {
+            return Err(GltfError::Invalid(format!(
+                "Camera node {} has a singular transform",
+                node.index()
+            )));
+        };
+        let flip_z = math::mat4::<WorldSpace, CameraSpace>(
+            1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+        );
+        cameras.push(GltfCamera {
+            name: camera.name().or(node.name()).unwrap_or("").to_string(),
+            view: flip_z * world_inv,
+            projection: camera_projection(&camera),
+        });
+    }
+
+    for child in node.children() {
+        visit_node(&child, world, instances, cameras)?;
+    }
+    Ok(())
+}
+
+/// Parse a .gltf or .glb file from memory. External buffers and images are loaded relative to
+/// `base_dir`, the mip chains of the textures are built with `mip_filter`.
+pub fn parse_gltf(
+    data: &[u8],
+    base_dir: impl AsRef<Path>,
+    mip_filter: MipFilter,
+) -> Result<GltfScene, GltfError> {
+    let base_dir = base_dir.as_ref();
+    let gltf = ::gltf::Gltf::from_slice(data)?;
+    let buffers = load_buffers(&gltf, base_dir)?;
+    let textures = load_textures(&gltf, &buffers, base_dir, mip_filter)?;
+
+    let mut meshes = Vec::new();
+    for mesh in gltf.document.meshes() {
+        let mut primitives = Vec::new();
+        for primitive in mesh.primitives() {
+            primitives.extend(load_primitive(&primitive, &buffers)?);
+        }
+        meshes.push(GltfMesh {
+            name: mesh.name().unwrap_or("").to_string(),
+            primitives,
+        });
+    }
+
+    let mut instances = Vec::new();
+    let mut cameras = Vec::new();
+    let scene = gltf
+        .document
+        .default_scene()
+        .or_else(|| gltf.document.scenes().next());
+    if let Some(scene) = scene {
+        for node in scene.nodes() {
+            visit_node(&node, Mat4::identity(), &mut instances, &mut cameras)?;
+        }
+    }
+
+    Ok(GltfScene {
+        meshes,
+        instances,
+        textures,
+        cameras,
+    })
+}
+
+/// Load a .gltf or .glb file and the buffers and images it references.
+pub fn load_gltf(path: impl AsRef<Path>, mip_filter: MipFilter) -> Result<GltfScene, GltfError> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    parse_gltf(&read_file(path)?, dir, mip_filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::rasterizer::PipelineState;
+    use crate::render::Renderer;
+    use crate::shaders;
+
+    // A counter-clockwise triangle (seen from +z) with u16 indices
+    fn triangle_buffer() -> Vec<u8> {
+        let positions: [f32; 9] = [-1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 0.0, 1.0, 0.0];
+        let uvs: [f32; 6] = [0.0, 1.0, 1.0, 1.0, 0.5, 0.0];
+        let indices: [u16; 4] = [0, 1, 2, 0]; // Last one is padding
+        let mut buf = Vec::new();
+        buf.extend(positions.iter().flat_map(|f| f.to_le_bytes()));
+        buf.extend(uvs.iter().flat_map(|f| f.to_le_bytes()));
+        buf.extend(indices.iter().flat_map(|i| i.to_le_bytes()));
+        buf
+    }
+
+    // `buffer` is the json of the single buffer, `extra` is added at the top level. The camera
+    // is at z = 5 looking at the triangle at z = -2.
+    fn triangle_gltf(buffer: &str, extra: &str) -> String {
+        format!(
+            r#"{{
+    "asset": {{ "version": "2.0" }},
+    "scene": 0,
+    "scenes": [{{ "nodes": [0, 2] }}],
+    "nodes": [
+        {{ "name": "root", "translation": [0.0, 0.0, -1.0], "children": [1] }},
+        {{ "mesh": 0, "translation": [0.0, 0.0, -1.0] }},
+        {{ "name": "cam_node", "camera": 0, "translation": [0.0, 0.0, 5.0] }}
+    ],
+    "cameras": [{{
+        "type": "perspective",
+        "perspective": {{ "yfov": 0.5, "znear": 0.1, "zfar": 100.0, "aspectRatio": 1.5 }}
+    }}],
+    "meshes": [{{
+        "name": "triangle",
+        "primitives": [{{
+            "attributes": {{ "POSITION": 0, "TEXCOORD_0": 1 }},
+            "indices": 2,
+            "material": 0
+        }}]
+    }}],
+    "buffers": [{buffer}],
+    "bufferViews": [
+        {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+        {{ "buffer": 0, "byteOffset": 36, "byteLength": 24 }},
+        {{ "buffer": 0, "byteOffset": 60, "byteLength": 6 }}
+    ],
+    "accessors": [
+        {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+           "min": [-1.0, -1.0, 0.0], "max": [1.0, 1.0, 0.0] }},
+        {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }},
+        {{ "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+    ]{extra}
+}}"#
+        )
+    }
+
+    const RED_MATERIAL: &str = r#",
+    "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.0, 0.0, 1.0] } }]"#;
+
+    fn data_uri_gltf() -> String {
+        let uri = base64::engine::general_purpose::STANDARD.encode(triangle_buffer());
+        let buffer = format!(
+            r#"{{ "byteLength": 66, "uri": "data:application/octet-stream;base64,{uri}" }}"#
+        );
+        triangle_gltf(&buffer, RED_MATERIAL)
+    }
+
+    #[test]
+    fn scene() {
+        let scene = parse_gltf(data_uri_gltf().as_bytes(), "", MipFilter::Box).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].name, "triangle");
+        assert!(scene.textures.is_empty());
+
+        let primitive = &scene.meshes[0].primitives[0];
+        // z is flipped
+        assert_eq!(
+            primitive.mesh.vertices,
+            vec![
+                Point3D::new(-1.0, -1.0, 0.0),
+                Point3D::new(1.0, -1.0, 0.0),
+                Point3D::new(0.0, 1.0, 0.0)
+            ]
+        );
+        // Reversed winding order
+        assert_eq!(primitive.mesh.indices, vec![0, 2, 1]);
+        // uvs are not
+        assert_eq!(primitive.mesh.attributes[2].uvs, [0.5, 0.0]);
+        // base color factor
+        assert_eq!(primitive.mesh.attributes[0].color.to_argb(), 0xFFFF0000);
+        assert!(primitive.normals.is_empty());
+        assert_eq!(primitive.base_color_texture, None);
+
+        // The parent transform is applied
+        assert_eq!(scene.instances.len(), 1);
+        assert_eq!(scene.instances[0].mesh, 0);
+        assert_eq!(scene.instances[0].world, math::translate(0.0, 0.0, 2.0));
+
+        assert_eq!(scene.cameras.len(), 1);
+        let camera = &scene.cameras[0];
+        assert_eq!(camera.name, "cam_node");
+        // At z = -5 looking down positive z in WorldSpace, same as the default camera
+        assert_eq!(camera.view, Camera::default().get_view_matrix());
+        assert_eq!(
+            camera.projection,
+            GltfProjection::Perspective {
+                yfov: 0.5,
+                aspect_ratio: Some(1.5),
+                znear: 0.1,
+                zfar: Some(100.0),
+            }
+        );
+    }
+
+    #[test]
+    fn render() {
+        let scene = parse_gltf(data_uri_gltf().as_bytes(), "", MipFilter::Box).unwrap();
+        let (width, height) = (60, 40);
+        let mut renderer = Renderer::headless(width, height);
+        let block = renderer.uniforms().write_block();
+        block.world = scene.instances[0].world;
+        block.view = scene.cameras[0].view;
+        block.projection = scene.cameras[0].projection_matrix(width, height);
+
+        // Front facing after the conversion, otherwise it would be culled
+        let mesh = &scene.meshes[0].primitives[0].mesh;
+        renderer.render(
+            mesh,
+            &PipelineState::default(),
+            shaders::mvp_vs,
+            shaders::color_fs,
+        );
+        renderer.display().unwrap();
+
+        let target = renderer.target();
+        assert_eq!(target.pixel(width / 2, height / 2), 0xFFFF0000);
+        let clear_color = target.pixel(0, 0);
+        assert_ne!(clear_color, 0xFFFF0000);
+        // The triangle is ~22 pixels high, centered
+        assert_eq!(target.pixel(width / 2, height / 2 - 13), clear_color);
+        assert_eq!(target.pixel(width / 2, height / 2 + 13), clear_color);
+        assert_eq!(target.pixel(width / 2, height / 2 + 10), 0xFFFF0000);
+    }
+
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = bin.to_vec();
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let mut out = Vec::new();
+        let total_length = 12 + 8 + json.len() + 8 + bin.len();
+        out.extend(b"glTF");
+        out.extend(2u32.to_le_bytes());
+        out.extend((total_length as u32).to_le_bytes());
+        out.extend((json.len() as u32).to_le_bytes());
+        out.extend(b"JSON");
+        out.extend(json);
+        out.extend((bin.len() as u32).to_le_bytes());
+        out.extend(b"BIN\0");
+        out.extend(bin);
+        out
+    }
+
+    #[test]
+    fn glb_with_texture() {
+        let mut bin = triangle_buffer();
+        let mut png = Vec::new();
+        crate::screenshot::write_png(&mut png, &[0xFF00FF00, 0xFF0000FF], 2, 1).unwrap();
+        let png_offset = bin.len();
+        bin.extend(&png);
+
+        let buffer = format!(r#"{{ "byteLength": {} }}"#, bin.len());
+        let extra = r#",
+    "materials": [{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } } }],
+    "textures": [{ "source": 0 }],
+    "images": [{ "bufferView": 3, "mimeType": "image/png" }]"#;
+        let json = triangle_gltf(&buffer, extra).replace(
+            r#"{ "buffer": 0, "byteOffset": 60, "byteLength": 6 }"#,
+            &format!(
+                r#"{{ "buffer": 0, "byteOffset": 60, "byteLength": 6 }},
+        {{ "buffer": 0, "byteOffset": {}, "byteLength": {} }}"#,
+                png_offset,
+                png.len()
+            ),
+        );
+
+        let scene = parse_gltf(&glb(&json, &bin), "", MipFilter::Box).unwrap();
+        assert_eq!(scene.textures.len(), 1);
+        let primitive = &scene.meshes[0].primitives[0];
+        assert_eq!(primitive.base_color_texture, Some(0));
+        // No base color factor, defaults to white
+        assert_eq!(primitive.mesh.attributes[0].color.to_argb(), 0xFFFFFFFF);
+
+        let tex = scene.base_color_texture(primitive).unwrap();
+        assert_eq!(tex.read_texel(0, 0).to_argb(), 0xFF00FF00);
+        assert_eq!(tex.read_texel(1, 0).to_argb(), 0xFF0000FF);
+    }
+
+    #[test]
+    fn errors() {
+        let missing = triangle_gltf(
+            r#"{ "byteLength": 66, "uri": "missing.bin" }"#,
+            RED_MATERIAL,
+        );
+        let dir = std::env::temp_dir();
+        assert!(matches!(
+            parse_gltf(missing.as_bytes(), &dir, MipFilter::Box),
+            Err(GltfError::Io(path, _)) if path == dir.join("missing.bin")
+        ));
+
+        let short = triangle_gltf(
+            r#"{ "byteLength": 66, "uri": "data:application/octet-stream;base64,AAAA" }"#,
+            RED_MATERIAL,
+        );
+        assert!(matches!(
+            parse_gltf(short.as_bytes(), "", MipFilter::Box),
+            Err(GltfError::Invalid(_))
+        ));
+
+        assert!(matches!(
+            parse_gltf(b"{ not json", "", MipFilter::Box),
+            Err(GltfError::Gltf(_))
+        ));
+
+        // An image in a buffer view that is larger than the buffer
+        let mut bin = triangle_buffer();
+        bin.extend(PNG_SIGNATURE);
+        let buffer = format!(r#"{{ "byteLength": {} }}"#, bin.len());
+        let extra = format!(
+            r#"{},
+    "images": [{{ "bufferView": 3, "mimeType": "image/png" }}]"#,
+            RED_MATERIAL
+        );
+        let json = triangle_gltf(&buffer, &extra).replace(
+            r#"{ "buffer": 0, "byteOffset": 60, "byteLength": 6 }"#,
+            r#"{ "buffer": 0, "byteOffset": 60, "byteLength": 6 },
+        { "buffer": 0, "byteOffset": 66, "byteLength": 1000 }"#,
+        );
+        let result = parse_gltf(&glb(&json, &bin), "", MipFilter::Box);
+        assert!(
+            matches!(result, Err(GltfError::Invalid(_))),
+            "{:?}",
+            result.err()
+        );
+
+        // Fewer uvs than vertices
+        let json = data_uri_gltf().replace(
+            r#""count": 3, "type": "VEC2""#,
+            r#""count": 2, "type": "VEC2""#,
+        );
+        let result = parse_gltf(json.as_bytes(), "", MipFilter::Box);
+        assert!(
+            matches!(&result, Err(GltfError::Invalid(msg)) if msg.contains("2 uvs")),
+            "{:?}",
+            result.err()
+        );
+
+        // Positions past the end of their buffer view
+        let json = data_uri_gltf().replace(
+            r#""bufferView": 0, "componentType""#,
+            r#""bufferView": 0, "byteOffset": 12, "componentType""#,
+        );
+        let result = parse_gltf(json.as_bytes(), "", MipFilter::Box);
+        assert!(
+            matches!(&result, Err(GltfError::Invalid(msg)) if msg.contains("positions")),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%20b%2Fc.bin").unwrap(), "a b/c.bin");
+        assert_eq!(percent_decode("%C3%A9.png").unwrap(), "\u{e9}.png");
+        for malformed in ["a%2", "a%", "%zz", "%+1", "%FF"] {
+            assert!(matches!(
+                percent_decode(malformed),
+                Err(GltfError::Invalid(_))
+            ));
+        }
+
+        let missing = triangle_gltf(
+            r#"{ "byteLength": 66, "uri": "missing%20file.bin" }"#,
+            RED_MATERIAL,
+        );
+        let dir = std::env::temp_dir();
+        assert!(matches!(
+            parse_gltf(missing.as_bytes(), &dir, MipFilter::Box),
+            Err(GltfError::Io(path, _)) if path == dir.join("missing file.bin")
+        ));
+    }
+
+    #[test]
+    fn strips_and_fans() {
+        use ::gltf::mesh::Mode;
+        let indices = vec![0, 1, 2, 3, 4];
+        assert_eq!(
+            triangle_list(Mode::TriangleStrip, indices.clone()),
+            Some(vec![0, 1, 2, 2, 1, 3, 2, 3, 4])
+        );
+        assert_eq!(
+            triangle_list(Mode::TriangleFan, indices.clone()),
+            Some(vec![0, 1, 2, 0, 2, 3, 0, 3, 4])
+        );
+        assert_eq!(triangle_list(Mode::Lines, indices), None);
+    }
+}
@@ -6,6 +6,24 @@ use core::ops::Sub;
 use crate::color::Color;
 use crate::math::*;
 
+/// Per-vertex shader outputs that are interpolated over the triangle, both when clipping and
+/// perspective correct for each fragment. Implemented for all types that can be linearly
+/// combined, e.g. `f32`, `Color`, vectors or a struct of those. `Send` and `Sync` as triangles
+/// are rasterized in parallel.
+pub trait Varying:
+    Copy + Send + Sync + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self>
+{
+    /// `self` at `t == 0` and `other` at `t == 1`.
+    fn lerp(self, other: Self, t: f32) -> Self {
+        (other - self) * t + self
+    }
+}
+
+impl<T> Varying for T where
+    T: Copy + Send + Sync + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>
+{
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct VertexAttribute {
     pub color: Color,
@@ -62,37 +80,40 @@ impl Sub for VertexAttribute {
 
 const N_VERTICES: usize = 3;
 #[derive(Clone)]
-pub struct Triangle<CS>
+pub struct Triangle<CS, V = VertexAttribute>
 where
     CS: CoordinateSystem,
+    V: Varying,
 {
     pub vertices: [Point4D<CS>; N_VERTICES],
-    pub vertex_attributes: [VertexAttribute; N_VERTICES],
+    pub vertex_attributes: [V; N_VERTICES],
 }
 
-impl<CSF, CST> Mul<Triangle<CSF>> for Mat4<CSF, CST>
+impl<CSF, CST, V> Mul<Triangle<CSF, V>> for Mat4<CSF, CST>
 where
     CSF: CoordinateSystem,
     CST: CoordinateSystem,
+    V: Varying,
 {
-    type Output = Triangle<CST>;
-    fn mul(self, other: Triangle<CSF>) -> Triangle<CST> {
+    type Output = Triangle<CST, V>;
+    fn mul(self, other: Triangle<CSF, V>) -> Triangle<CST, V> {
         let Triangle {
             vertices: verts,
             vertex_attributes: attrs,
         } = other;
         let vertices = [self * verts[0], self * verts[1], self * verts[2]];
 
-        Triangle::<CST> {
+        Triangle {
             vertices,
             vertex_attributes: attrs,
         }
     }
 }
 
-impl<CS> std::fmt::Debug for Triangle<CS>
+impl<CS, V> std::fmt::Debug for Triangle<CS, V>
 where
     CS: PrintableType + CoordinateSystem,
+    V: Varying + std::fmt::Debug,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Triangle:")?;
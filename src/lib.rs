@@ -0,0 +1,36 @@
+//! A software rasterizer, written as a learning project for understanding the GPU pipeline better.
+//!
+//! The pipeline is driven through a [`Renderer`], which runs the vertex shader over a [`Mesh`],
+//! assembles triangles and hands them to the [`Rasterizer`] which clips, rasterizes and shades
+//! them before the resolved frame is presented to a [`RenderTarget`](render_target::RenderTarget).
+
+pub mod camera;
+pub mod color;
+pub mod gltf;
+pub mod graphics_primitives;
+pub mod math;
+pub mod mesh;
+pub mod obj;
+pub mod rasterizer;
+pub mod render;
+pub mod render_target;
+pub mod sampler;
+pub mod screenshot;
+pub mod shaders;
+pub mod texture;
+pub mod uniform;
+
+pub use crate::camera::Camera;
+pub use crate::color::Color;
+pub use crate::graphics_primitives::{Triangle, Varying, VertexAttribute};
+pub use crate::mesh::Mesh;
+pub use crate::rasterizer::{
+    BlendFactor, BlendOp, BlendState, ColorFormat, CompareFunc, CoverageMask, CullMode, DepthState,
+    FragCoords, FragmentOutput, Framebuffer, OutputTransform, PipelineState, Rasterizer,
+    SampleCount, StencilFaceState, StencilOp, StencilState, ToneMap, Winding,
+};
+pub use crate::render::{FragmentShader, Renderer, ShaderProgram, VertexShader};
+pub use crate::render_target::{OffscreenTarget, RenderTarget, WindowTarget};
+pub use crate::sampler::{FilterMode, Sampler, WrapMode};
+pub use crate::texture::{MipFilter, Texture};
+pub use crate::uniform::{UniformBlock, Uniforms};
@@ -17,6 +17,7 @@ where
 
 pub type Mat4<CSF, CST = CSF> = Matrix<CSF, CST, 4>;
 
+#[allow(clippy::too_many_arguments)]
 pub fn mat4<CSF, CST>(
     x00: f32,
     x01: f32,
@@ -162,6 +163,68 @@ where
     }
 }
 
+impl<CSF, CST> Mat4<CSF, CST>
+where
+    CSF: CoordinateSystem,
+    CST: CoordinateSystem,
+{
+    /// Inverse through the adjugate (transpose of the cofactor matrix), None if the matrix is
+    /// singular.
+    pub fn inverse(&self) -> Option<Mat4<CST, CSF>> {
+        let m = &self.array;
+
+        // 2x2 determinants of the upper two and lower two rows, indexed by their column pairs
+        let s0 = m[0][0] * m[1][1] - m[1][0] * m[0][1];
+        let s1 = m[0][0] * m[1][2] - m[1][0] * m[0][2];
+        let s2 = m[0][0] * m[1][3] - m[1][0] * m[0][3];
+        let s3 = m[0][1] * m[1][2] - m[1][1] * m[0][2];
+        let s4 = m[0][1] * m[1][3] - m[1][1] * m[0][3];
+        let s5 = m[0][2] * m[1][3] - m[1][2] * m[0][3];
+
+        let c5 = m[2][2] * m[3][3] - m[3][2] * m[2][3];
+        let c4 = m[2][1] * m[3][3] - m[3][1] * m[2][3];
+        let c3 = m[2][1] * m[3][2] - m[3][1] * m[2][2];
+        let c2 = m[2][0] * m[3][3] - m[3][0] * m[2][3];
+        let c1 = m[2][0] * m[3][2] - m[3][0] * m[2][2];
+        let c0 = m[2][0] * m[3][1] - m[3][0] * m[2][1];
+
+        let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+        if det == 0.0 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let array = [
+            [
+                (m[1][1] * c5 - m[1][2] * c4 + m[1][3] * c3) * inv_det,
+                (-m[0][1] * c5 + m[0][2] * c4 - m[0][3] * c3) * inv_det,
+                (m[3][1] * s5 - m[3][2] * s4 + m[3][3] * s3) * inv_det,
+                (-m[2][1] * s5 + m[2][2] * s4 - m[2][3] * s3) * inv_det,
+            ],
+            [
+                (-m[1][0] * c5 + m[1][2] * c2 - m[1][3] * c1) * inv_det,
+                (m[0][0] * c5 - m[0][2] * c2 + m[0][3] * c1) * inv_det,
+                (-m[3][0] * s5 + m[3][2] * s2 - m[3][3] * s1) * inv_det,
+                (m[2][0] * s5 - m[2][2] * s2 + m[2][3] * s1) * inv_det,
+            ],
+            [
+                (m[1][0] * c4 - m[1][1] * c2 + m[1][3] * c0) * inv_det,
+                (-m[0][0] * c4 + m[0][1] * c2 - m[0][3] * c0) * inv_det,
+                (m[3][0] * s4 - m[3][1] * s2 + m[3][3] * s0) * inv_det,
+                (-m[2][0] * s4 + m[2][1] * s2 - m[2][3] * s0) * inv_det,
+            ],
+            [
+                (-m[1][0] * c3 + m[1][1] * c1 - m[1][2] * c0) * inv_det,
+                (m[0][0] * c3 - m[0][1] * c1 + m[0][2] * c0) * inv_det,
+                (-m[3][0] * s3 + m[3][1] * s1 - m[3][2] * s0) * inv_det,
+                (m[2][0] * s3 - m[2][1] * s1 + m[2][2] * s0) * inv_det,
+            ],
+        ];
+
+        Some(Mat4::from_raw(&array))
+    }
+}
+
 impl<CSF, CST, const N: usize> std::fmt::Debug for Matrix<CSF, CST, { N }>
 where
     CSF: PrintableType + CoordinateSystem,
@@ -284,4 +347,23 @@ mod test {
         let result = Mat4::<WorldSpace>::from_raw(&a);
         assert_eq!(mat * mat.transpose(), result);
     }
+
+    #[test]
+    fn inverse() {
+        let mat = translate::<WorldSpace>(1.0, -2.0, 3.0) * rotate(0.3, 0.5, 0.7);
+        let inv = mat.inverse().unwrap();
+
+        let identity = Mat4::<WorldSpace>::identity();
+        for (a, b) in (mat * inv).array.iter().zip(identity.array.iter()) {
+            for (x, y) in a.iter().zip(b.iter()) {
+                assert!((x - y).abs() < 1e-5, "{:?}", mat * inv);
+            }
+        }
+        assert_eq!(Mat4::<WorldSpace>::identity().inverse(), Some(identity));
+
+        let singular = mat4::<WorldSpace, WorldSpace>(
+            1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0, 13.0, 14.0, 15.0, 16.0,
+        );
+        assert_eq!(singular.inverse(), None);
+    }
 }
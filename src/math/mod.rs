@@ -1,3 +1,14 @@
+//! This module contains a very basic implementation of math library mainly for learning purposes.
+//! A lot of the types are generic over the coordinate space they are defined in, e.g. a point in
+//! WorldSpace. This is mostly an experiment to learn about coordinate systems and rust generics,
+//! and I can't say that it has prevented a lot of bugs but there have been a few occasions when
+//! it has made the progress through the graphics pipeline more clear to me at least.
+//!
+//! The general idea is that primitives such as points, vectors and triangles (defined in graphics_primitives::) are defined
+//! in a coordinate space and to transform them to another, you need to transform them with a matrix that defines a transformation
+//! between coordinate systems as part of its type signature, e.g. Mat4<WorldSpace, CameraSpace> would transform a Vec4<WorldSpace>
+//! to Vec4<CameraSpace>.
+
 pub mod matrix;
 pub mod point;
 pub mod transform;
@@ -7,17 +18,6 @@ pub use crate::math::point::*;
 pub use crate::math::transform::*;
 pub use crate::math::vector::*;
 
-/// This module contains a very basic implementation of math library mainly for learning purposes.
-/// A lot of the types are generic over the coordinate space they are defined in, e.g. a point in
-/// WorldSpace. This is mostly an experiment to learn about coordinate systems and rust generics,
-/// and I can't say that it has prevented a lot of bugs but there have been a few occasions when
-/// it has made the progress through the graphics pipeline more clear to me at least.
-///
-/// The general idea is that primitives such as points, vectors and triangles (defined in graphics_primitives::) are defined
-/// in a coordinate space and to transform them to another, you need to transform them with a matrix that defines a transformation
-/// between coordinate systems as part of its type signature, e.g. Mat4<WorldSpace, CameraSpace> would transform a Vec4<WorldSpace>
-/// to Vec4<CameraSpace>.
-
 pub trait PrintableType {
     const NAME: &'static str;
 }
@@ -25,9 +25,9 @@ pub trait PrintableType {
 #[derive(Copy, Clone)]
 pub struct Any2D;
 
-/// The transformations below oncur in the following order (with transform):
-/// World  ->  Camera   ->   Clip        ->         NDC    ->    Screen
-///       view      projection    perspective_divide  viewport_transform
+// The transformations below oncur in the following order (with transform):
+// World  ->  Camera   ->   Clip        ->         NDC    ->    Screen
+//       view      projection    perspective_divide  viewport_transform
 
 /// The coordinate system in which the models/triangle are position relative towards
 /// eachother and the camera. X right, Y up, Z towards screen (left-handed)
@@ -47,6 +47,7 @@ pub struct ClipSpace;
 
 /// Normalized Device Coordinates, x and y have been divided by the the clip space w coordinate.
 /// All axes range from -1, 1.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
 pub struct NDC;
 
@@ -120,3 +121,62 @@ pub fn project(
         0.0,
     )
 }
+
+// Same as `project` but with the far plane at infinity, i.e. the limit of `project` as far -> inf.
+pub fn project_infinite(
+    near: f32,
+    aspect_ratio: f32,
+    vert_fov: f32,
+) -> Mat4<CameraSpace, ClipSpace> {
+    assert!(near > 0.0);
+    let half_width = (vert_fov / 2.0).tan() * near;
+    let half_height = aspect_ratio * half_width;
+
+    mat4(
+        near / half_width,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        near / half_height,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        -1.0,
+        -2.0 * near,
+        0.0,
+        0.0,
+        -1.0,
+        0.0,
+    )
+}
+
+// Maps the box [-half_width, half_width] x [-half_height, half_height] x [-near, -far] (the camera
+// looks down negative z) to the clip space cube.
+pub fn orthographic(
+    half_width: f32,
+    half_height: f32,
+    near: f32,
+    far: f32,
+) -> Mat4<CameraSpace, ClipSpace> {
+    assert!(far > near);
+    mat4(
+        1.0 / half_width,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        1.0 / half_height,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        -2.0 / (far - near),
+        -(far + near) / (far - near),
+        0.0,
+        0.0,
+        0.0,
+        1.0,
+    )
+}
@@ -2,16 +2,16 @@ use crate::color::Color;
 use crate::graphics_primitives::*;
 use crate::math::*;
 
-pub struct Mesh<CS>
+/// `A` is the per-vertex input besides the position, see `ShaderProgram::VertexInput`.
+pub struct Mesh<CS, A = VertexAttribute>
 where
     CS: CoordinateSystem,
 {
     pub vertices: Vec<Point3D<CS>>,
     pub indices: Vec<usize>,
-    pub attributes: Vec<VertexAttribute>,
+    pub attributes: Vec<A>,
 }
 
-#[allow(unused)]
 pub fn centered_quad<CS>(width: f32) -> Mesh<CS>
 where
     CS: CoordinateSystem,
@@ -39,7 +39,6 @@ where
     }
 }
 
-#[allow(unused)]
 pub fn triangle<CS>() -> Mesh<CS>
 where
     CS: CoordinateSystem,
@@ -64,7 +63,6 @@ where
     }
 }
 
-#[allow(unused)]
 pub fn cube<CS>(width: f32) -> Mesh<CS>
 where
     CS: CoordinateSystem,
@@ -148,7 +146,6 @@ where
     }
 }
 
-#[allow(unused)]
 pub fn sphere<CS>(radius: f32) -> Mesh<CS>
 where
     CS: CoordinateSystem,
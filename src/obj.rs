@@ -0,0 +1,620 @@
+// Loader for Wavefront OBJ files (and the MTL material files they reference).
+// See http://paulbourke.net/dataformats/obj/ and http://paulbourke.net/dataformats/mtl/
+//
+// Only the polygonal parts of the format are supported (v, vt, vn, f, o, g, usemtl, mtllib),
+// everything else (e.g. lines, smoothing groups, free-form geometry) is ignored.
+//
+// OBJ files use a right-handed coordinate system with counter-clockwise front faces while
+// WorldSpace is left-handed with clockwise front faces. z is negated to go from right- to
+// left-handed and the winding order of each face is reversed. Note that the z flip alone does not
+// change the winding as seen from the camera, the model and the viewer are mirrored together.
+// Texture coordinates have their origin in the lower left corner in OBJ but textures here start in
+// the upper left corner, so v is flipped as well.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::color::Color;
+use crate::graphics_primitives::VertexAttribute;
+use crate::math::{vec3, Point3D, Vec3, WorldSpace};
+use crate::mesh::Mesh;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// usemtl referenced a material that is not in any of the mtllibs
+    MissingMaterial(String),
+}
+
+impl std::fmt::Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ObjError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            ObjError::MissingMaterial(name) => write!(f, "Material {} not found", name),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io(_, e) => Some(e),
+            ObjError::Parse { .. } | ObjError::MissingMaterial(_) => None,
+        }
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> ObjError {
+    ObjError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    /// Kd, with the alpha from d (or Tr)
+    pub diffuse: Color,
+    /// map_Kd, relative to the working directory if the material was loaded with `load_obj`
+    pub diffuse_texture: Option<PathBuf>,
+}
+
+impl Material {
+    fn new(name: &str) -> Self {
+        Material {
+            name: name.to_string(),
+            diffuse: Color::white(),
+            diffuse_texture: None,
+        }
+    }
+}
+
+pub struct ObjMesh {
+    /// From the last o or g statement before the faces
+    pub name: String,
+    /// From usemtl, look it up with `ObjModel::material`
+    pub material: Option<String>,
+    pub mesh: Mesh<WorldSpace>,
+    /// Per vertex normals, empty if the faces did not reference any normals.
+    pub normals: Vec<Vec3<WorldSpace>>,
+}
+
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    /// The mtllib statements of the file
+    pub material_libs: Vec<String>,
+    pub materials: Vec<Material>,
+}
+
+impl ObjModel {
+    pub fn material(&self, mesh: &ObjMesh) -> Option<&Material> {
+        let name = mesh.material.as_ref()?;
+        self.materials.iter().find(|m| &m.name == name)
+    }
+}
+
+fn parse_floats<const N: usize>(
+    line: usize,
+    args: &[&str],
+    n_required: usize,
+) -> Result<[f32; N], ObjError> {
+    if args.len() < n_required || args.len() > N {
+        return Err(parse_error(
+            line,
+            format!(
+                "Expected {} to {} numbers, got {}",
+                n_required,
+                N,
+                args.len()
+            ),
+        ));
+    }
+    let mut out = [0.0; N];
+    for (o, arg) in out.iter_mut().zip(args.iter()) {
+        *o = arg
+            .parse()
+            .map_err(|_| parse_error(line, format!("Invalid number: {}", arg)))?;
+    }
+    Ok(out)
+}
+
+// Index into the position, uv and normal arrays, in that order. Zero-based.
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+// OBJ indices are one-based and negative values are relative to the end of the list
+fn resolve_index(line: usize, s: &str, len: usize) -> Result<usize, ObjError> {
+    let idx: i64 = s
+        .parse()
+        .map_err(|_| parse_error(line, format!("Invalid index: {}", s)))?;
+    let resolved = if idx < 0 { len as i64 + idx } else { idx - 1 };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(parse_error(
+            line,
+            format!("Index {} out of bounds (have {})", idx, len),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+struct MeshBuilder {
+    name: String,
+    material: Option<String>,
+    mesh: Mesh<WorldSpace>,
+    normals: Vec<Vec3<WorldSpace>>,
+    // De-indexing, OBJ has separate indices for position, uv and normal but the rasterizer only
+    // supports a single index buffer, so each unique combination becomes a vertex.
+    vertex_map: HashMap<FaceVertex, usize>,
+}
+
+impl MeshBuilder {
+    fn new(name: &str, material: Option<String>) -> Self {
+        MeshBuilder {
+            name: name.to_string(),
+            material,
+            mesh: Mesh {
+                vertices: Vec::new(),
+                indices: Vec::new(),
+                attributes: Vec::new(),
+            },
+            normals: Vec::new(),
+            vertex_map: HashMap::new(),
+        }
+    }
+
+    fn vertex(&mut self, fv: FaceVertex, data: &ObjData) -> usize {
+        if let Some(&idx) = self.vertex_map.get(&fv) {
+            return idx;
+        }
+
+        let (pos_idx, uv_idx, normal_idx) = fv;
+        let idx = self.mesh.vertices.len();
+        self.mesh.vertices.push(data.positions[pos_idx]);
+        let uvs = uv_idx.map(|i| data.uvs[i]).unwrap_or([0.0, 0.0]);
+        self.mesh
+            .attributes
+            .push((data.colors[pos_idx], uvs).into());
+        if let Some(i) = normal_idx {
+            // Vertices before this one did not have normals, pad with zero-vectors
+            self.normals
+                .resize(self.mesh.vertices.len() - 1, vec3(0.0, 0.0, 0.0));
+            self.normals.push(data.normals[i]);
+        }
+        self.vertex_map.insert(fv, idx);
+        idx
+    }
+
+    fn build(mut self) -> ObjMesh {
+        if !self.normals.is_empty() {
+            self.normals
+                .resize(self.mesh.vertices.len(), vec3(0.0, 0.0, 0.0));
+        }
+        ObjMesh {
+            name: self.name,
+            material: self.material,
+            mesh: self.mesh,
+            normals: self.normals,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Point3D<WorldSpace>>,
+    colors: Vec<Color>,
+    uvs: Vec<[f32; 2]>,
+    normals: Vec<Vec3<WorldSpace>>,
+}
+
+/// Parse an OBJ file. Materials are not loaded, only referenced by name, see `load_obj`.
+pub fn parse_obj(reader: impl BufRead) -> Result<ObjModel, ObjError> {
+    let mut data = ObjData::default();
+    let mut material_libs = Vec::new();
+    let mut meshes = Vec::new();
+    let mut cur = MeshBuilder::new("", None);
+
+    for (i, line) in reader.lines().enumerate() {
+        let line_nr = i + 1;
+        let line = line.map_err(|e| parse_error(line_nr, e.to_string()))?;
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                // Optional vertex colors are a common extension: v x y z r g b
+                let [x, y, z, r, g, b] = if args.len() == 6 {
+                    parse_floats::<6>(line_nr, &args, 6)?
+                } else {
+                    let [x, y, z, _w] = parse_floats::<4>(line_nr, &args, 3)?;
+                    [x, y, z, 1.0, 1.0, 1.0]
+                };
+                data.positions.push(Point3D::new(x, y, -z));
+                data.colors.push(Color { r, g, b, a: 1.0 });
+            }
+            "vt" => {
+                let [u, v, _w] = parse_floats::<3>(line_nr, &args, 1)?;
+                data.uvs.push([u, 1.0 - v]);
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats::<3>(line_nr, &args, 3)?;
+                data.normals.push(vec3(x, y, -z));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(parse_error(
+                        line_nr,
+                        format!("A face needs at least 3 vertices, got {}", args.len()),
+                    ));
+                }
+                let mut face = Vec::with_capacity(args.len());
+                for arg in args {
+                    let mut parts = arg.split('/');
+                    let pos = resolve_index(line_nr, parts.next().unwrap(), data.positions.len())?;
+                    let uv = match parts.next() {
+                        None | Some("") => None,
+                        Some(s) => Some(resolve_index(line_nr, s, data.uvs.len())?),
+                    };
+                    let normal = match parts.next() {
+                        None | Some("") => None,
+                        Some(s) => Some(resolve_index(line_nr, s, data.normals.len())?),
+                    };
+                    if parts.next().is_some() {
+                        return Err(parse_error(
+                            line_nr,
+                            format!("Invalid face vertex: {}", arg),
+                        ));
+                    }
+                    face.push(cur.vertex((pos, uv, normal), &data));
+                }
+                // Triangle fan, assumes convex polygons. Reversed to make it clockwise.
+                for i in 1..face.len() - 1 {
+                    cur.mesh
+                        .indices
+                        .extend_from_slice(&[face[0], face[i + 1], face[i]]);
+                }
+            }
+            "o" | "g" => {
+                let name = args.join(" ");
+                let material = cur.material.clone();
+                let prev = std::mem::replace(&mut cur, MeshBuilder::new(&name, material));
+                if !prev.mesh.indices.is_empty() {
+                    meshes.push(prev.build());
+                }
+            }
+            "usemtl" => {
+                let Some(&material) = args.first() else {
+                    return Err(parse_error(line_nr, "usemtl without a material name"));
+                };
+                let name = cur.name.clone();
+                let prev = std::mem::replace(
+                    &mut cur,
+                    MeshBuilder::new(&name, Some(material.to_string())),
+                );
+                if !prev.mesh.indices.is_empty() {
+                    meshes.push(prev.build());
+                }
+            }
+            "mtllib" => material_libs.extend(args.iter().map(|s| s.to_string())),
+            _ => (),
+        }
+    }
+
+    if !cur.mesh.indices.is_empty() {
+        meshes.push(cur.build());
+    }
+
+    Ok(ObjModel {
+        meshes,
+        material_libs,
+        materials: Vec::new(),
+    })
+}
+
+/// Parse an MTL file. Only the diffuse color, diffuse texture and transparency are used.
+pub fn parse_mtl(reader: impl BufRead) -> Result<Vec<Material>, ObjError> {
+    let mut materials: Vec<Material> = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line_nr = i + 1;
+        let line = line.map_err(|e| parse_error(line_nr, e.to_string()))?;
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            let Some(&name) = args.first() else {
+                return Err(parse_error(line_nr, "newmtl without a material name"));
+            };
+            materials.push(Material::new(name));
+            continue;
+        }
+
+        let current = match keyword {
+            "Kd" | "d" | "Tr" | "map_Kd" => materials
+                .last_mut()
+                .ok_or_else(|| parse_error(line_nr, format!("{} before newmtl", keyword)))?,
+            _ => continue,
+        };
+
+        match keyword {
+            "Kd" => {
+                let [r, g, b] = parse_floats::<3>(line_nr, &args, 3)?;
+                current.diffuse = Color {
+                    a: current.diffuse.a,
+                    r,
+                    g,
+                    b,
+                };
+            }
+            "d" => current.diffuse.a = parse_floats::<1>(line_nr, &args, 1)?[0],
+            "Tr" => current.diffuse.a = 1.0 - parse_floats::<1>(line_nr, &args, 1)?[0],
+            "map_Kd" => {
+                // Options (e.g. -s 1 1 1) come before the file name, which is last
+                let Some(&path) = args.last() else {
+                    return Err(parse_error(line_nr, "map_Kd without a file name"));
+                };
+                current.diffuse_texture = Some(PathBuf::from(path));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(materials)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, ObjError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| ObjError::Io(path.to_path_buf(), e))
+}
+
+/// Load an OBJ file and the MTL files it references. The diffuse color of each mesh's material is
+/// multiplied into the vertex colors.
+pub fn load_obj(path: impl AsRef<Path>) -> Result<ObjModel, ObjError> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut model = parse_obj(open(path)?)?;
+
+    for lib in &model.material_libs {
+        let lib_path = dir.join(lib);
+        let mut materials = parse_mtl(open(&lib_path)?)?;
+        for m in materials.iter_mut() {
+            if let Some(tex) = &m.diffuse_texture {
+                m.diffuse_texture = Some(dir.join(tex));
+            }
+        }
+        model.materials.extend(materials);
+    }
+
+    for obj_mesh in &mut model.meshes {
+        let Some(name) = &obj_mesh.material else {
+            continue;
+        };
+        let Some(material) = model.materials.iter().find(|m| &m.name == name) else {
+            return Err(ObjError::MissingMaterial(name.clone()));
+        };
+        for attr in obj_mesh.mesh.attributes.iter_mut() {
+            *attr = VertexAttribute {
+                color: Color {
+                    r: attr.color.r * material.diffuse.r,
+                    g: attr.color.g * material.diffuse.g,
+                    b: attr.color.b * material.diffuse.b,
+                    a: attr.color.a * material.diffuse.a,
+                },
+                uvs: attr.uvs,
+            };
+        }
+    }
+
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "
+# A quad split into two groups
+mtllib quad.mtl
+v -1.0 -1.0 0.0
+v 1.0 -1.0 0.0
+v 1.0 1.0 0.5
+v -1.0 1.0 0.0
+vt 0.0 0.0
+vt 1.0 0.0
+vt 1.0 1.0
+vt 0.0 1.0
+vn 0.0 0.0 1.0
+o quad
+usemtl red
+f 1/1/1 2/2/1 3/3/1 4/4/1
+g second
+f -4/-4 -2/-2 -1/-1
+";
+
+    #[test]
+    fn quad() {
+        let model = parse_obj(QUAD.as_bytes()).unwrap();
+        assert_eq!(model.material_libs, vec!["quad.mtl".to_string()]);
+        assert_eq!(model.meshes.len(), 2);
+
+        let quad = &model.meshes[0];
+        assert_eq!(quad.name, "quad");
+        assert_eq!(quad.material.as_deref(), Some("red"));
+        assert_eq!(quad.mesh.vertices.len(), 4);
+        assert_eq!(quad.mesh.indices, vec![0, 2, 1, 0, 3, 2]);
+        assert_eq!(quad.normals.len(), 4);
+        // z is flipped
+        assert_eq!(quad.mesh.vertices[2], Point3D::new(1.0, 1.0, -0.5));
+        assert_eq!(quad.normals[0], vec3(0.0, 0.0, -1.0));
+        // v is flipped
+        assert_eq!(quad.mesh.attributes[0].uvs, [0.0, 1.0]);
+        assert_eq!(quad.mesh.attributes[2].uvs, [1.0, 0.0]);
+
+        let second = &model.meshes[1];
+        assert_eq!(second.name, "second");
+        // Material is kept until the next usemtl
+        assert_eq!(second.material.as_deref(), Some("red"));
+        assert_eq!(second.mesh.vertices.len(), 3);
+        assert_eq!(second.mesh.indices, vec![0, 2, 1]);
+        assert!(second.normals.is_empty());
+        assert_eq!(second.mesh.vertices[0], Point3D::new(-1.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn deindex() {
+        // Same position but different uvs => different vertices
+        let obj = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+vt 0 0
+vt 1 1
+f 1/1 2/1 3/1
+f 1/2 2/1 3/1
+";
+        let model = parse_obj(obj.as_bytes()).unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0].mesh;
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 2, 1, 3, 2, 1]);
+    }
+
+    #[test]
+    fn front_facing() {
+        use crate::{math, shaders, Camera, PipelineState, Renderer};
+
+        // Counter-clockwise seen from +z, i.e. facing the default camera after the conversion
+        let obj = "
+v -1 -1 0
+v 1 -1 0
+v 1 1 0
+v -1 1 0
+f 1 2 3 4
+";
+        let model = parse_obj(obj.as_bytes()).unwrap();
+        let mut renderer = Renderer::headless(32, 32);
+        let block = renderer.uniforms().write_block();
+        block.view = Camera::default().get_view_matrix();
+        block.projection = math::project(1.0, 100.0, 1.0, std::f32::consts::FRAC_PI_2);
+        renderer.render(
+            &model.meshes[0].mesh,
+            &PipelineState::default(),
+            shaders::mvp_vs,
+            shaders::color_fs,
+        );
+        renderer.display().unwrap();
+
+        assert_eq!(renderer.target().pixel(16, 16), Color::white().to_argb());
+    }
+
+    #[test]
+    fn vertex_colors() {
+        let obj = "
+v 0 0 0 1 0 0
+v 1 0 0 0 1 0
+v 1 1 0 0 0 1
+f 1 2 3
+";
+        let model = parse_obj(obj.as_bytes()).unwrap();
+        let attrs = &model.meshes[0].mesh.attributes;
+        assert_eq!(attrs[0].color.to_argb(), Color::red().to_argb());
+        assert_eq!(attrs[1].color.to_argb(), Color::green().to_argb());
+        assert_eq!(attrs[2].color.to_argb(), Color::blue().to_argb());
+    }
+
+    fn expect_parse_error(obj: &str, expected_line: usize) {
+        match parse_obj(obj.as_bytes()) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, expected_line),
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Expected an error"),
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        expect_parse_error("v 0 0\n", 1);
+        expect_parse_error("v 0 0 0\nv 1 a 0\n", 2);
+        expect_parse_error("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 4\n", 4);
+        expect_parse_error("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 -4\n", 4);
+        expect_parse_error("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2\n", 4);
+        expect_parse_error("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1/1 2 3\n", 4);
+        expect_parse_error("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1//1/1 2 3\n", 4);
+        expect_parse_error("usemtl\n", 1);
+    }
+
+    #[test]
+    fn mtl() {
+        let mtl = "
+newmtl red
+Ka 1.0 1.0 1.0
+Kd 1.0 0.0 0.0
+d 0.5
+newmtl textured
+map_Kd -s 1 1 1 textures/checkerboard.png
+";
+        let materials = parse_mtl(mtl.as_bytes()).unwrap();
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].name, "red");
+        assert_eq!(materials[0].diffuse.r, 1.0);
+        assert_eq!(materials[0].diffuse.g, 0.0);
+        assert_eq!(materials[0].diffuse.a, 0.5);
+        assert!(materials[0].diffuse_texture.is_none());
+        assert_eq!(materials[1].name, "textured");
+        assert_eq!(
+            materials[1].diffuse_texture.as_deref(),
+            Some(Path::new("textures/checkerboard.png"))
+        );
+
+        assert!(parse_mtl("Kd 1 1 1\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn load_with_materials() {
+        let dir = std::env::temp_dir().join("rusterizer_obj_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("quad.obj"), QUAD).unwrap();
+        std::fs::write(
+            dir.join("quad.mtl"),
+            "newmtl red\nKd 1 0 0\nmap_Kd red.png\n",
+        )
+        .unwrap();
+
+        let model = load_obj(dir.join("quad.obj")).unwrap();
+        assert_eq!(model.materials.len(), 1);
+        let material = model.material(&model.meshes[0]).unwrap();
+        assert_eq!(
+            material.diffuse_texture.as_deref(),
+            Some(dir.join("red.png").as_path())
+        );
+        for attr in &model.meshes[0].mesh.attributes {
+            assert_eq!(attr.color.to_argb(), Color::red().to_argb());
+        }
+
+        std::fs::write(dir.join("quad.mtl"), "newmtl blue\nKd 0 0 1\n").unwrap();
+        assert!(matches!(
+            load_obj(dir.join("quad.obj")),
+            Err(ObjError::MissingMaterial(name)) if name == "red"
+        ));
+
+        std::fs::remove_file(dir.join("quad.mtl")).unwrap();
+        assert!(matches!(
+            load_obj(dir.join("quad.obj")),
+            Err(ObjError::Io(..))
+        ));
+    }
+}
@@ -1,24 +1,33 @@
 use super::bounding_box::PixelBoundingBox;
-use super::N_MSAA_SAMPLES;
+use super::framebuffer::ColorFormat;
+use super::SampleCount;
 use crate::color::Color;
 
+// sRGB-encoded, like the presented frames. The color attachments are cleared to it decoded to
+// linear, so that it presents unchanged with the default output transform.
 pub const CLEAR_COLOR: u32 = 0xFF191919;
 pub const CLEAR_DEPTH: f32 = f32::MAX;
+pub const CLEAR_STENCIL: u8 = 0;
 
 pub const TILE_SIZE: usize = 64;
 
-// Keeps two masks to allow clearing prev resolve buffer before writing
+// Keeps two masks to allow clearing prev resolve buffer before writing.
+//
+// The pixels of the attachments are stored tile by tile, in the order of the tiles and row-major
+// within a tile. That way each tile is a contiguous range of every attachment and can be written
+// by its own thread.
 pub struct BufferTiles {
     tiles: Vec<PixelBoundingBox>,
     masks: [Vec<bool>; 2],
     mask_idx: usize,
     n_horizontal: usize,
+    width: usize,
 }
 
 impl BufferTiles {
     pub fn new(width: usize, height: usize) -> Self {
-        let is_width_exact = width % TILE_SIZE == 0;
-        let is_height_exact = height % TILE_SIZE == 0;
+        let is_width_exact = width.is_multiple_of(TILE_SIZE);
+        let is_height_exact = height.is_multiple_of(TILE_SIZE);
         let n_horizontal = width / TILE_SIZE + if is_width_exact { 0 } else { 1 };
         let n_vertical = height / TILE_SIZE + if is_height_exact { 0 } else { 1 };
 
@@ -43,16 +52,32 @@ impl BufferTiles {
             masks,
             n_horizontal,
             mask_idx: 0,
+            width,
         }
     }
 
-    pub fn tile_idx(&self, row: usize, col: usize) -> usize {
-        (row / TILE_SIZE) * self.n_horizontal + (col / TILE_SIZE)
+    pub fn n_horizontal(&self) -> usize {
+        self.n_horizontal
     }
 
-    pub fn mark(&mut self, row: usize, col: usize) {
-        let idx = self.tile_idx(row, col);
-        self.masks[self.mask_idx][idx] = true;
+    /// The index of the pixel in the attachments
+    pub fn pixel_index(&self, x: usize, y: usize) -> usize {
+        let (i, j) = (x / TILE_SIZE, y / TILE_SIZE);
+        let tile = &self.tiles[j * self.n_horizontal + i];
+        // Only the last row and column of tiles can be smaller
+        let tile_start = j * TILE_SIZE * self.width + i * TILE_SIZE * (tile.max_y - tile.min_y);
+        tile_start + (y - tile.min_y) * (tile.max_x - tile.min_x) + (x - tile.min_x)
+    }
+
+    /// The range of the pixels of a tile in the attachments
+    pub fn pixels(&self, tile: &PixelBoundingBox) -> std::ops::Range<usize> {
+        let start = self.pixel_index(tile.min_x, tile.min_y);
+        start..start + (tile.max_x - tile.min_x) * (tile.max_y - tile.min_y)
+    }
+
+    /// The tiles in order, with their marks of the current frame
+    pub fn tiles_mut(&mut self) -> impl Iterator<Item = (&PixelBoundingBox, &mut bool)> {
+        self.tiles.iter().zip(self.masks[self.mask_idx].iter_mut())
     }
 
     pub fn next(&mut self) {
@@ -79,80 +104,94 @@ impl BufferTiles {
     }
 }
 
+// The samples of a color attachment, in the bytes of its format
 #[derive(Debug)]
 pub struct ColorBuffer {
-    pub clear_buffer: Vec<[u32; N_MSAA_SAMPLES as usize]>,
-    pub buffer: Vec<[u32; N_MSAA_SAMPLES as usize]>,
-    pub resolve_buffer: Vec<u32>,
+    pub format: ColorFormat,
+    pub n_samples: usize,
+    pub samples: Vec<u8>,
+    // One sample encoded with the clear color
+    clear_sample: Vec<u8>,
 }
 
 impl ColorBuffer {
-    pub fn new(width: usize, height: usize) -> Self {
-        let mut buffer = Vec::with_capacity(width * height);
-        let mut clear_buffer = Vec::with_capacity(width * height);
-        let resolve_buffer = vec![CLEAR_COLOR; width * height];
-        // Initialize to black
-        for _i in 0..width * height {
-            buffer.push([CLEAR_COLOR; N_MSAA_SAMPLES as usize]);
-            clear_buffer.push([CLEAR_COLOR; N_MSAA_SAMPLES as usize]);
-        }
+    pub fn new(width: usize, height: usize, format: ColorFormat, samples: SampleCount) -> Self {
+        let mut clear_sample = vec![0; format.bytes_per_sample()];
+        format.encode(format.clear_color(), &mut clear_sample);
+        let n_samples = samples.count();
 
         Self {
-            clear_buffer,
-            buffer,
-            resolve_buffer,
+            format,
+            n_samples,
+            samples: clear_sample.repeat(width * height * n_samples),
+            clear_sample,
         }
     }
 
-    pub fn set_pixel(&mut self, pixel_idx: usize, mask_idx: u8, color: Color) {
-        self.buffer[pixel_idx][mask_idx as usize] = color.to_argb();
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.format.bytes_per_sample() * self.n_samples
+    }
+
+    pub fn pixel(&self, idx: usize) -> &[u8] {
+        let n = self.bytes_per_pixel();
+        &self.samples[idx * n..(idx + 1) * n]
     }
 
-    pub fn box_filter_color(colors: &[u32; N_MSAA_SAMPLES as usize]) -> u32 {
-        let mut red_sum = 0;
-        let mut blue_sum = 0;
-        let mut green_sum = 0;
-        for c in colors.iter() {
-            red_sum += (c & 0x00FF0000) >> 16;
-            green_sum += (c & 0x0000FF00) >> 8;
-            blue_sum += c & 0x000000FF;
+    pub fn clear(&mut self, pixels: std::ops::Range<usize>) {
+        let n = self.bytes_per_pixel();
+        for sample in
+            self.samples[pixels.start * n..pixels.end * n].chunks_exact_mut(self.clear_sample.len())
+        {
+            sample.copy_from_slice(&self.clear_sample);
         }
+    }
+
+    pub fn is_clear(&self) -> bool {
+        self.samples
+            .chunks_exact(self.clear_sample.len())
+            .all(|s| s == self.clear_sample)
+    }
 
-        (0xFF << 24)
-            | (red_sum / N_MSAA_SAMPLES as u32) << 16
-            | (green_sum / N_MSAA_SAMPLES as u32) << 8
-            | (blue_sum / N_MSAA_SAMPLES as u32)
+    /// Average of the samples of a pixel
+    pub fn resolve(&self, idx: usize) -> Color {
+        let sum = self
+            .pixel(idx)
+            .chunks_exact(self.format.bytes_per_sample())
+            .map(|s| self.format.decode(s))
+            .fold(Color::default(), |sum, c| sum + c);
+        sum / self.n_samples as f32
     }
 }
 
+// The samples of all pixels, `n_samples` consecutive ones per pixel
 #[derive(Debug)]
 pub struct DepthBuffer {
-    pub buffer: Vec<[f32; N_MSAA_SAMPLES as usize]>,
-    pub clear_buffer: Vec<[f32; N_MSAA_SAMPLES as usize]>,
+    pub n_samples: usize,
+    pub buffer: Vec<f32>,
 }
 
 impl DepthBuffer {
-    pub fn new(width: usize, height: usize) -> Self {
-        let mut buffer = Vec::with_capacity(width * height);
-        let mut clear_buffer = Vec::with_capacity(width * height);
+    pub fn new(width: usize, height: usize, samples: SampleCount) -> Self {
         // Initialize to max depth => everything will be in front
-        for _i in 0..width * height {
-            buffer.push([CLEAR_DEPTH; N_MSAA_SAMPLES as usize]);
-            clear_buffer.push([CLEAR_DEPTH; N_MSAA_SAMPLES as usize]);
-        }
-        Self {
-            buffer,
-            clear_buffer,
-        }
+        let n_samples = samples.count();
+        let buffer = vec![CLEAR_DEPTH; width * height * n_samples];
+        Self { n_samples, buffer }
     }
 
-    pub fn get_depth(&self, idx: usize) -> &[f32; N_MSAA_SAMPLES as usize] {
-        &self.buffer[idx]
+    pub fn pixel(&self, idx: usize) -> &[f32] {
+        &self.buffer[idx * self.n_samples..(idx + 1) * self.n_samples]
     }
+}
 
-    pub fn set_depth(&mut self, idx: usize, mask_idx: u8, depth: f32) {
-        debug_assert!((0.0..=1.0).contains(&depth), "Invalid depth: {}", depth);
-        self.buffer[idx][mask_idx as usize] = depth;
+#[derive(Debug)]
+pub struct StencilBuffer {
+    pub buffer: Vec<u8>,
+}
+
+impl StencilBuffer {
+    pub fn new(width: usize, height: usize, samples: SampleCount) -> Self {
+        let buffer = vec![CLEAR_STENCIL; width * height * samples.count()];
+        Self { buffer }
     }
 }
 
@@ -160,56 +199,136 @@ impl DepthBuffer {
 mod tests {
     use super::*;
 
-    const RED: u32 = 0xFFFF0000u32;
-    const BLUE: u32 = 0xFF0000FFu32;
-    const GREEN: u32 = 0xFF00FF00u32;
+    fn mark(tiles: &mut BufferTiles, row: usize, col: usize) {
+        let i = row / TILE_SIZE * tiles.n_horizontal + col / TILE_SIZE;
+        *tiles.tiles_mut().nth(i).unwrap().1 = true;
+    }
+
+    #[test]
+    fn pixel_index_tiled() {
+        let (width, height) = (150, 100);
+        let tiles = BufferTiles::new(width, height);
+
+        // Every pixel has its own index
+        let mut seen = vec![false; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let idx = tiles.pixel_index(x, y);
+                assert!(!seen[idx], "{} {}", x, y);
+                seen[idx] = true;
+            }
+        }
+
+        // The tiles are consecutive, row-major inside
+        let mut start = 0;
+        for tile in &tiles.tiles {
+            let pixels = tiles.pixels(tile);
+            assert_eq!(pixels.start, start);
+            assert_eq!(tiles.pixel_index(tile.min_x + 1, tile.min_y), start + 1);
+            let tile_width = tile.max_x - tile.min_x;
+            assert_eq!(
+                tiles.pixel_index(tile.min_x, tile.min_y + 1),
+                start + tile_width
+            );
+            assert_eq!(
+                tiles.pixel_index(tile.max_x - 1, tile.max_y - 1),
+                pixels.end - 1
+            );
+            start = pixels.end;
+        }
+        assert_eq!(start, width * height);
+    }
 
-    fn verify_avg_same(c: u32) {
-        let colors = [c; 4];
-        let avg = ColorBuffer::box_filter_color(&colors);
-        assert_eq!(c, avg, "{:x}, {:x}", c, avg);
+    const FORMATS: [ColorFormat; 4] = [
+        ColorFormat::Rgba8,
+        ColorFormat::Rgba16F,
+        ColorFormat::Rgba32F,
+        ColorFormat::R32F,
+    ];
+
+    // Resolves a 4x MSAA pixel with the samples in each format
+    fn verify_resolve(samples: [Color; 4], expected: Color) {
+        for format in FORMATS {
+            let mut buffer = ColorBuffer::new(1, 1, format, SampleCount::X4);
+            let n = format.bytes_per_sample();
+            for (color, sample) in samples.iter().zip(buffer.samples.chunks_exact_mut(n)) {
+                format.encode(*color, sample);
+            }
+            let resolved = buffer.resolve(0);
+            // Half floats round to 11 significant bits
+            let tolerance = if format == ColorFormat::Rgba16F {
+                1e-3
+            } else {
+                1e-6
+            };
+            let expected = match format {
+                ColorFormat::R32F => Color {
+                    r: expected.r,
+                    a: 1.0,
+                    ..Color::default()
+                },
+                _ => expected,
+            };
+            let diff = resolved - expected;
+            assert!(
+                diff.to_array().iter().all(|d| d.abs() <= tolerance),
+                "{:?}: {:?}, {:?}",
+                format,
+                resolved,
+                expected
+            );
+        }
     }
 
     #[test]
-    fn average_same_color() {
-        verify_avg_same(BLUE);
-        verify_avg_same(GREEN);
-        verify_avg_same(RED);
+    fn resolve_same_color() {
+        let clear = Color::from_argb(CLEAR_COLOR).decode_srgb();
+        for color in [Color::red(), Color::blue(), clear] {
+            verify_resolve([color; 4], color);
+        }
     }
 
     #[test]
-    fn average_two_colors() {
-        let colors = [RED, BLUE, RED, BLUE];
-        let avg = ColorBuffer::box_filter_color(&colors);
-        let expected = 0xFF7F007Fu32;
-        assert_eq!(expected, avg, "{:x}, {:x}", expected, avg);
-
-        let colors = [RED, RED, BLUE, BLUE];
-        let expected = 0xFF7F007Fu32;
-        let avg = ColorBuffer::box_filter_color(&colors);
-        assert_eq!(expected, avg, "{:x}, {:x}", expected, avg);
-
-        let colors = [RED, GREEN, RED, GREEN];
-        let expected = 0xFF7F7F00u32;
-        let avg = ColorBuffer::box_filter_color(&colors);
-        assert_eq!(expected, avg, "{:x}, {:x}", expected, avg);
+    fn resolve_two_colors() {
+        let (red, blue) = (Color::red(), Color::blue());
+        let expected = Color {
+            r: 0.5,
+            g: 0.0,
+            b: 0.5,
+            a: 1.0,
+        };
+        verify_resolve([red, blue, red, blue], expected);
+        verify_resolve([red, red, blue, blue], expected);
     }
 
     #[test]
-    fn average_three_colors() {
-        let colors = [RED, GREEN, RED, BLUE];
-        let avg = ColorBuffer::box_filter_color(&colors);
-        let expected = 0xFF7F3F3Fu32;
-        assert_eq!(expected, avg, "{:x}, {:x}", expected, avg);
+    fn resolve_three_colors() {
+        let (red, green, blue) = (Color::red(), Color::green(), Color::blue());
+        let expected = Color {
+            r: 0.5,
+            g: 0.25,
+            b: 0.25,
+            a: 1.0,
+        };
+        verify_resolve([red, green, red, blue], expected);
     }
 
     #[test]
-    fn average_colors() {
-        let colors = [0xFF35B565, 0xFFF3FA12, 0xFF3E5469, 0xFF435623];
+    fn resolve_colors() {
+        // 8-bit sRGB colors, which Rgba8 stores exactly
+        let samples = [0xFF35B565, 0xFFF3FA12, 0xFF3E5469, 0x80435623]
+            .map(|argb| Color::from_argb(argb).decode_srgb());
+        let expected = (samples[0] + samples[1] + samples[2] + samples[3]) / 4.0;
+        verify_resolve(samples, expected);
+    }
 
-        let avg = ColorBuffer::box_filter_color(&colors);
-        let expected = 0xFF6A9640u32;
-        assert_eq!(expected, avg, "{:x}, {:x}", expected, avg);
+    #[test]
+    fn clear_color_round_trip() {
+        let buffer = ColorBuffer::new(1, 1, ColorFormat::Rgba8, SampleCount::X1);
+        let stored = u32::from_ne_bytes(buffer.samples[..].try_into().unwrap());
+        assert_eq!(stored, CLEAR_COLOR);
+        let presented = buffer.resolve(0).encode_srgb().to_argb();
+        assert_eq!(presented, CLEAR_COLOR);
     }
 
     #[test]
@@ -234,19 +353,19 @@ mod tests {
         assert_eq!(tiles.tiles.last().unwrap().min_y, 128 - TILE_SIZE);
         assert_eq!(tiles.tiles.last().unwrap().max_y, 128);
 
-        tiles.mark(0, 0);
+        mark(&mut tiles, 0, 0);
         assert!(!tiles.masks[0].iter().all(|x| !x));
         assert!(tiles.masks[0][0]);
 
-        tiles.mark(TILE_SIZE - 1, TILE_SIZE - 1);
+        mark(&mut tiles, TILE_SIZE - 1, TILE_SIZE - 1);
         assert_eq!(tiles.marked().count(), 1);
         assert!(tiles.masks[0][0]);
 
-        tiles.mark(127, 127);
+        mark(&mut tiles, 127, 127);
         assert_eq!(tiles.marked().count(), 2);
         assert!(tiles.masks[0].last().unwrap());
 
-        tiles.mark(64, 64);
+        mark(&mut tiles, 64, 64);
         let expected = if TILE_SIZE >= 64 { 2 } else { 3 };
         assert_eq!(tiles.marked().count(), expected);
         assert!(tiles.masks[0][64 / TILE_SIZE * (128 / TILE_SIZE) + 64 / TILE_SIZE]);
@@ -288,19 +407,19 @@ mod tests {
         );
         assert_eq!(tiles.tiles[n_horizontal * (n_vertical - 1)].max_y, 711);
 
-        tiles.mark(0, 0);
+        mark(&mut tiles, 0, 0);
         assert!(!tiles.masks[0].iter().all(|x| !x));
         assert!(tiles.masks[0][0]);
 
-        tiles.mark(TILE_SIZE - 1, TILE_SIZE - 1);
+        mark(&mut tiles, TILE_SIZE - 1, TILE_SIZE - 1);
         assert_eq!(tiles.marked().count(), 1);
         assert!(tiles.masks[0][0]);
 
-        tiles.mark(711, 442);
+        mark(&mut tiles, 711, 442);
         assert_eq!(tiles.marked().count(), 2);
         assert!(tiles.masks[0].last().unwrap());
 
-        tiles.mark(64, 64);
+        mark(&mut tiles, 64, 64);
         assert_eq!(tiles.marked().count(), 3);
         assert!(tiles.masks[0][64 / TILE_SIZE * (442 / TILE_SIZE + 1) + 64 / TILE_SIZE]);
 
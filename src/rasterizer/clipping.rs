@@ -1,16 +1,17 @@
-use crate::graphics_primitives::{Triangle, VertexAttribute};
+use crate::graphics_primitives::{Triangle, Varying};
 use crate::math::point::*;
 use crate::math::ClipSpace;
 
 #[derive(Debug, Clone)]
-pub enum ClipResult {
+pub enum ClipResult<V: Varying> {
     Outside,
     Inside,
-    Clipped(Vec<Triangle<ClipSpace>>),
+    Clipped(Vec<Triangle<ClipSpace, V>>),
 }
 
 const CULL_DEGENERATE_TRIANGLE_AREA_EPS: f32 = 0.000001;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy)]
 enum ClipPlane {
     LEFT,
@@ -59,7 +60,7 @@ const CLIP_PLANES: [ClipPlane; 6] = [
     ClipPlane::FAR,
 ];
 
-pub fn try_clip(triangle: &Triangle<ClipSpace>) -> ClipResult {
+pub fn try_clip<V: Varying>(triangle: &Triangle<ClipSpace, V>) -> ClipResult<V> {
     if super::triangle_2x_area(&triangle.vertices).abs() < CULL_DEGENERATE_TRIANGLE_AREA_EPS {
         return ClipResult::Outside;
     }
@@ -116,7 +117,7 @@ pub fn try_clip(triangle: &Triangle<ClipSpace>) -> ClipResult {
 
     // Here, the Sutherland-Hodgman algorithm starts.
     let mut out_vertices: Vec<Point4D<ClipSpace>> = triangle.vertices.to_vec();
-    let mut out_attrs: Vec<VertexAttribute> = triangle.vertex_attributes.to_vec();
+    let mut out_attrs: Vec<V> = triangle.vertex_attributes.to_vec();
 
     for plane in CLIP_PLANES {
         let in_vertices = out_vertices.clone();
@@ -146,7 +147,7 @@ pub fn try_clip(triangle: &Triangle<ClipSpace>) -> ClipResult {
                         cur_distance_measure,
                     );
                     out_vertices.push(intersection);
-                    out_attrs.push((*cur_attr - prev_attr) * interpolation_factor + prev_attr);
+                    out_attrs.push(prev_attr.lerp(*cur_attr, interpolation_factor));
                 }
                 // Prev outside, cur inside => Add intersection and current, adding a new edge
                 (false, true) => {
@@ -158,7 +159,7 @@ pub fn try_clip(triangle: &Triangle<ClipSpace>) -> ClipResult {
                     );
 
                     out_vertices.push(intersection);
-                    out_attrs.push((*cur_attr - prev_attr) * interpolation_factor + prev_attr);
+                    out_attrs.push(prev_attr.lerp(*cur_attr, interpolation_factor));
                     out_vertices.push(*cur_vert);
                     out_attrs.push(*cur_attr);
                 }
@@ -197,6 +198,7 @@ pub fn try_clip(triangle: &Triangle<ClipSpace>) -> ClipResult {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::graphics_primitives::VertexAttribute;
 
     fn dump(verts: &[Point4D<ClipSpace>]) {
         for (i, v) in verts.iter().enumerate() {
@@ -468,4 +470,30 @@ mod test {
             assert!(std::matches!(try_clip(&t), ClipResult::Inside));
         }
     }
+
+    #[test]
+    fn custom_varying() {
+        // The x coordinate is a varying as well, which means it should be interpolated to the
+        // same value as the clipped vertex.
+        let vertices = [
+            Point4D::<ClipSpace>::new(1.5, 0.0, 0.0, 2.0),
+            Point4D::<ClipSpace>::new(2.5, 1.0, 0.0, 2.0),
+            Point4D::<ClipSpace>::new(0.6, 1.0, 0.0, 2.0),
+        ];
+
+        let tri = Triangle {
+            vertices,
+            vertex_attributes: [vertices[0].x(), vertices[1].x(), vertices[2].x()],
+        };
+
+        let ClipResult::Clipped(clipped) = try_clip(&tri) else {
+            unreachable!("Expected the triangle to be clipped");
+        };
+
+        for t in clipped {
+            for (v, x) in t.vertices.iter().zip(t.vertex_attributes.iter()) {
+                assert!((v.x() - x).abs() < 0.00001, "{} != {}", v.x(), x);
+            }
+        }
+    }
 }
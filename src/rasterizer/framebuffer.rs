@@ -0,0 +1,342 @@
+// Render targets of the rasterizer. A framebuffer has any number of color attachments, each with
+// its own format, and optionally a depth/stencil attachment. All attachments have the same size
+// and number of samples per pixel, which is set per framebuffer.
+
+use super::buffers::*;
+use super::half::{f16_to_f32, f32_to_f16};
+use super::output::OutputTransform;
+use super::SampleCount;
+use crate::color::{linear_to_srgb8, srgb8_to_linear, Color};
+use crate::sampler::{FilterMode, Sampler};
+use crate::texture::{sample_image, MipFilter, Texture};
+
+/// How the samples of a color attachment are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorFormat {
+    /// 8 bits per channel, packed ARGB. The color channels are sRGB-encoded like the presented
+    /// frames, so that dark colors don't band. Clamped to [0, 1] on write.
+    Rgba8,
+    /// 16-bit float per channel, for linear HDR color
+    Rgba16F,
+    /// 32-bit float per channel
+    Rgba32F,
+    /// A single 32-bit float, the red channel. E.g. for depth or ids in a G-buffer.
+    R32F,
+}
+
+impl ColorFormat {
+    pub(super) fn bytes_per_sample(self) -> usize {
+        match self {
+            ColorFormat::Rgba8 => 4,
+            ColorFormat::Rgba16F => 8,
+            ColorFormat::Rgba32F => 16,
+            ColorFormat::R32F => 4,
+        }
+    }
+
+    pub(super) fn encode(self, color: Color, out: &mut [u8]) {
+        match self {
+            ColorFormat::Rgba8 => {
+                let [r, g, b, a] = to_srgba8(color);
+                out.copy_from_slice(&u32::from_be_bytes([a, r, g, b]).to_ne_bytes());
+            }
+            ColorFormat::Rgba16F => {
+                for (v, out) in color.to_array().into_iter().zip(out.chunks_exact_mut(2)) {
+                    out.copy_from_slice(&f32_to_f16(v).to_ne_bytes());
+                }
+            }
+            ColorFormat::Rgba32F => {
+                for (v, out) in color.to_array().into_iter().zip(out.chunks_exact_mut(4)) {
+                    out.copy_from_slice(&v.to_ne_bytes());
+                }
+            }
+            ColorFormat::R32F => out.copy_from_slice(&color.r.to_ne_bytes()),
+        }
+    }
+
+    pub(super) fn decode(self, bytes: &[u8]) -> Color {
+        match self {
+            ColorFormat::Rgba8 => {
+                let [a, r, g, b] = u32::from_ne_bytes(channel(bytes, 0)).to_be_bytes();
+                Color {
+                    r: srgb8_to_linear(r),
+                    g: srgb8_to_linear(g),
+                    b: srgb8_to_linear(b),
+                    a: a as f32 / 255.0,
+                }
+            }
+            ColorFormat::Rgba16F => Color::from_array(std::array::from_fn(|i| {
+                f16_to_f32(u16::from_ne_bytes(channel(bytes, i)))
+            })),
+            ColorFormat::Rgba32F => Color::from_array(std::array::from_fn(|i| {
+                f32::from_ne_bytes(channel(bytes, i))
+            })),
+            ColorFormat::R32F => Color {
+                r: f32::from_ne_bytes(channel(bytes, 0)),
+                g: 0.0,
+                b: 0.0,
+                a: 1.0,
+            },
+        }
+    }
+
+    pub(super) fn clear_color(self) -> Color {
+        match self {
+            ColorFormat::Rgba8 | ColorFormat::Rgba16F | ColorFormat::Rgba32F => {
+                Color::from_argb(CLEAR_COLOR).decode_srgb()
+            }
+            ColorFormat::R32F => Color::default(),
+        }
+    }
+}
+
+// sRGB-encoded color channels and linear alpha, as stored by `Rgba8`
+fn to_srgba8(color: Color) -> [u8; 4] {
+    let [r, g, b] = [color.r, color.g, color.b].map(linear_to_srgb8);
+    [r, g, b, (color.a.clamp(0.0, 1.0) * 255.0).round() as u8]
+}
+
+// The bytes of the i-th N byte channel of a sample
+fn channel<const N: usize>(bytes: &[u8], i: usize) -> [u8; N] {
+    bytes[i * N..(i + 1) * N].try_into().unwrap()
+}
+
+pub struct Framebuffer {
+    pub(super) width: usize,
+    pub(super) height: usize,
+    pub(super) samples: SampleCount,
+    pub(super) color: Vec<ColorBuffer>,
+    pub(super) depth_stencil: Option<(DepthBuffer, StencilBuffer)>,
+    pub(super) tiles: BufferTiles,
+    // The first color attachment, resolved by `resolve_and_clear`
+    resolve_buffer: Vec<u32>,
+    // The presented clear color of the pixels of `resolve_buffer` that were not drawn to
+    resolve_clear: u32,
+}
+
+impl std::fmt::Debug for Framebuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let formats: Vec<_> = self.color.iter().map(|c| c.format).collect();
+        write!(
+            f,
+            "Framebuffer {}x{} ({}x MSAA), color: {:?}, depth/stencil: {}",
+            self.width,
+            self.height,
+            self.samples.count(),
+            formats,
+            self.depth_stencil.is_some()
+        )
+    }
+}
+
+impl Framebuffer {
+    /// One `Rgba16F` color attachment and a depth/stencil attachment, with 4x MSAA. Colors stay
+    /// linear floats until they are converted to 8 bits per channel when presented.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::with_attachments(width, height, &[ColorFormat::Rgba16F], true)
+    }
+
+    /// The fragment shader outputs are written to the color attachments in order. Without the
+    /// depth/stencil attachment, the depth and stencil tests are disabled. 4x MSAA, see
+    /// `set_sample_count`.
+    pub fn with_attachments(
+        width: usize,
+        height: usize,
+        color_formats: &[ColorFormat],
+        depth_stencil: bool,
+    ) -> Self {
+        Self::allocate(width, height, color_formats, depth_stencil, SampleCount::X4)
+    }
+
+    fn allocate(
+        width: usize,
+        height: usize,
+        color_formats: &[ColorFormat],
+        depth_stencil: bool,
+        samples: SampleCount,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            samples,
+            color: color_formats
+                .iter()
+                .map(|&format| ColorBuffer::new(width, height, format, samples))
+                .collect(),
+            depth_stencil: depth_stencil.then(|| {
+                (
+                    DepthBuffer::new(width, height, samples),
+                    StencilBuffer::new(width, height, samples),
+                )
+            }),
+            tiles: BufferTiles::new(width, height),
+            resolve_buffer: vec![CLEAR_COLOR; width * height],
+            resolve_clear: CLEAR_COLOR,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn color_formats(&self) -> impl Iterator<Item = ColorFormat> + '_ {
+        self.color.iter().map(|c| c.format)
+    }
+
+    pub fn has_depth_stencil(&self) -> bool {
+        self.depth_stencil.is_some()
+    }
+
+    pub fn sample_count(&self) -> SampleCount {
+        self.samples
+    }
+
+    /// Reallocates the attachments with `samples` per pixel, their contents are cleared. Fewer
+    /// samples trade the quality of the edges for speed.
+    pub fn set_sample_count(&mut self, samples: SampleCount) {
+        if samples != self.samples {
+            let formats: Vec<_> = self.color_formats().collect();
+            let depth_stencil = self.has_depth_stencil();
+            *self = Self::allocate(self.width, self.height, &formats, depth_stencil, samples);
+        }
+    }
+
+    /// The color of a pixel of an attachment, the average of its samples.
+    pub fn read_color(&self, attachment: usize, x: usize, y: usize) -> Color {
+        assert!(x < self.width && y < self.height);
+        self.color[attachment].resolve(self.tiles.pixel_index(x, y))
+    }
+
+    /// A color attachment resolved into a texture, e.g. to sample it in a later draw. The mips are
+    /// built with a box filter, see `Texture::generate_mips`.
+    /// `Rgba8` attachments become sRGB textures with 8-bit channels, the float formats float
+    /// textures.
+    pub fn resolve_color(&self, attachment: usize) -> Texture {
+        let buffer = &self.color[attachment];
+        let pixels = self.pixel_indices().map(|idx| buffer.resolve(idx));
+        match buffer.format {
+            ColorFormat::Rgba8 => {
+                let buf = pixels.flat_map(to_srgba8).collect();
+                Texture::from_srgb8(buf, self.width, self.height, 4, MipFilter::Box)
+            }
+            ColorFormat::Rgba16F | ColorFormat::Rgba32F => {
+                let buf = pixels.flat_map(Color::to_array).collect();
+                Texture::from_f32(buf, self.width, self.height, 4, MipFilter::Box)
+            }
+            ColorFormat::R32F => {
+                let buf = pixels.map(|c| c.r).collect();
+                Texture::from_f32(buf, self.width, self.height, 1, MipFilter::Box)
+            }
+        }
+    }
+
+    /// The depth attachment resolved into a single channel float texture, e.g. for shadow maps.
+    /// Panics without a depth/stencil attachment.
+    pub fn resolve_depth(&self) -> Texture {
+        let buf = self.pixel_indices().map(|idx| self.depth_at(idx)).collect();
+        Texture::from_f32(buf, self.width, self.height, 1, MipFilter::Box)
+    }
+
+    /// Samples a color attachment directly, without resolving it into a texture first. There are
+    /// no mips, `FilterMode::Nearest` is nearest and everything else bilinear.
+    pub fn sample_color(&self, attachment: usize, u: f32, v: f32, sampler: &Sampler) -> Color {
+        let buffer = &self.color[attachment];
+        let bilinear = sampler.filter != FilterMode::Nearest;
+        sample_image(self.width, self.height, u, v, sampler, bilinear, |x, y| {
+            buffer.resolve(self.tiles.pixel_index(x, y))
+        })
+    }
+
+    /// Same as `sample_color` for the depth attachment, the depth is in the red channel.
+    pub fn sample_depth(&self, u: f32, v: f32, sampler: &Sampler) -> f32 {
+        let bilinear = sampler.filter != FilterMode::Nearest;
+        let depth = |x: usize, y: usize| Color {
+            r: self.depth_at(self.tiles.pixel_index(x, y)),
+            ..Color::default()
+        };
+        sample_image(self.width, self.height, u, v, sampler, bilinear, depth).r
+    }
+
+    // The indices of the pixels in the attachments, row by row
+    fn pixel_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.height)
+            .flat_map(move |y| (0..self.width).map(move |x| self.tiles.pixel_index(x, y)))
+    }
+
+    // The closest sample of the pixel, the far plane if nothing was drawn
+    fn depth_at(&self, idx: usize) -> f32 {
+        let (depth, _) = self
+            .depth_stencil
+            .as_ref()
+            .expect("No depth/stencil attachment");
+        depth.pixel(idx).iter().fold(1.0, |min, &d| d.min(min))
+    }
+
+    /// Resets the samples of all attachments that were drawn to.
+    pub fn clear(&mut self) {
+        for tile in self.tiles.marked() {
+            let pixels = self.tiles.pixels(tile);
+            self.color.iter_mut().for_each(|c| c.clear(pixels.clone()));
+            if let Some((depth, stencil)) = &mut self.depth_stencil {
+                let samples = pixels.start * depth.n_samples..pixels.end * depth.n_samples;
+                depth.buffer[samples.clone()].fill(CLEAR_DEPTH);
+                stencil.buffer[samples].fill(CLEAR_STENCIL);
+            }
+        }
+    }
+
+    // Resolves the first color attachment through the output transform and clears all
+    // attachments. Only the tiles that were drawn to in this or the previous frame are touched,
+    // unless the presented clear color changed.
+    pub(super) fn resolve_and_clear(&mut self, output: &OutputTransform) -> &[u32] {
+        let clear_color = self
+            .color
+            .first()
+            .map_or(Color::default(), |c| c.format.clear_color());
+        let clear = output.apply(clear_color);
+        if clear != self.resolve_clear {
+            self.resolve_buffer.fill(clear);
+            self.resolve_clear = clear;
+        }
+        for tile in self.tiles.prev_marked() {
+            for y in tile.min_y..tile.max_y {
+                for idx in y * self.width + tile.min_x..y * self.width + tile.max_x {
+                    self.resolve_buffer[idx] = clear;
+                }
+            }
+        }
+
+        debug_assert!(self.resolve_buffer.iter().all(|&x| x == clear));
+
+        if let Some(color) = self.color.first() {
+            for tile in self.tiles.marked() {
+                for y in tile.min_y..tile.max_y {
+                    for x in tile.min_x..tile.max_x {
+                        let idx = self.tiles.pixel_index(x, y);
+                        self.resolve_buffer[y * self.width + x] = output.apply(color.resolve(idx));
+                    }
+                }
+            }
+        }
+        self.clear();
+
+        debug_assert!(self.color.iter().all(|c| c.is_clear()));
+        debug_assert!(self.depth_stencil.iter().all(|(depth, stencil)| {
+            let depth_clear = depth.buffer.iter().all(|&v| v == CLEAR_DEPTH);
+            let stencil_clear = stencil.buffer.iter().all(|&v| v == CLEAR_STENCIL);
+            depth_clear && stencil_clear
+        }));
+
+        self.tiles.next();
+
+        &self.resolve_buffer
+    }
+
+    pub(super) fn resolved(&self) -> &[u32] {
+        &self.resolve_buffer
+    }
+}
@@ -0,0 +1,105 @@
+// IEEE 754 half precision floats, the storage of the `Rgba16F` color format. Conversions from f32
+// round to nearest even, values out of range become infinity.
+
+pub fn f32_to_f16(v: f32) -> u16 {
+    let bits = v.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xFF) as i32;
+    let man = bits & 0x7F_FFFF;
+
+    if exp == 0xFF {
+        // Infinity or NaN, NaNs stay NaN
+        let nan = if man != 0 { 0x200 } else { 0 };
+        return sign | 0x7C00 | nan;
+    }
+
+    let exp = exp - 127 + 15;
+    if exp >= 0x1F {
+        return sign | 0x7C00;
+    }
+
+    // The mantissa with the implicit bit, shifted into place, rounded to nearest even
+    let (man, shift) = if exp > 0 {
+        (((exp as u32) << 23) | man, 13)
+    } else if exp >= -10 {
+        // Subnormal
+        (man | 0x80_0000, (14 - exp) as u32)
+    } else {
+        return sign;
+    };
+    let half = man >> shift;
+    let rem = man & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    let round_up = rem > halfway || (rem == halfway && half & 1 == 1);
+    // A carry out of the mantissa increments the exponent, up to infinity
+    sign | (half + round_up as u32) as u16
+}
+
+pub fn f16_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exp = ((h >> 10) & 0x1F) as u32;
+    let man = (h & 0x3FF) as u32;
+
+    match exp {
+        0 => {
+            // Zero or subnormal, exact in f32
+            let v = man as f32 * 2f32.powi(-24);
+            f32::from_bits(sign | v.to_bits())
+        }
+        0x1F => f32::from_bits(sign | 0x7F80_0000 | man << 13),
+        _ => f32::from_bits(sign | (exp + 127 - 15) << 23 | man << 13),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_values() {
+        for (v, h) in [
+            (0.0, 0x0000),
+            (-0.0, 0x8000),
+            (1.0, 0x3C00),
+            (-2.0, 0xC000),
+            (0.5, 0x3800),
+            (65504.0, 0x7BFF),
+            (f32::INFINITY, 0x7C00),
+            (f32::NEG_INFINITY, 0xFC00),
+            (2f32.powi(-14), 0x0400),
+            (2f32.powi(-24), 0x0001),
+        ] {
+            assert_eq!(f32_to_f16(v), h, "{}", v);
+            assert_eq!(f16_to_f32(h), v, "{:x}", h);
+        }
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn roundtrip() {
+        for h in 0..=u16::MAX {
+            let v = f16_to_f32(h);
+            if !v.is_nan() {
+                assert_eq!(f32_to_f16(v), h, "{:x}", h);
+            }
+        }
+    }
+
+    #[test]
+    fn rounding() {
+        let ulp = 2f32.powi(-10);
+        // Ties to even
+        assert_eq!(f32_to_f16(1.0 + ulp / 2.0), 0x3C00);
+        assert_eq!(f32_to_f16(1.0 + ulp * 1.5), 0x3C02);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.6), 0x3C01);
+        assert_eq!(f32_to_f16(0.1), 0x2E66);
+        // Carries into the exponent, overflows to infinity
+        assert_eq!(f32_to_f16(2.0 - ulp / 4.0), 0x4000);
+        assert_eq!(f32_to_f16(65520.0), 0x7C00);
+        assert_eq!(f32_to_f16(1e10), 0x7C00);
+        // Subnormals, underflow to zero
+        assert_eq!(f32_to_f16(1.5 * 2f32.powi(-24)), 0x0002);
+        assert_eq!(f32_to_f16(2f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_f16(-1e-10), 0x8000);
+    }
+}
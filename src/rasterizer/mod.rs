@@ -1,14 +1,23 @@
 use crate::color::Color;
 use crate::graphics_primitives::*;
 use crate::math::*;
-use crate::uniform::*;
 
 mod bounding_box;
 mod buffers;
 mod clipping;
+mod framebuffer;
+mod half;
+mod output;
+mod pool;
+mod simd;
+mod state;
 
 use crate::rasterizer::bounding_box::*;
 use crate::rasterizer::buffers::*;
+pub use crate::rasterizer::framebuffer::{ColorFormat, Framebuffer};
+pub use crate::rasterizer::output::{OutputTransform, ToneMap};
+use crate::rasterizer::simd::*;
+pub use crate::rasterizer::state::*;
 
 use std::f32;
 
@@ -20,181 +29,341 @@ pub fn triangle_2x_area<CS: CoordinateSystem, const N: usize>(
     v10.cross(v20)
 }
 
-const N_MSAA_SAMPLES: u8 = 4;
+/// Samples per pixel of a framebuffer. Coverage, depth and stencil are evaluated per sample,
+/// the fragment shader runs once per pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleCount {
+    X1 = 1,
+    X2 = 2,
+    X4 = 4,
+    X8 = 8,
+    X16 = 16,
+}
+
+impl SampleCount {
+    pub const fn count(self) -> usize {
+        self as usize
+    }
+
+    // The standard sample positions of D3D/Vulkan, in 1/16th of a pixel relative to its center
+    fn positions(self) -> &'static [[f32; 2]] {
+        const fn to_pixel<const N: usize>(offsets: [[i8; 2]; N]) -> [[f32; 2]; N] {
+            let mut out = [[0.0; 2]; N];
+            let mut i = 0;
+            while i < N {
+                out[i] = [
+                    0.5 + offsets[i][0] as f32 / 16.0,
+                    0.5 + offsets[i][1] as f32 / 16.0,
+                ];
+                i += 1;
+            }
+            out
+        }
+        const X1: [[f32; 2]; 1] = to_pixel([[0, 0]]);
+        const X2: [[f32; 2]; 2] = to_pixel([[4, 4], [-4, -4]]);
+        const X4: [[f32; 2]; 4] = to_pixel([[-2, -6], [6, -2], [-6, 2], [2, 6]]);
+        const X8: [[f32; 2]; 8] = to_pixel([
+            [1, -3],
+            [-1, 3],
+            [5, 1],
+            [-3, -5],
+            [-5, 5],
+            [-7, -1],
+            [3, 7],
+            [7, -7],
+        ]);
+        const X16: [[f32; 2]; 16] = to_pixel([
+            [1, 1],
+            [-1, -3],
+            [-3, 2],
+            [4, -1],
+            [-5, -2],
+            [2, 5],
+            [5, 3],
+            [3, -5],
+            [-2, 6],
+            [0, -7],
+            [-4, -6],
+            [-6, 4],
+            [-8, 0],
+            [7, -4],
+            [6, 7],
+            [-7, -8],
+        ]);
+        match self {
+            SampleCount::X1 => &X1,
+            SampleCount::X2 => &X2,
+            SampleCount::X4 => &X4,
+            SampleCount::X8 => &X8,
+            SampleCount::X16 => &X16,
+        }
+    }
+}
 
+const MAX_SAMPLES: usize = SampleCount::X16.count();
+
+/// The samples of a pixel covered by a triangle and passing the depth/stencil tests, bit i for
+/// sample i.
 #[derive(Copy, Clone, Debug)]
 pub struct CoverageMask {
-    mask: u8,
+    mask: u16,
+    n_samples: u8,
 }
 
 impl CoverageMask {
-    const fn len() -> u8 {
-        N_MSAA_SAMPLES
+    fn new(samples: SampleCount) -> Self {
+        CoverageMask {
+            mask: 0,
+            n_samples: samples.count() as u8,
+        }
+    }
+
+    fn full(samples: SampleCount) -> Self {
+        CoverageMask {
+            mask: ((1u32 << samples.count()) - 1) as u16,
+            n_samples: samples.count() as u8,
+        }
     }
-    fn new() -> Self {
-        CoverageMask { mask: 0u8 }
+
+    /// The number of samples per pixel
+    pub const fn n_samples(&self) -> u8 {
+        self.n_samples
     }
 
-    fn any(&self) -> bool {
+    pub fn any(&self) -> bool {
         self.mask != 0
     }
 
-    fn all(&self) -> bool {
-        self.mask == 0b1111
+    pub fn all(&self) -> bool {
+        self.mask as u32 == (1u32 << self.n_samples) - 1
     }
 
-    fn empty(&self) -> bool {
+    pub fn empty(&self) -> bool {
         self.mask == 0
     }
 
-    fn get(&self, i: u8) -> bool {
-        debug_assert!(i < N_MSAA_SAMPLES);
+    pub fn get(&self, i: u8) -> bool {
+        debug_assert!(i < self.n_samples);
         ((1 << i) & self.mask) != 0
     }
 
     fn set(&mut self, i: u8, v: bool) {
-        debug_assert!(i < N_MSAA_SAMPLES);
+        debug_assert!(i < self.n_samples);
         let v = if v { 1 } else { 0 };
         self.mask = (self.mask & (!(1 << i))) | (v << i);
     }
 }
 
-struct Fragment<'a> {
-    sampled_depths: [f32; N_MSAA_SAMPLES as usize],
-    edge_functions: &'a EdgeFunctions,
-    depths_camera_space: &'a [f32; 3],
-    triangle_attributes: &'a [VertexAttribute; 3],
+// Pixels are processed in 2x2 quads, the pixels are the lanes of the `F32x4`s, in the order
+// (x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1).
+const QUAD_OFFSETS: [[usize; 2]; 4] = [[0, 0], [1, 0], [0, 1], [1, 1]];
+
+fn quad_lanes(x: usize, y: usize, dx: f32, dy: f32) -> (F32x4, F32x4) {
+    let (x, y) = (x as f32 + dx, y as f32 + dy);
+    (
+        F32x4::new(x, x + 1.0, x, x + 1.0),
+        F32x4::new(y, y, y + 1.0, y + 1.0),
+    )
 }
 
-impl<'a> Fragment<'a> {
-    fn interpolate(&self, x: usize, y: usize, cov: CoverageMask) -> VertexAttribute {
-        let mut x_sample = x as f32 + 0.5;
-        let mut y_sample = y as f32 + 0.5;
-
-        // We have to sample inside the triangle
-        if !cov.all() {
-            for i in 0..N_MSAA_SAMPLES {
-                if cov.get(i) {
-                    x_sample = x as f32 + RGSS_SAMPLE_PATTERN[i as usize][0];
-                    y_sample = y as f32 + RGSS_SAMPLE_PATTERN[i as usize][1];
-                    break;
-                }
-            }
+#[derive(Debug, Clone)]
+struct EdgeFunctions {
+    points: [Point2D; 3],
+    normals: [Vec2; 3],
+    // Per edge, all lanes set if a sample exactly on the edge is inside, see `tie_inside`
+    tie_masks: [u8; 3],
+}
+
+impl EdgeFunctions {
+    fn new(points: [Point2D; 3], normals: [Vec2; 3]) -> Self {
+        let tie_masks = normals.map(|n| if Self::tie_inside(n) { ALL_LANES } else { 0 });
+        Self {
+            points,
+            normals,
+            tie_masks,
+        }
+    }
+
+    fn eval_lanes(&self, x: F32x4, y: F32x4) -> [F32x4; 3] {
+        std::array::from_fn(|i| {
+            let (n, p) = (self.normals[i], self.points[i]);
+            (x - F32x4::splat(p.x())) * n.x() + (y - F32x4::splat(p.y())) * n.y()
+        })
+    }
+
+    // The samples of the quad with the upper left pixel at (x, y). If it is known to be fully
+    // covered, e.g. from `block_coverage`, the inside tests are skipped.
+    fn eval_quad(&self, x: usize, y: usize, samples: SampleCount, covered: bool) -> Quad {
+        let mut evaluated = [[F32x4::splat(0.0); 3]; MAX_SAMPLES];
+        for (evaluated, &[dx, dy]) in evaluated.iter_mut().zip(samples.positions()) {
+            let (xs, ys) = quad_lanes(x, y, dx, dy);
+            *evaluated = self.eval_lanes(xs, ys);
         }
+        let mut quad = Quad {
+            x,
+            y,
+            samples,
+            evaluated,
+            coverage: [CoverageMask::new(samples); 4],
+        };
+        quad.update_coverage(self, covered);
+        quad
+    }
 
-        let efs = self.edge_functions.eval_single(x_sample, y_sample);
+    // Samples exactly on an edge belong to the triangle if the edge is a left edge or a
+    // horizontal top edge (top-left rule).
+    fn tie_inside(normal: Vec2) -> bool {
+        if normal.x() > 0.0 {
+            return true;
+        }
+        if normal.x() < 0.0 {
+            return false;
+        }
+        normal.y() > 0.0
+    }
 
-        // Perspective correct barycentrics.
-        let f_u = efs[1] / self.depths_camera_space[0];
-        let f_v = efs[2] / self.depths_camera_space[1];
-        let f_w = efs[0] / self.depths_camera_space[2];
-        let sum = f_u + f_v + f_w;
-        let u = clamp_bary(f_u / sum);
-        let v = clamp_bary(f_v / sum);
-        let w = clamp_bary(1.0 - u - v);
+    // Conservative coverage of the square block with the upper left corner at (x, y). As the edge
+    // functions are linear, their extremes over the block are at the corners.
+    fn block_coverage(&self, x: usize, y: usize, size: usize) -> BlockCoverage {
+        let (x0, y0) = (x as f32, y as f32);
+        let (x1, y1) = ((x + size) as f32, (y + size) as f32);
+        let corners = self.eval_lanes(F32x4::new(x0, x1, x0, x1), F32x4::new(y0, y0, y1, y1));
+
+        let mut all_inside = true;
+        for (corners, normal) in corners.iter().zip(self.normals.iter()) {
+            // Margin for the rounding errors of the incremental evaluation, 1% of a pixel.
+            let margin = (normal.x().abs() + normal.y().abs()) * 0.01;
+            if corners.lt(F32x4::splat(-margin)) == ALL_LANES {
+                return BlockCoverage::Outside;
+            }
+            all_inside &= corners.gt(F32x4::splat(margin)) == ALL_LANES;
+        }
 
-        self.triangle_attributes[0] * u
-            + self.triangle_attributes[1] * v
-            + self.triangle_attributes[2] * w
+        if all_inside {
+            BlockCoverage::Inside
+        } else {
+            BlockCoverage::Partial
+        }
     }
 }
 
-fn clamp_bary(x: f32) -> f32 {
-    const EPS: f32 = 0.0001;
-    debug_assert!((0.0 - EPS..=1.0 + EPS).contains(&x), "{}", x);
-    x.clamp(0.0, 1.0)
-}
+// Size of the blocks that are tested against the triangle before walking their quads
+const BLOCK_SIZE: usize = 8;
 
-// Rotated grid super sampling
-const RGSS_SAMPLE_PATTERN: [[f32; 2]; N_MSAA_SAMPLES as usize] = [
-    [5.0 / 8.0, 1.0 / 8.0],
-    [7.0 / 8.0, 5.0 / 8.0],
-    [3.0 / 8.0, 7.0 / 8.0],
-    [1.0 / 8.0, 3.0 / 8.0],
-];
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockCoverage {
+    Outside,
+    Partial,
+    Inside,
+}
 
 #[derive(Debug, Clone)]
-struct EdgeFunctions {
-    points: [Point2D; 3],
-    normals: [Vec2; 3],
-    coverage_evaluated: [[f32; 3]; N_MSAA_SAMPLES as usize],
-    coverage_mask: CoverageMask,
+struct Quad {
+    // Upper left pixel
+    x: usize,
+    y: usize,
+    samples: SampleCount,
+    // The edge functions per sample, the first `samples` are used
+    evaluated: [[F32x4; 3]; MAX_SAMPLES],
+    // Per pixel
+    coverage: [CoverageMask; 4],
 }
 
-impl EdgeFunctions {
-    fn eval_single(&self, x: f32, y: f32) -> [f32; 3] {
-        let p = Point2D::new(x, y);
-        [
-            self.normals[0].dot(p - self.points[0]),
-            self.normals[1].dot(p - self.points[1]),
-            self.normals[2].dot(p - self.points[2]),
-        ]
+impl Quad {
+    // Move one quad to the right by adding the x component of the normals to the evaluated edge
+    // functions.
+    fn step_x(&mut self, edge_functions: &EdgeFunctions, covered: bool) {
+        self.x += 2;
+        for evaluated in self.evaluated[..self.samples.count()].iter_mut() {
+            for (val, normal) in evaluated.iter_mut().zip(edge_functions.normals.iter()) {
+                *val = *val + F32x4::splat(2.0 * normal.x());
+            }
+        }
+        self.update_coverage(edge_functions, covered);
     }
 
-    fn eval(&mut self, x: usize, y: usize) {
-        for i in 0..N_MSAA_SAMPLES {
-            let x_sample = x as f32 + RGSS_SAMPLE_PATTERN[i as usize][0];
-            let y_sample = y as f32 + RGSS_SAMPLE_PATTERN[i as usize][1];
+    fn update_coverage(&mut self, edge_functions: &EdgeFunctions, covered: bool) {
+        if covered {
+            self.coverage = [CoverageMask::full(self.samples); 4];
+            return;
+        }
 
-            self.coverage_evaluated[i as usize] = self.eval_single(x_sample, y_sample);
+        let zero = F32x4::splat(0.0);
+        self.coverage = [CoverageMask::new(self.samples); 4];
+        for (i, evaluated) in self.evaluated[..self.samples.count()].iter().enumerate() {
+            let inside = evaluated
+                .iter()
+                .zip(edge_functions.tie_masks.iter())
+                .fold(ALL_LANES, |mask, (val, tie)| {
+                    mask & (val.gt(zero) | (val.eq(zero) & tie))
+                });
+            for (lane, cov) in self.coverage.iter_mut().enumerate() {
+                cov.set(i as u8, inside & (1 << lane) != 0);
+            }
+        }
+    }
 
-            self.coverage_mask.set(
-                i,
-                EdgeFunctions::inside(&self.normals, &self.coverage_evaluated[i as usize]),
-            );
+    // Pixels that are not set in `lanes` are not covered
+    fn mask_lanes(&mut self, lanes: u8) {
+        for (lane, cov) in self.coverage.iter_mut().enumerate() {
+            if lanes & (1 << lane) == 0 {
+                *cov = CoverageMask::new(self.samples);
+            }
         }
     }
 
-    fn inside(normals: &[Vec2; 3], eval_edge_funcs: &[f32; 3]) -> bool {
-        eval_edge_funcs
+    fn active_lanes(&self) -> u8 {
+        self.coverage
             .iter()
-            .zip(normals.iter())
-            .all(|(val, normal)| {
-                if *val > 0.0 {
-                    return true;
-                }
-                if *val < 0.0 {
-                    return false;
-                }
-                if normal.x() > 0.0 {
-                    return true;
-                }
-                if normal.x() < 0.0 {
-                    return false;
-                }
-                if normal.y() < 0.0 {
-                    return true;
-                }
-                false
-            })
+            .enumerate()
+            .fold(0, |m, (lane, cov)| m | (cov.any() as u8) << lane)
     }
+}
 
-    fn any_coverage(&self) -> bool {
-        self.coverage_mask.any()
-    }
+fn clamp_bary(x: F32x4, active_lanes: u8) -> F32x4 {
+    const EPS: f32 = 0.0001;
+    debug_assert!(
+        x.lt(F32x4::splat(-EPS)) & active_lanes == 0
+            && x.gt(F32x4::splat(1.0 + EPS)) & active_lanes == 0,
+        "{:?}",
+        x
+    );
+    x.clamp(0.0, 1.0)
 }
+
 // Implicitly in 2D Screen space
 #[derive(Debug, Clone)]
-struct RasterizerTriangle {
+struct RasterizerTriangle<V: Varying = VertexAttribute> {
     edge_functions: EdgeFunctions,
     depths_camera_space: [f32; 3],
     depths: [f32; 3],
-    attributes: [VertexAttribute; 3],
+    attributes: [V; 3],
     inv_2x_area: f32,
+    // Of the input vertices, the stored ones are always clockwise
+    winding: Winding,
 }
 
-impl RasterizerTriangle {
+impl<V: Varying> RasterizerTriangle<V> {
     pub fn new(
-        vertices: [Point3D<ScreenSpace>; 3],
-        depths_camera_space: [f32; 3],
-        attributes: [VertexAttribute; 3],
+        mut vertices: [Point3D<ScreenSpace>; 3],
+        mut depths_camera_space: [f32; 3],
+        mut attributes: [V; 3],
     ) -> Self {
+        // Positive area means clockwise as y is pointing down. Counter-clockwise triangles are made
+        // clockwise by swapping two vertices so that the same edge equations work for both.
+        let winding = if triangle_2x_area(&vertices) < 0.0 {
+            vertices.swap(1, 2);
+            depths_camera_space.swap(1, 2);
+            attributes.swap(1, 2);
+            Winding::CounterClockwise
+        } else {
+            Winding::Clockwise
+        };
+
         // Clockwise edge equations
         // To have the normals all pointing towards the inner part of the triangle,
         // they all need to have their positive halfspace to the right of the triangle.
-        // If we wanted counter-clockwise, then we switch signs on both x and y of normals
-        // (and also switch order for v computations above. Note that coordinate system
-        // starts in upper left corner.
+        // Note that coordinate system starts in upper left corner.
 
         let v0 = vertices[1] - vertices[0];
         let v1 = vertices[2] - vertices[1];
@@ -205,12 +374,10 @@ impl RasterizerTriangle {
 
         let inv_2x_area = 1.0 / triangle_2x_area(&vertices);
 
-        let edge_functions = EdgeFunctions {
-            points: [vertices[0].xy(), vertices[1].xy(), vertices[2].xy()],
-            normals: [n0, n1, n2],
-            coverage_evaluated: [[0.0; 3]; N_MSAA_SAMPLES as usize],
-            coverage_mask: CoverageMask::new(),
-        };
+        let edge_functions = EdgeFunctions::new(
+            [vertices[0].xy(), vertices[1].xy(), vertices[2].xy()],
+            [n0, n1, n2],
+        );
 
         Self {
             edge_functions,
@@ -218,70 +385,179 @@ impl RasterizerTriangle {
             depths: [vertices[0].z(), vertices[1].z(), vertices[2].z()],
             attributes,
             inv_2x_area,
+            winding,
         }
     }
 
+    fn is_degenerate(&self) -> bool {
+        !self.inv_2x_area.is_finite()
+    }
+
+    // The depths of the covered samples of each pixel of the quad, the others are 0.
     // See realtime rendering on details
-    fn fragment(&self) -> Fragment<'_> {
-        let interpolate_depth = |edge_functions: &[f32; 3]| -> f32 {
+    fn sample_depths(&self, quad: &Quad) -> [[f32; MAX_SAMPLES]; 4] {
+        let mut depths = [[0.0; MAX_SAMPLES]; 4];
+        for (i, edge_functions) in quad.evaluated[..quad.samples.count()].iter().enumerate() {
+            let covered = quad
+                .coverage
+                .iter()
+                .enumerate()
+                .fold(0, |m, (lane, cov)| m | (cov.get(i as u8) as u8) << lane);
             // Linear barycentrics, used only for interpolating z
-            let bary0 = clamp_bary(edge_functions[1] * self.inv_2x_area);
-            let bary1 = clamp_bary(edge_functions[2] * self.inv_2x_area);
-            let bary2 = clamp_bary(1.0 - bary0 - bary1);
+            let bary0 = clamp_bary(edge_functions[1] * self.inv_2x_area, covered);
+            let bary1 = clamp_bary(edge_functions[2] * self.inv_2x_area, covered);
+            let bary2 = clamp_bary(F32x4::splat(1.0) - bary0 - bary1, covered);
 
             // z here is in NDC and in that transform it was divided by w (camera space depth) which
             // means we can interpolate it with the linear barycentrics. For attributes, we need
             // perspective correct barycentrics
-            bary0 * self.depths[0] + bary1 * self.depths[1] + bary2 * self.depths[2]
-        };
-
-        let mut sampled_depths = [0.0; N_MSAA_SAMPLES as usize];
-
-        for i in 0..N_MSAA_SAMPLES {
-            if self.edge_functions.coverage_mask.get(i) {
-                sampled_depths[i as usize] =
-                    interpolate_depth(&self.edge_functions.coverage_evaluated[i as usize]);
+            let z = (bary0 * self.depths[0] + bary1 * self.depths[1] + bary2 * self.depths[2])
+                .to_array();
+            for lane in 0..4 {
+                if covered & (1 << lane) != 0 {
+                    depths[lane][i] = z[lane];
+                }
             }
         }
 
-        Fragment {
-            sampled_depths,
-            edge_functions: &self.edge_functions,
-            depths_camera_space: &self.depths_camera_space,
-            triangle_attributes: &self.attributes,
+        depths
+    }
+
+    // Perspective correct barycentrics for each pixel of the quad. At the center of the pixel if
+    // all samples are covered, otherwise at the first covered sample as we have to sample inside
+    // the triangle.
+    fn barycentrics(&self, quad: &Quad, coverage: &[CoverageMask; 4]) -> [F32x4; 3] {
+        let mut xs = [0.0; 4];
+        let mut ys = [0.0; 4];
+        let mut active_lanes = 0;
+        for (lane, cov) in coverage.iter().enumerate() {
+            let (x, y) = (
+                quad.x + QUAD_OFFSETS[lane][0],
+                quad.y + QUAD_OFFSETS[lane][1],
+            );
+            let [dx, dy] = (0..cov.n_samples())
+                .find(|&i| !cov.all() && cov.get(i))
+                .map_or([0.5, 0.5], |i| quad.samples.positions()[i as usize]);
+            xs[lane] = x as f32 + dx;
+            ys[lane] = y as f32 + dy;
+            active_lanes |= (cov.any() as u8) << lane;
         }
+
+        let efs = self
+            .edge_functions
+            .eval_lanes(F32x4::from_array(xs), F32x4::from_array(ys));
+
+        let f_u = efs[1] / F32x4::splat(self.depths_camera_space[0]);
+        let f_v = efs[2] / F32x4::splat(self.depths_camera_space[1]);
+        let f_w = efs[0] / F32x4::splat(self.depths_camera_space[2]);
+        let sum = f_u + f_v + f_w;
+        let u = clamp_bary(f_u / sum, active_lanes);
+        let v = clamp_bary(f_v / sum, active_lanes);
+        let w = clamp_bary(F32x4::splat(1.0) - u - v, active_lanes);
+        [u, v, w]
+    }
+
+    // Difference of the perspective correct barycentrics between the centers of two pixels.
+    // Extrapolated for pixels outside of the triangle, like the helper pixels of a GPU.
+    fn barycentric_difference(&self, from: [usize; 2], to: [usize; 2]) -> [f32; 3] {
+        let xs = F32x4::new(from[0] as f32, to[0] as f32, 0.0, 0.0) + F32x4::splat(0.5);
+        let ys = F32x4::new(from[1] as f32, to[1] as f32, 0.0, 0.0) + F32x4::splat(0.5);
+        let efs = self.edge_functions.eval_lanes(xs, ys);
+        let f_u = efs[1] / F32x4::splat(self.depths_camera_space[0]);
+        let f_v = efs[2] / F32x4::splat(self.depths_camera_space[1]);
+        let f_w = efs[0] / F32x4::splat(self.depths_camera_space[2]);
+        let sum = f_u + f_v + f_w;
+        let u = (f_u / sum).to_array();
+        let v = (f_v / sum).to_array();
+        let bary = |lane: usize| [u[lane], v[lane], 1.0 - u[lane] - v[lane]];
+        let (from, to) = (bary(0), bary(1));
+        [to[0] - from[0], to[1] - from[1], to[2] - from[2]]
+    }
+
+    fn interpolate(&self, barycentrics: &[F32x4; 3], lane: usize) -> V {
+        let [u, v, w] = barycentrics.map(|b| b.to_array()[lane]);
+        self.attributes[0] * u + self.attributes[1] * v + self.attributes[2] * w
     }
 }
 
-pub struct FragCoords {
+pub struct FragCoords<'a, V: Varying = VertexAttribute> {
     // x,y are screen space
     pub x: f32,
     pub y: f32,
-    pub depths: [f32; 4],
+    /// Of the samples in `mask`
+    pub depths: &'a [f32],
     pub mask: CoverageMask,
+    // According to the winding and `PipelineState::front_face`
+    pub front_facing: bool,
+    // The derivatives are only computed when asked for
+    triangle: &'a RasterizerTriangle<V>,
+    quad: [usize; 2],
+    lane: usize,
+}
+
+impl<V: Varying> FragCoords<'_, V> {
+    /// Screen-space derivative of the varyings along x, the difference to the horizontal
+    /// neighbour in the 2x2 quad of pixels that are shaded together. E.g. for the texture LOD.
+    pub fn dfdx(&self) -> V {
+        // Lanes 0 and 1 are the upper row
+        self.derivative(self.lane & 2, self.lane & 2 | 1)
+    }
+
+    /// Screen-space derivative of the varyings along y, see `dfdx`.
+    pub fn dfdy(&self) -> V {
+        // Lanes 0 and 2 are the left column
+        self.derivative(self.lane & 1, self.lane & 1 | 2)
+    }
+
+    fn derivative(&self, from: usize, to: usize) -> V {
+        let pixel = |lane: usize| {
+            let [dx, dy] = QUAD_OFFSETS[lane];
+            [self.quad[0] + dx, self.quad[1] + dy]
+        };
+        let weights = self.triangle.barycentric_difference(pixel(from), pixel(to));
+        let attributes = &self.triangle.attributes;
+        attributes[0] * weights[0] + attributes[1] * weights[1] + attributes[2] * weights[2]
+    }
+}
+
+/// What a fragment shader returns, one color per color attachment of the framebuffer. Outputs
+/// without an attachment are dropped and attachments without an output are not written.
+pub trait FragmentOutput {
+    fn colors(&self) -> &[Color];
+}
+
+impl FragmentOutput for Color {
+    fn colors(&self) -> &[Color] {
+        std::slice::from_ref(self)
+    }
+}
+
+impl<const N: usize> FragmentOutput for [Color; N] {
+    fn colors(&self) -> &[Color] {
+        self
+    }
 }
 
 pub struct Rasterizer {
-    color_buffer: ColorBuffer,
-    depth_buffer: DepthBuffer,
-    buffer_tiles: BufferTiles,
-    width: usize,
-    height: usize,
+    // Sized to the window, what `framebuffer` resolves
+    framebuffer: Framebuffer,
+    output: OutputTransform,
+    pool: pool::WorkerPool,
 }
 
 impl Rasterizer {
     pub fn new(width: usize, height: usize) -> Self {
         Self {
-            width,
-            height,
-            color_buffer: ColorBuffer::new(width, height),
-            depth_buffer: DepthBuffer::new(width, height),
-            buffer_tiles: BufferTiles::new(width, height),
+            framebuffer: Framebuffer::new(width, height),
+            output: OutputTransform::default(),
+            pool: pool::WorkerPool::new(
+                std::thread::available_parallelism().map_or(1, |n| n.get()),
+            ),
         }
     }
 
     // Divide x, y and z by w
-    fn perspective_divide(triangle: &Triangle<ClipSpace>) -> Triangle<NDC> {
+    fn perspective_divide<V: Varying>(triangle: &Triangle<ClipSpace, V>) -> Triangle<NDC, V> {
         let old_verts = triangle.vertices;
 
         let v0 = Point4D::<NDC>::new(
@@ -306,13 +582,198 @@ impl Rasterizer {
         );
 
         let vertices = [v0, v1, v2];
-        Triangle::<NDC> {
+        Triangle {
             vertices,
             vertex_attributes: triangle.vertex_attributes,
         }
     }
 
-    fn viewport_transform(&self, tri: Triangle<NDC>) -> RasterizerTriangle {
+    /// The fragment shader is called with the perspective correct interpolation of the varyings
+    /// of the triangle.
+    pub fn rasterize<U, V, O, F>(
+        &mut self,
+        triangles: &[Triangle<ClipSpace, V>],
+        state: &PipelineState,
+        uniforms: &U,
+        fragment_shader: F,
+    ) where
+        U: Sync,
+        V: Varying,
+        O: FragmentOutput,
+        F: Fn(&U, &FragCoords<V>, &V) -> O + Sync,
+    {
+        Self::rasterize_on(
+            &self.pool,
+            &mut self.framebuffer,
+            triangles,
+            state,
+            uniforms,
+            fragment_shader,
+        );
+    }
+
+    /// Same as `rasterize` but into a framebuffer of any size, instead of the one of the window.
+    pub fn rasterize_to<U, V, O, F>(
+        &self,
+        framebuffer: &mut Framebuffer,
+        triangles: &[Triangle<ClipSpace, V>],
+        state: &PipelineState,
+        uniforms: &U,
+        fragment_shader: F,
+    ) where
+        U: Sync,
+        V: Varying,
+        O: FragmentOutput,
+        F: Fn(&U, &FragCoords<V>, &V) -> O + Sync,
+    {
+        Self::rasterize_on(
+            &self.pool,
+            framebuffer,
+            triangles,
+            state,
+            uniforms,
+            fragment_shader,
+        );
+    }
+
+    fn rasterize_on<U, V, O, F>(
+        pool: &pool::WorkerPool,
+        framebuffer: &mut Framebuffer,
+        triangles: &[Triangle<ClipSpace, V>],
+        state: &PipelineState,
+        uniforms: &U,
+        fragment_shader: F,
+    ) where
+        U: Sync,
+        V: Varying,
+        O: FragmentOutput,
+        F: Fn(&U, &FragCoords<V>, &V) -> O + Sync,
+    {
+        // # Triangle clipping
+        // As I've understood things, there are two opportunities for clipping/culling:
+        // 1. Before perspective divide. Clipping triangles based on the viewing frustum.
+        // 2. After conversion to screenspace, clipping the triangle bounding box.
+        //
+        // 1. Gives these benefits:
+        //   - More triangles discarded early.
+        //   - Bounds the input values for coordinates for later stages by constraining all triangles/coordinates to the viewing volume.
+        //   - Simpler to interpolate vertex attributes for newly created triangles.
+        // 2. Is fairly straightforward, we just make sure to not walk the pixels (fragments) in the triangle bounding box that we know already are outside.
+        //   It only works for x/y directions though, the depth needs to be clipped in 1. New triangles are not created, no need for interpolation.
+        //
+        // It might be possible to remove 2, assuming 1 always produces triangles that are perfectly inside the viewport but to make the code
+        // a bit more error-tolerant, I have kept 2 as well. It would also be needed in case guard-bands are introduced for clipping at some point in the
+        // future. Triangles would no longer be clipped to the viewport in 1 but to a larger area, which means they'd need to be clipped to the viewport.
+        // Some sources:
+        // * https://www.reddit.com/r/GraphicsProgramming/comments/zxlti5/near_clipping_before_perspective_projection/
+        // * https://www.reddit.com/r/GraphicsProgramming/comments/mi45z7/raster_clipping_vs_geometry_clipping/
+        // * https://fabiensanglard.net/polygon_codec/clippingdocument/p245-blinn.pdf
+
+        let mut setup = Vec::with_capacity(triangles.len());
+        for raw_triangle in triangles {
+            let mut clipped_triangles: &[Triangle<ClipSpace, V>] =
+                std::slice::from_ref(raw_triangle);
+            let clipped_triangles_buf;
+            use clipping::ClipResult;
+            match clipping::try_clip(raw_triangle) {
+                ClipResult::Outside => continue,
+                ClipResult::Inside => (),
+                ClipResult::Clipped(tris) => {
+                    clipped_triangles_buf = tris;
+                    clipped_triangles = &clipped_triangles_buf;
+                }
+            }
+
+            for triangle in clipped_triangles {
+                let triangle: Triangle<NDC, V> = Rasterizer::perspective_divide(triangle);
+                let triangle: RasterizerTriangle<V> = framebuffer.viewport_transform(triangle);
+                if triangle.is_degenerate() || state.culls(triangle.winding) {
+                    continue;
+                }
+                let b_box = framebuffer.bounding_box(&triangle);
+                if b_box.min_x < b_box.max_x && b_box.min_y < b_box.max_y {
+                    setup.push((triangle, b_box));
+                }
+            }
+        }
+
+        if setup.is_empty() {
+            return;
+        }
+
+        // Binning: Each tile gets the triangles overlapping it, in submission order. The threads
+        // then take the tiles from a queue and rasterize them. A pixel is only ever written by the
+        // thread owning its tile, which means the triangles are still drawn in order for each
+        // pixel.
+        let n_horizontal = framebuffer.tiles.n_horizontal();
+        let mut bins = vec![Vec::new(); n_horizontal * framebuffer.height.div_ceil(TILE_SIZE)];
+        for (i, (triangle, b_box)) in setup.iter().enumerate() {
+            for tile_y in b_box.min_y / TILE_SIZE..b_box.max_y.div_ceil(TILE_SIZE) {
+                for tile_x in b_box.min_x / TILE_SIZE..b_box.max_x.div_ceil(TILE_SIZE) {
+                    let coverage = triangle.edge_functions.block_coverage(
+                        tile_x * TILE_SIZE,
+                        tile_y * TILE_SIZE,
+                        TILE_SIZE,
+                    );
+                    if coverage != BlockCoverage::Outside {
+                        bins[tile_y * n_horizontal + tile_x].push(i);
+                    }
+                }
+            }
+        }
+
+        let work = framebuffer
+            .split_tiles()
+            .into_iter()
+            .zip(bins)
+            .filter(|(_, bin)| !bin.is_empty());
+        let work = std::sync::Mutex::new(work);
+        let rasterize_tiles = || loop {
+            // Released before rasterizing
+            let next = work.lock().unwrap().next();
+            let Some((mut tile, bin)) = next else {
+                break;
+            };
+            for i in bin {
+                let (triangle, b_box) = &setup[i];
+                tile.rasterize_triangle(triangle, b_box, state, uniforms, &fragment_shader);
+            }
+        };
+
+        pool.run(&rasterize_tiles);
+    }
+
+    /// The number of threads used by `rasterize`, defaults to the available parallelism. They
+    /// are kept around until the rasterizer is dropped.
+    pub fn set_thread_count(&mut self, n_threads: usize) {
+        assert!(n_threads > 0);
+        if n_threads != self.pool.n_threads() {
+            self.pool = pool::WorkerPool::new(n_threads);
+        }
+    }
+
+    /// Samples per pixel of the framebuffer of the window, 4x by default. Clears it.
+    pub fn set_sample_count(&mut self, samples: SampleCount) {
+        self.framebuffer.set_sample_count(samples);
+    }
+
+    /// Applied to the frames resolved by `framebuffer`
+    pub fn output_transform(&mut self) -> &mut OutputTransform {
+        &mut self.output
+    }
+
+    pub fn framebuffer(&mut self) -> &[u32] {
+        self.framebuffer.resolve_and_clear(&self.output)
+    }
+
+    /// The frame produced by the last call to `framebuffer`.
+    pub fn last_frame(&self) -> &[u32] {
+        self.framebuffer.resolved()
+    }
+}
+
+impl Framebuffer {
+    fn viewport_transform<V: Varying>(&self, tri: Triangle<NDC, V>) -> RasterizerTriangle<V> {
         let zmin = 0.0;
         let zmax = 1.0;
         let new_vert = |vert: Point4D<NDC>| {
@@ -344,7 +805,7 @@ impl Rasterizer {
         RasterizerTriangle::new(vertices, depths, tri.vertex_attributes)
     }
 
-    fn bounding_box(&self, triangle: &RasterizerTriangle) -> PixelBoundingBox {
+    fn bounding_box<V: Varying>(&self, triangle: &RasterizerTriangle<V>) -> PixelBoundingBox {
         let tri_b_box = PixelBoundingBox::from(&triangle.edge_functions.points);
         // In the future, I think this would be the place to implement scissor support.
         // Instead of hardcoding these, the user would supply a scissoring rect that could be used to bound the triangles.
@@ -360,171 +821,328 @@ impl Rasterizer {
         }
     }
 
-    fn depth_coverage(
-        &self,
+    // Splits the attachments into their tiles, which can be written independently
+    fn split_tiles(&mut self) -> Vec<Tile<'_>> {
+        let samples = self.samples;
+        let mut color: Vec<_> = self
+            .color
+            .iter_mut()
+            .map(|c| (c.format, c.bytes_per_pixel(), c.samples.as_mut_slice()))
+            .collect();
+        let (mut depth, mut stencil) = match &mut self.depth_stencil {
+            Some((depth, stencil)) => (
+                Some(depth.buffer.as_mut_slice()),
+                Some(stencil.buffer.as_mut_slice()),
+            ),
+            None => (None, None),
+        };
+
+        self.tiles
+            .tiles_mut()
+            .map(|(bounds, marked)| {
+                let n_pixels = (bounds.max_x - bounds.min_x) * (bounds.max_y - bounds.min_y);
+                let n_samples = n_pixels * samples.count();
+                Tile {
+                    bounds: bounds.clone(),
+                    samples,
+                    color: color
+                        .iter_mut()
+                        .map(|(format, bytes_per_pixel, rest)| {
+                            let n_bytes = n_pixels * *bytes_per_pixel;
+                            (*format, rest.split_off_mut(..n_bytes).unwrap())
+                        })
+                        .collect(),
+                    depth: depth
+                        .as_mut()
+                        .map(|rest| rest.split_off_mut(..n_samples).unwrap()),
+                    stencil: stencil
+                        .as_mut()
+                        .map(|rest| rest.split_off_mut(..n_samples).unwrap()),
+                    marked,
+                }
+            })
+            .collect()
+    }
+}
+
+// A tile of the render target, rasterized by a single thread. Rows and columns are in screen
+// space, i.e. not relative to the tile.
+struct Tile<'a> {
+    bounds: PixelBoundingBox,
+    samples: SampleCount,
+    // The samples of each color attachment, in the bytes of its format
+    color: Vec<(ColorFormat, &'a mut [u8])>,
+    // Both or neither, the depth/stencil attachment is optional
+    depth: Option<&'a mut [f32]>,
+    stencil: Option<&'a mut [u8]>,
+    marked: &'a mut bool,
+}
+
+impl Tile<'_> {
+    fn idx(&self, row: usize, col: usize) -> usize {
+        let b = &self.bounds;
+        debug_assert!((b.min_y..b.max_y).contains(&row) && (b.min_x..b.max_x).contains(&col));
+        (row - b.min_y) * (b.max_x - b.min_x) + (col - b.min_x)
+    }
+
+    fn mark(&mut self) {
+        *self.marked = true;
+    }
+
+    // Stencil and depth test of the covered samples. The stencil buffer is updated according to
+    // the stencil ops as part of the test, the depth buffer is written in `write_pixel`.
+    fn depth_stencil_coverage(
+        &mut self,
         row: usize,
         col: usize,
         cov: CoverageMask,
-        sampled_depths: &[f32; N_MSAA_SAMPLES as usize],
+        sampled_depths: &[f32],
+        state: &PipelineState,
+        front_facing: bool,
     ) -> CoverageMask {
-        let cur_depths = self.depth_buffer.get_depth(row * self.width + col);
-        let mut depth_cov = CoverageMask::new();
-        for i in 0..N_MSAA_SAMPLES {
-            if cov.get(i) {
-                depth_cov.set(i, sampled_depths[i as usize] < cur_depths[i as usize]);
+        let depth_state = &state.depth;
+        let stencil_state = &state.stencil;
+        let (idx, n_samples) = (self.idx(row, col), self.samples.count());
+        let pixel = idx * n_samples..(idx + 1) * n_samples;
+        let (Some(depth), Some(stencil)) = (&self.depth, &mut self.stencil) else {
+            return cov;
+        };
+        if !depth_state.test_enable && !stencil_state.enable {
+            return cov;
+        }
+
+        let cur_depths = &depth[pixel.clone()];
+        if !stencil_state.enable {
+            // Four samples at once, the lanes past the last sample are not in the coverage mask
+            let lanes =
+                |v: &[f32]| F32x4::from_array(std::array::from_fn(|i| *v.get(i).unwrap_or(&0.0)));
+            let mut pass = 0;
+            for (i, (sampled, cur)) in sampled_depths
+                .chunks(4)
+                .zip(cur_depths.chunks(4))
+                .enumerate()
+            {
+                pass |=
+                    (depth_state.compare.passes_x4(lanes(sampled), lanes(cur)) as u16) << (4 * i);
             }
+            return CoverageMask {
+                mask: cov.mask & pass,
+                ..cov
+            };
         }
-        depth_cov
+
+        let stencil = &mut stencil[pixel];
+        let stencil_face = stencil_state.face(front_facing);
+        let mut out_cov = CoverageMask::new(self.samples);
+        let mut stencil_written = false;
+        for i in 0..cov.n_samples() {
+            if !cov.get(i) {
+                continue;
+            }
+
+            let depth_pass = !depth_state.test_enable
+                || depth_state
+                    .compare
+                    .passes(sampled_depths[i as usize], cur_depths[i as usize]);
+
+            let stored = stencil[i as usize];
+            let stencil_pass = stencil_state.test(stencil_face, stored);
+            let op = if !stencil_pass {
+                stencil_face.fail
+            } else if !depth_pass {
+                stencil_face.depth_fail
+            } else {
+                stencil_face.pass
+            };
+            let new = stencil_state.update(op, stored);
+            if new != stored {
+                stencil[i as usize] = new;
+                stencil_written = true;
+            }
+
+            out_cov.set(i, stencil_pass && depth_pass);
+        }
+
+        // The stencil can be written without any color writes, e.g. on depth fail. Mark the tile
+        // so that it is cleared.
+        if stencil_written {
+            self.mark();
+        }
+
+        out_cov
     }
 
     fn write_pixel(
         &mut self,
         row: usize,
         col: usize,
-        color: Color,
-        depths: &[f32; N_MSAA_SAMPLES as usize],
+        colors: &[Color],
+        depths: &[f32],
         cov_mask: CoverageMask,
+        state: &PipelineState,
     ) {
         debug_assert!(cov_mask.any());
-        self.buffer_tiles.mark(row, col);
-        for i in 0..N_MSAA_SAMPLES {
-            if cov_mask.get(i) {
-                let idx = row * self.width + col;
-                self.color_buffer.set_pixel(idx, i, color);
-                self.depth_buffer.set_depth(idx, i, depths[i as usize]);
+        self.mark();
+        let pixel = self.idx(row, col) * self.samples.count();
+        for (&color, (format, samples)) in colors.iter().zip(self.color.iter_mut()) {
+            let n_bytes = format.bytes_per_sample();
+            for i in 0..cov_mask.n_samples() {
+                if cov_mask.get(i) {
+                    let sample = &mut samples[(pixel + i as usize) * n_bytes..][..n_bytes];
+                    // Per sample, as the samples of a pixel can have different colors along edges
+                    let color = match &state.blend {
+                        Some(blend) => blend.blend(color, format.decode(sample)),
+                        None => color,
+                    };
+                    format.encode(color, sample);
+                }
             }
         }
-    }
 
-    pub fn rasterize(
-        &mut self,
-        triangles: &[Triangle<ClipSpace>],
-        uniforms: &Uniforms,
-        fragment_shader: crate::render::FragmentShader,
-    ) {
-        // # Triangle clipping
-        // As I've understood things, there are two opportunities for clipping/culling:
-        // 1. Before perspective divide. Clipping triangles based on the viewing frustum.
-        // 2. After conversion to screenspace, clipping the triangle bounding box.
-        //
-        // 1. Gives these benefits:
-        //   - More triangles discarded early.
-        //   - Bounds the input values for coordinates for later stages by constraining all triangles/coordinates to the viewing volume.
-        //   - Simpler to interpolate vertex attributes for newly created triangles.
-        // 2. Is fairly straightforward, we just make sure to not walk the pixels (fragments) in the triangle bounding box that we know already are outside.
-        //   It only works for x/y directions though, the depth needs to be clipped in 1. New triangles are not created, no need for interpolation.
-        //
-        // It might be possible to remove 2, assuming 1 always produces triangles that are perfectly inside the viewport but to make the code
-        // a bit more error-tolerant, I have kept 2 as well. It would also be needed in case guard-bands are introduced for clipping at some point in the
-        // future. Triangles would no longer be clipped to the viewport in 1 but to a larger area, which means they'd need to be clipped to the viewport.
-        // Some sources:
-        // * https://www.reddit.com/r/GraphicsProgramming/comments/zxlti5/near_clipping_before_perspective_projection/
-        // * https://www.reddit.com/r/GraphicsProgramming/comments/mi45z7/raster_clipping_vs_geometry_clipping/
-        // * https://fabiensanglard.net/polygon_codec/clippingdocument/p245-blinn.pdf
-
-        for raw_triangle in triangles {
-            let mut clipped_triangles: &[Triangle<ClipSpace>] = &[raw_triangle.clone()];
-            let clipped_triangles_buf;
-            use clipping::ClipResult;
-            match clipping::try_clip(raw_triangle) {
-                ClipResult::Outside => continue,
-                ClipResult::Inside => (),
-                ClipResult::Clipped(tris) => {
-                    clipped_triangles_buf = tris;
-                    clipped_triangles = &clipped_triangles_buf;
+        if let Some(depth) = self.depth.as_mut().filter(|_| state.writes_depth()) {
+            for i in 0..cov_mask.n_samples() {
+                if cov_mask.get(i) {
+                    let d = depths[i as usize];
+                    debug_assert!((0.0..=1.0).contains(&d), "Invalid depth: {}", d);
+                    depth[pixel + i as usize] = d;
                 }
             }
+        }
+    }
 
-            for triangle in clipped_triangles {
-                let triangle: Triangle<NDC> = Rasterizer::perspective_divide(triangle);
-                let mut triangle: RasterizerTriangle = self.viewport_transform(triangle);
-                let b_box = self.bounding_box(&triangle);
-
-                for i in b_box.min_y..b_box.max_y {
-                    for j in b_box.min_x..b_box.max_x {
-                        triangle.edge_functions.eval(j, i);
-                        if triangle.edge_functions.any_coverage() {
-                            let fragment = triangle.fragment();
-                            let cov_mask = self.depth_coverage(
-                                i,
-                                j,
-                                triangle.edge_functions.coverage_mask,
-                                &fragment.sampled_depths,
-                            );
-                            if cov_mask.empty() {
-                                continue;
-                            }
-
-                            let fc = FragCoords {
-                                x: j as f32 + 0.5,
-                                y: i as f32 + 0.5,
-                                depths: fragment.sampled_depths,
-                                mask: fragment.edge_functions.coverage_mask,
-                            };
-
-                            let col = fragment_shader(
-                                uniforms,
-                                &fc,
-                                &fragment.interpolate(j, i, cov_mask),
-                            );
-                            self.write_pixel(i, j, col, &fragment.sampled_depths, cov_mask);
+    fn rasterize_triangle<U, V, O, F>(
+        &mut self,
+        triangle: &RasterizerTriangle<V>,
+        b_box: &PixelBoundingBox,
+        state: &PipelineState,
+        uniforms: &U,
+        fragment_shader: &F,
+    ) where
+        V: Varying,
+        O: FragmentOutput,
+        F: Fn(&U, &FragCoords<V>, &V) -> O,
+    {
+        let min_x = b_box.min_x.max(self.bounds.min_x);
+        let max_x = b_box.max_x.min(self.bounds.max_x);
+        let min_y = b_box.min_y.max(self.bounds.min_y);
+        let max_y = b_box.max_y.min(self.bounds.max_y);
+        // Blocks are aligned to the screen (and thereby the tiles and quads)
+        let blocks_y = (min_y / BLOCK_SIZE * BLOCK_SIZE..max_y).step_by(BLOCK_SIZE);
+        let blocks_x = (min_x / BLOCK_SIZE * BLOCK_SIZE..max_x).step_by(BLOCK_SIZE);
+        for block_y in blocks_y {
+            for block_x in blocks_x.clone() {
+                let covered = match triangle
+                    .edge_functions
+                    .block_coverage(block_x, block_y, BLOCK_SIZE)
+                {
+                    BlockCoverage::Outside => continue,
+                    BlockCoverage::Partial => false,
+                    BlockCoverage::Inside => true,
+                };
+
+                let cols = block_x.max(min_x)..(block_x + BLOCK_SIZE).min(max_x);
+                let rows = block_y.max(min_y)..(block_y + BLOCK_SIZE).min(max_y);
+                for y in (rows.start & !1..rows.end).step_by(2) {
+                    // Evaluated from scratch at the start of each row to not accumulate errors
+                    let mut quad = triangle.edge_functions.eval_quad(
+                        cols.start & !1,
+                        y,
+                        self.samples,
+                        covered,
+                    );
+                    loop {
+                        // Pixels of the quad outside of the bounding box, e.g. outside of the
+                        // screen or another tile.
+                        let lanes = QUAD_OFFSETS.iter().enumerate().fold(0, |m, (lane, o)| {
+                            let inside =
+                                cols.contains(&(quad.x + o[0])) && rows.contains(&(y + o[1]));
+                            m | (inside as u8) << lane
+                        });
+                        quad.mask_lanes(lanes);
+                        self.shade_quad(triangle, &quad, state, uniforms, fragment_shader);
+
+                        if quad.x + 2 >= cols.end {
+                            break;
                         }
+                        quad.step_x(&triangle.edge_functions, covered);
                     }
                 }
             }
         }
     }
 
-    fn resolve_and_clear(&mut self) -> &[u32] {
-        debug_assert_eq!(self.width * self.height, self.color_buffer.buffer.len());
-        debug_assert_eq!(self.width * self.height, self.depth_buffer.buffer.len());
-
-        let resolve = &mut self.color_buffer.resolve_buffer;
-        let cbuf = &mut self.color_buffer.buffer;
-        let dbuf = &mut self.depth_buffer.buffer;
-
-        for tile in self.buffer_tiles.prev_marked() {
-            for y in tile.min_y..tile.max_y {
-                for x in tile.min_x..tile.max_x {
-                    let idx = y * self.width + x;
-                    resolve[idx] = buffers::CLEAR_COLOR;
-                }
-            }
+    fn shade_quad<U, V, O, F>(
+        &mut self,
+        triangle: &RasterizerTriangle<V>,
+        quad: &Quad,
+        state: &PipelineState,
+        uniforms: &U,
+        fragment_shader: &F,
+    ) where
+        V: Varying,
+        O: FragmentOutput,
+        F: Fn(&U, &FragCoords<V>, &V) -> O,
+    {
+        if quad.active_lanes() == 0 {
+            return;
         }
 
-        debug_assert!(resolve.iter().all(|&x| x == buffers::CLEAR_COLOR));
-
-        for tile in self.buffer_tiles.marked() {
-            for y in tile.min_y..tile.max_y {
-                for x in tile.min_x..tile.max_x {
-                    let idx = y * self.width + x;
-                    resolve[idx] = ColorBuffer::box_filter_color(&cbuf[idx]);
-                    cbuf[idx] = [buffers::CLEAR_COLOR; N_MSAA_SAMPLES as usize];
-                    dbuf[idx] = [buffers::CLEAR_DEPTH; N_MSAA_SAMPLES as usize];
-                }
+        let front_facing = state.is_front_facing(triangle.winding);
+        let sampled_depths = triangle.sample_depths(quad);
+        let n_samples = self.samples.count();
+        let mut coverage = [CoverageMask::new(self.samples); 4];
+        for (lane, cov) in coverage.iter_mut().enumerate() {
+            if quad.coverage[lane].any() {
+                let [dx, dy] = QUAD_OFFSETS[lane];
+                *cov = self.depth_stencil_coverage(
+                    quad.y + dy,
+                    quad.x + dx,
+                    quad.coverage[lane],
+                    &sampled_depths[lane][..n_samples],
+                    state,
+                    front_facing,
+                );
             }
         }
 
-        debug_assert!(cbuf
-            .iter()
-            .all(|x| x.iter().all(|v| *v == buffers::CLEAR_COLOR)));
-        debug_assert!(dbuf
-            .iter()
-            .all(|x| x.iter().all(|v| *v == buffers::CLEAR_DEPTH)));
-
-        self.buffer_tiles.next();
+        if coverage.iter().all(|cov| cov.empty()) {
+            return;
+        }
 
-        resolve
-    }
+        let barycentrics = triangle.barycentrics(quad, &coverage);
+        for (lane, cov) in coverage.iter().enumerate() {
+            if cov.empty() {
+                continue;
+            }
 
-    pub fn framebuffer(&mut self) -> &[u32] {
-        self.resolve_and_clear()
+            let (x, y) = (
+                quad.x + QUAD_OFFSETS[lane][0],
+                quad.y + QUAD_OFFSETS[lane][1],
+            );
+            let fc = FragCoords {
+                x: x as f32 + 0.5,
+                y: y as f32 + 0.5,
+                depths: &sampled_depths[lane][..n_samples],
+                mask: quad.coverage[lane],
+                front_facing,
+                triangle,
+                quad: [quad.x, quad.y],
+                lane,
+            };
+
+            let out = fragment_shader(uniforms, &fc, &triangle.interpolate(&barycentrics, lane));
+            let depths = &sampled_depths[lane][..n_samples];
+            self.write_pixel(y, x, out.colors(), depths, *cov, state);
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::sampler::{FilterMode, Sampler, WrapMode};
+    use crate::uniform::Uniforms;
+    use std::cmp::Ordering;
 
     #[test]
     fn perspective_divide() {
@@ -534,7 +1152,7 @@ mod test {
             Point4D::<ClipSpace>::new(0.5, -0.3, 0.0, 1.0),
         ];
 
-        let vertex_attributes = [
+        let vertex_attributes: [VertexAttribute; 3] = [
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
@@ -554,8 +1172,8 @@ mod test {
         ];
 
         assert_eq!(expected.len(), tri_ndc.vertices.len());
-        for i in 0..expected.len() {
-            assert_eq!(tri_ndc.vertices[i], expected[i]);
+        for (v, e) in tri_ndc.vertices.iter().zip(expected.iter()) {
+            assert_eq!(v, e);
         }
     }
 
@@ -564,7 +1182,7 @@ mod test {
         const WIDTH: usize = 400;
         const HEIGHT: usize = 500;
 
-        let rasterizer = Rasterizer::new(WIDTH, HEIGHT);
+        let framebuffer = Framebuffer::new(WIDTH, HEIGHT);
 
         let vertices = [
             Point4D::<NDC>::new(-1.0, 0.5, -0.5, 5.0),
@@ -572,7 +1190,7 @@ mod test {
             Point4D::<NDC>::new(0.0, -0.5, 0.5, 7.0),
         ];
 
-        let vertex_attributes = [
+        let vertex_attributes: [VertexAttribute; 3] = [
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
@@ -583,10 +1201,10 @@ mod test {
             vertex_attributes,
         };
 
-        let rast_tri = rasterizer.viewport_transform(tri);
+        let rast_tri = framebuffer.viewport_transform(tri);
 
-        for i in 0..3 {
-            assert_eq!(rast_tri.depths_camera_space[i], vertices[i].w());
+        for (depth, v) in rast_tri.depths_camera_space.iter().zip(vertices.iter()) {
+            assert_eq!(*depth, v.w());
         }
 
         assert_eq!(rast_tri.depths[0], 0.25);
@@ -612,7 +1230,7 @@ mod test {
         const WIDTH: usize = 400;
         const HEIGHT: usize = 500;
 
-        let rasterizer = Rasterizer::new(WIDTH, HEIGHT);
+        let framebuffer = Framebuffer::new(WIDTH, HEIGHT);
 
         let vertices = [
             Point4D::<NDC>::new(-0.25, 1.0, -1.0, 5.0),
@@ -620,7 +1238,7 @@ mod test {
             Point4D::<NDC>::new(0.25, -1.0, 1.0, 7.0),
         ];
 
-        let vertex_attributes = [
+        let vertex_attributes: [VertexAttribute; 3] = [
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
@@ -631,10 +1249,10 @@ mod test {
             vertex_attributes,
         };
 
-        let rast_tri = rasterizer.viewport_transform(tri);
+        let rast_tri = framebuffer.viewport_transform(tri);
 
-        for i in 0..3 {
-            assert_eq!(rast_tri.depths_camera_space[i], vertices[i].w());
+        for (depth, v) in rast_tri.depths_camera_space.iter().zip(vertices.iter()) {
+            assert_eq!(*depth, v.w());
         }
 
         assert_eq!(rast_tri.depths[0], 0.0);
@@ -656,7 +1274,7 @@ mod test {
 
     #[test]
     fn coverage_mask() {
-        let mut m = CoverageMask::new();
+        let mut m = CoverageMask::new(SampleCount::X4);
         assert!(m.empty());
         assert!(!m.any());
 
@@ -699,6 +1317,37 @@ mod test {
         assert!(!m.get(3));
         assert!(!m.any());
         assert!(m.empty());
+
+        for samples in [SampleCount::X1, SampleCount::X8, SampleCount::X16] {
+            let mut m = CoverageMask::full(samples);
+            assert!(m.all());
+            assert_eq!(m.n_samples() as usize, samples.count());
+            m.set(m.n_samples() - 1, false);
+            assert!(!m.all());
+            assert_eq!(m.empty(), samples == SampleCount::X1);
+        }
+    }
+
+    #[test]
+    fn sample_positions() {
+        for samples in [
+            SampleCount::X1,
+            SampleCount::X2,
+            SampleCount::X4,
+            SampleCount::X8,
+            SampleCount::X16,
+        ] {
+            let positions = samples.positions();
+            assert_eq!(positions.len(), samples.count());
+            assert!(positions.iter().flatten().all(|v| (0.0..1.0).contains(v)));
+            // No two samples share a row or a column, so near horizontal and vertical edges get
+            // as many steps as there are samples
+            for (i, a) in positions.iter().enumerate() {
+                for b in &positions[i + 1..] {
+                    assert!(a[0] != b[0] && a[1] != b[1], "{:?}", samples);
+                }
+            }
+        }
     }
 
     fn setup_rasterizer_triangle() -> RasterizerTriangle {
@@ -710,7 +1359,7 @@ mod test {
 
         let depths = [5.0, 6.0, 7.0];
 
-        let vertex_attributes = [
+        let vertex_attributes: [VertexAttribute; 3] = [
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
@@ -719,9 +1368,38 @@ mod test {
         RasterizerTriangle::new(vertices, depths, vertex_attributes)
     }
 
+    // The coverage of a single pixel, through the quad containing it
+    fn coverage_at(rast_tri: &RasterizerTriangle, x: usize, y: usize) -> CoverageMask {
+        let quad = rast_tri
+            .edge_functions
+            .eval_quad(x & !1, y & !1, SampleCount::X4, false);
+        quad.coverage[(x & 1) + 2 * (y & 1)]
+    }
+
+    fn depths_at(rast_tri: &RasterizerTriangle, x: usize, y: usize) -> [f32; 4] {
+        let quad = rast_tri
+            .edge_functions
+            .eval_quad(x & !1, y & !1, SampleCount::X4, false);
+        rast_tri.sample_depths(&quad)[(x & 1) + 2 * (y & 1)][..4]
+            .try_into()
+            .unwrap()
+    }
+
+    fn eval_single(edge_functions: &EdgeFunctions, x: f32, y: f32) -> [f32; 3] {
+        edge_functions
+            .eval_lanes(F32x4::splat(x), F32x4::splat(y))
+            .map(|e| e.to_array()[0])
+    }
+
+    fn inside(edge_functions: &EdgeFunctions, e: &[f32; 3]) -> bool {
+        e.iter()
+            .zip(edge_functions.normals.iter())
+            .all(|(&v, &n)| v > 0.0 || (v == 0.0 && EdgeFunctions::tie_inside(n)))
+    }
+
     #[test]
     fn edge_functions_basic() {
-        let mut rast_tri = setup_rasterizer_triangle();
+        let rast_tri = setup_rasterizer_triangle();
 
         assert_eq!(
             rast_tri.edge_functions.points[0],
@@ -737,45 +1415,116 @@ mod test {
             Point2D::new(300.0, 300.0)
         );
 
-        rast_tri.edge_functions.eval(200, 200);
-        assert!(rast_tri.edge_functions.any_coverage());
-        assert_eq!(rast_tri.edge_functions.coverage_mask.mask, 0b1111);
+        let cov = coverage_at(&rast_tri, 200, 200);
+        assert!(cov.any());
+        assert_eq!(cov.mask, 0b1111);
+
+        let cov = coverage_at(&rast_tri, 99, 299);
+        assert!(!cov.any());
+        assert_eq!(cov.mask, 0);
+
+        let cov = coverage_at(&rast_tri, 101, 299);
+        assert!(cov.any());
+        assert_eq!(cov.mask, 0b1111);
+
+        let cov = coverage_at(&rast_tri, 200, 149);
+        assert!(!cov.any());
+        assert_eq!(cov.mask, 0);
+        let cov = coverage_at(&rast_tri, 200, 151);
+        assert!(cov.any());
+        assert_eq!(cov.mask, 0b1111);
 
-        rast_tri.edge_functions.eval(99, 299);
-        assert!(!rast_tri.edge_functions.any_coverage());
-        assert_eq!(rast_tri.edge_functions.coverage_mask.mask, 0);
+        let cov = coverage_at(&rast_tri, 301, 300);
+        assert!(!cov.any());
+        assert_eq!(cov.mask, 0);
+    }
 
-        rast_tri.edge_functions.eval(101, 299);
-        assert!(rast_tri.edge_functions.any_coverage());
-        assert_eq!(rast_tri.edge_functions.coverage_mask.mask, 0b1111);
+    #[test]
+    fn edge_functions_step_x() {
+        // Thin and diagonal
+        let vertices = [
+            Point3D::<ScreenSpace>::new(10.3, 5.1, 0.5),
+            Point3D::<ScreenSpace>::new(90.7, 60.2, 0.5),
+            Point3D::<ScreenSpace>::new(88.1, 63.9, 0.5),
+        ];
+        let rast_tri = RasterizerTriangle::new(vertices, [1.0; 3], [0.0f32; 3]);
+        let efs = &rast_tri.edge_functions;
+
+        for y in (0..70).step_by(2) {
+            let mut stepped = efs.eval_quad(0, y, SampleCount::X4, false);
+            for x in (2..100).step_by(2) {
+                stepped.step_x(efs, false);
+                let direct = efs.eval_quad(x, y, SampleCount::X4, false);
+                assert_eq!(stepped.x, direct.x);
+                for (s, d) in stepped.coverage.iter().zip(direct.coverage.iter()) {
+                    assert_eq!(s.mask, d.mask);
+                }
+                for (s, d) in stepped.evaluated.iter().zip(direct.evaluated.iter()) {
+                    for k in 0..3 {
+                        for (s, d) in s[k].to_array().iter().zip(d[k].to_array()) {
+                            // Difference in pixels
+                            let diff = (s - d).abs() / efs.normals[k].len();
+                            assert!(diff < 0.001, "{} != {}", s, d);
+                        }
+                    }
+                }
+            }
+        }
+    }
 
-        rast_tri.edge_functions.eval(200, 149);
-        assert!(!rast_tri.edge_functions.any_coverage());
-        assert_eq!(rast_tri.edge_functions.coverage_mask.mask, 0);
-        rast_tri.edge_functions.eval(200, 151);
-        assert!(rast_tri.edge_functions.any_coverage());
-        assert_eq!(rast_tri.edge_functions.coverage_mask.mask, 0b1111);
+    #[test]
+    fn quads() {
+        let rast_tri = setup_rasterizer_triangle();
+        let efs = &rast_tri.edge_functions;
+
+        let quad = efs.eval_quad(200, 200, SampleCount::X4, false);
+        assert!(quad.coverage.iter().all(|cov| cov.all()));
+        assert_eq!(quad.active_lanes(), 0b1111);
+
+        // Along the left edge, the upper left pixel is outside
+        let quad = efs.eval_quad(100, 298, SampleCount::X4, false);
+        assert_eq!(quad.active_lanes(), 0b1110);
+        assert!(!quad.coverage[2].all());
+        assert!(quad.coverage[3].all());
+
+        let mut quad = efs.eval_quad(200, 200, SampleCount::X4, true);
+        assert!(quad.coverage.iter().all(|cov| cov.all()));
+        quad.mask_lanes(0b0101);
+        assert_eq!(quad.active_lanes(), 0b0101);
+        assert!(quad.coverage[1].empty());
+
+        let quad = efs.eval_quad(0, 0, SampleCount::X4, false);
+        assert_eq!(quad.active_lanes(), 0);
+    }
 
-        rast_tri.edge_functions.eval(301, 300);
-        assert!(!rast_tri.edge_functions.any_coverage());
-        assert_eq!(rast_tri.edge_functions.coverage_mask.mask, 0);
+    #[test]
+    fn edge_functions_block_coverage() {
+        let rast_tri = setup_rasterizer_triangle();
+        let efs = &rast_tri.edge_functions;
+
+        assert_eq!(efs.block_coverage(0, 0, 8), BlockCoverage::Outside);
+        assert_eq!(efs.block_coverage(304, 296, 8), BlockCoverage::Outside);
+        assert_eq!(efs.block_coverage(200, 248, 8), BlockCoverage::Inside);
+        assert_eq!(efs.block_coverage(96, 296, 8), BlockCoverage::Partial);
+        assert_eq!(efs.block_coverage(192, 144, 8), BlockCoverage::Partial);
+        assert_eq!(efs.block_coverage(0, 0, 512), BlockCoverage::Partial);
     }
 
     #[test]
     fn edge_functions_partial() {
-        let mut rast_tri = setup_rasterizer_triangle();
+        let rast_tri = setup_rasterizer_triangle();
 
-        rast_tri.edge_functions.eval(299, 299);
-        assert!(rast_tri.edge_functions.any_coverage());
-        assert_eq!(rast_tri.edge_functions.coverage_mask.mask, 0b1100);
+        let cov = coverage_at(&rast_tri, 299, 299);
+        assert!(cov.any());
+        assert_eq!(cov.mask, 0b1101);
 
-        rast_tri.edge_functions.eval(150, 224);
-        assert!(rast_tri.edge_functions.any_coverage());
-        assert_eq!(rast_tri.edge_functions.coverage_mask.mask, 0b0111);
+        let cov = coverage_at(&rast_tri, 150, 224);
+        assert!(cov.any());
+        assert_eq!(cov.mask, 0b1010);
 
-        rast_tri.edge_functions.eval(250, 225);
-        assert!(rast_tri.edge_functions.any_coverage());
-        assert_eq!(rast_tri.edge_functions.coverage_mask.mask, 0b1100);
+        let cov = coverage_at(&rast_tri, 250, 225);
+        assert!(cov.any());
+        assert_eq!(cov.mask, 0b0100);
     }
 
     #[test]
@@ -783,40 +1532,76 @@ mod test {
         let rast_tri = setup_rasterizer_triangle();
 
         // Testing the tie-breaker rules.
-        let e = rast_tri.edge_functions.eval_single(150.0, 225.0);
-        assert!(EdgeFunctions::inside(&rast_tri.edge_functions.normals, &e));
+        let e = eval_single(&rast_tri.edge_functions, 150.0, 225.0);
+        assert!(inside(&rast_tri.edge_functions, &e));
         assert_eq!(e[0], 0.0);
         assert!(rast_tri.edge_functions.normals[0].x() > 0.0);
 
-        let e = rast_tri.edge_functions.eval_single(250.0, 225.0);
-        assert!(!EdgeFunctions::inside(&rast_tri.edge_functions.normals, &e));
+        let e = eval_single(&rast_tri.edge_functions, 250.0, 225.0);
+        assert!(!inside(&rast_tri.edge_functions, &e));
         assert_eq!(e[1], 0.0);
         assert!(rast_tri.edge_functions.normals[1].x() < 0.0);
 
-        let e = rast_tri.edge_functions.eval_single(250.0, 300.0);
-        assert!(EdgeFunctions::inside(&rast_tri.edge_functions.normals, &e));
+        // Horizontal edges: bottom ones are outside, top ones inside
+        let e = eval_single(&rast_tri.edge_functions, 250.0, 300.0);
+        assert!(!inside(&rast_tri.edge_functions, &e));
         assert_eq!(e[2], 0.0);
         assert_eq!(rast_tri.edge_functions.normals[2].x(), 0.0);
         assert!(rast_tri.edge_functions.normals[2].y() < 0.0);
+
+        let flipped: RasterizerTriangle = RasterizerTriangle::new(
+            [
+                Point3D::<ScreenSpace>::new(100.0, 150.0, 0.5),
+                Point3D::<ScreenSpace>::new(300.0, 150.0, 0.5),
+                Point3D::<ScreenSpace>::new(200.0, 300.0, 0.5),
+            ],
+            [5.0, 6.0, 7.0],
+            [(Color::red(), [0.0, 0.0]).into(); 3],
+        );
+        let e = eval_single(&flipped.edge_functions, 200.0, 150.0);
+        assert!(inside(&flipped.edge_functions, &e));
+        assert!(e.contains(&0.0));
     }
 
     #[test]
-    fn fragment_creation_same_depth() {
-        let mut rast_tri = setup_rasterizer_triangle();
+    fn horizontal_edge_samples() {
+        // Two triangles sharing a horizontal edge through the topmost sample of the pixels in
+        // row 0, like a triangle clipped to the top of the viewport and the one above it
+        let y = SampleCount::X4
+            .positions()
+            .iter()
+            .map(|p| p[1])
+            .fold(f32::MAX, f32::min);
+        let triangle = |vertices: [[f32; 2]; 3]| -> RasterizerTriangle {
+            RasterizerTriangle::new(
+                vertices.map(|[x, y]| Point3D::<ScreenSpace>::new(x, y, 0.5)),
+                [5.0; 3],
+                [(Color::red(), [0.0, 0.0]).into(); 3],
+            )
+        };
+        let below = triangle([[-8.0, y], [24.0, y], [-8.0, 40.0]]);
+        let above = triangle([[-8.0, y - 32.0], [24.0, y], [-8.0, y]]);
+
+        // The sample on the edge belongs to the triangle below it only
+        for x in 0..4 {
+            assert!(coverage_at(&below, x, 0).all());
+            assert!(coverage_at(&above, x, 0).empty());
+        }
+    }
 
-        rast_tri.edge_functions.eval(200, 200);
+    #[test]
+    fn fragment_creation_same_depth() {
+        let rast_tri = setup_rasterizer_triangle();
 
-        let fragment = rast_tri.fragment();
-        assert_eq!(fragment.sampled_depths, [0.5; 4]);
+        let sampled_depths = depths_at(&rast_tri, 200, 200);
+        assert_eq!(sampled_depths, [0.5; 4]);
     }
 
     #[test]
     fn fragment_creation_same_depth_partial_coverage() {
-        let mut rast_tri = setup_rasterizer_triangle();
-        rast_tri.edge_functions.eval(299, 299);
-
-        let fragment = rast_tri.fragment();
-        assert_eq!(fragment.sampled_depths, [0.0, 0.0, 0.5, 0.5]);
+        let rast_tri = setup_rasterizer_triangle();
+        let sampled_depths = depths_at(&rast_tri, 299, 299);
+        assert_eq!(sampled_depths, [0.5, 0.0, 0.5, 0.5]);
     }
 
     #[test]
@@ -830,51 +1615,49 @@ mod test {
         // Not important atm, so can be anything
         let depths = [5.0, 6.0, 7.0];
 
-        let vertex_attributes = [
+        let vertex_attributes: [VertexAttribute; 3] = [
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 0.0]).into(),
         ];
 
-        let mut rast_tri = RasterizerTriangle::new(vertices, depths, vertex_attributes);
+        let rast_tri = RasterizerTriangle::new(vertices, depths, vertex_attributes);
 
-        rast_tri.edge_functions.eval(101, 299);
-        let fragment = rast_tri.fragment();
+        let sampled_depths = depths_at(&rast_tri, 101, 299);
         // This is expected to be very close to the attribute
         assert_eq!(
-            fragment.sampled_depths,
-            [0.50039583, 0.5019375, 0.50177085, 0.5002292]
+            sampled_depths,
+            [0.5000208, 0.50135416, 0.5008125, 0.5021458]
         );
 
-        rast_tri.edge_functions.eval(200, 151);
-        let fragment = rast_tri.fragment();
+        let sampled_depths = depths_at(&rast_tri, 200, 151);
         // This is expected to be very close to the attribute
         assert_eq!(
-            fragment.sampled_depths,
-            [0.30356252, 0.30510417, 0.3049375, 0.30339584]
+            sampled_depths,
+            [0.30318752, 0.30452085, 0.30397916, 0.3053125]
         );
 
-        rast_tri.edge_functions.eval(298, 299);
-        let fragment = rast_tri.fragment();
+        let sampled_depths = depths_at(&rast_tri, 298, 299);
         // This is expected to be very close to the attribute
         assert_eq!(
-            fragment.sampled_depths,
-            [0.7958958, 0.79743755, 0.79727083, 0.79572916]
+            sampled_depths,
+            [0.7955209, 0.7968542, 0.7963125, 0.79764587]
         );
 
         // Sample in the middle
-        rast_tri.edge_functions.eval(200, 258);
-        let fragment = rast_tri.fragment();
+        let sampled_depths = depths_at(&rast_tri, 200, 258);
         assert_eq!(
-            fragment.sampled_depths,
-            [0.55322915, 0.5547708, 0.5546042, 0.55306244]
+            sampled_depths,
+            [0.5528542, 0.55418754, 0.55364585, 0.5549792]
         );
     }
 
-    fn verify_uvs_at(rast_tri: &mut RasterizerTriangle, x: usize, y: usize, expected: &[f32; 2]) {
-        rast_tri.edge_functions.eval(x, y);
-        let fragment = rast_tri.fragment();
-        let attrs = fragment.interpolate(x, y, rast_tri.edge_functions.coverage_mask);
+    fn verify_uvs_at(rast_tri: &RasterizerTriangle, x: usize, y: usize, expected: &[f32; 2]) {
+        let quad = rast_tri
+            .edge_functions
+            .eval_quad(x & !1, y & !1, SampleCount::X4, false);
+        let barycentrics = rast_tri.barycentrics(&quad, &quad.coverage);
+        let attrs = rast_tri.interpolate(&barycentrics, (x & 1) + 2 * (y & 1));
         assert_eq!(&attrs.uvs, expected);
     }
 
@@ -890,20 +1673,699 @@ mod test {
         // These are used for interpolation
         let depths = [5.0, 5.0, 5.0];
 
-        let vertex_attributes = [
+        let vertex_attributes: [VertexAttribute; 3] = [
             (Color::red(), [0.0, 0.0]).into(),
             (Color::red(), [0.0, 1.0]).into(),
             (Color::red(), [1.0, 1.0]).into(),
         ];
 
-        let mut rast_tri = RasterizerTriangle::new(vertices, depths, vertex_attributes);
+        let rast_tri = RasterizerTriangle::new(vertices, depths, vertex_attributes);
 
         // This is expected to be very close to the attribute
-        verify_uvs_at(&mut rast_tri, 100, 299, &[0.00020831265, 0.006041646]);
-        verify_uvs_at(&mut rast_tri, 200, 150, &[0.004791677, 0.99895835]);
-        verify_uvs_at(&mut rast_tri, 299, 299, &[0.99645835, 0.9972917]);
+        verify_uvs_at(&rast_tri, 100, 299, &[0.0022916752, 0.006458342]);
+        verify_uvs_at(&rast_tri, 200, 150, &[0.0027083158, 0.99854165]);
+        verify_uvs_at(&rast_tri, 299, 299, &[0.99395835, 0.9997917]);
 
         // Sample in the middle
-        verify_uvs_at(&mut rast_tri, 200, 258, &[0.3641667, 0.6408334]);
+        verify_uvs_at(&rast_tri, 200, 258, &[0.3641667, 0.6408334]);
+    }
+
+    #[test]
+    fn counter_clockwise_is_made_clockwise() {
+        let cw = setup_rasterizer_triangle();
+        assert_eq!(cw.winding, Winding::Clockwise);
+
+        let vertices = [
+            Point3D::<ScreenSpace>::new(100.0, 300.0, 0.5),
+            Point3D::<ScreenSpace>::new(300.0, 300.0, 0.5),
+            Point3D::<ScreenSpace>::new(200.0, 150.0, 0.5),
+        ];
+        let vertex_attributes: [VertexAttribute; 3] = [
+            (Color::red(), [0.0, 0.0]).into(),
+            (Color::green(), [0.0, 0.0]).into(),
+            (Color::blue(), [0.0, 0.0]).into(),
+        ];
+        let ccw = RasterizerTriangle::new(vertices, [5.0, 7.0, 6.0], vertex_attributes);
+        assert_eq!(ccw.winding, Winding::CounterClockwise);
+        assert_eq!(ccw.edge_functions.points, cw.edge_functions.points);
+        assert_eq!(ccw.depths_camera_space, cw.depths_camera_space);
+        assert_eq!(ccw.inv_2x_area, cw.inv_2x_area);
+        assert_eq!(ccw.attributes[1].color.to_argb(), Color::blue().to_argb());
+        assert!(!ccw.is_degenerate());
+
+        let line = RasterizerTriangle::new(
+            [vertices[0], vertices[1], vertices[1]],
+            [5.0, 6.0, 7.0],
+            vertex_attributes,
+        );
+        assert!(line.is_degenerate());
+    }
+
+    #[derive(Clone, Copy)]
+    struct Shading {
+        normal: Vec3<WorldSpace>,
+        intensity: f32,
+    }
+
+    impl std::ops::Mul<f32> for Shading {
+        type Output = Self;
+        fn mul(self, s: f32) -> Self {
+            Self {
+                normal: self.normal * s,
+                intensity: self.intensity * s,
+            }
+        }
+    }
+
+    impl std::ops::Add for Shading {
+        type Output = Self;
+        fn add(self, o: Self) -> Self {
+            Self {
+                normal: self.normal + o.normal,
+                intensity: self.intensity + o.intensity,
+            }
+        }
+    }
+
+    impl std::ops::Sub for Shading {
+        type Output = Self;
+        fn sub(self, o: Self) -> Self {
+            Self {
+                normal: self.normal - o.normal,
+                intensity: self.intensity - o.intensity,
+            }
+        }
+    }
+
+    #[test]
+    fn custom_varying() {
+        const WIDTH: usize = 16;
+        const HEIGHT: usize = 16;
+
+        let mut rasterizer = Rasterizer::new(WIDTH, HEIGHT);
+        let shading = Shading {
+            normal: vec3(0.0, 0.0, -1.0),
+            intensity: 0.5,
+        };
+        // Different w per vertex and the near plane is clipped
+        let triangle = Triangle {
+            vertices: [
+                Point4D::<ClipSpace>::new(-0.5, -0.5, -2.0, 1.0),
+                Point4D::<ClipSpace>::new(0.0, 1.0, 0.0, 2.0),
+                Point4D::<ClipSpace>::new(1.5, -1.5, 0.5, 3.0),
+            ],
+            vertex_attributes: [shading; 3],
+        };
+
+        let fragment_shader = |_: &Uniforms, _: &FragCoords<Shading>, s: &Shading| {
+            let expected =
+                (s.normal.z() + 1.0).abs() < 0.0001 && (s.intensity - 0.5).abs() < 0.0001;
+            if expected {
+                Color::red()
+            } else {
+                Color::blue()
+            }
+        };
+        rasterizer.rasterize(
+            &[triangle],
+            &PipelineState::default(),
+            &Uniforms::new(),
+            fragment_shader,
+        );
+        let frame = rasterizer.framebuffer();
+        assert_eq!(
+            frame[HEIGHT / 2 * WIDTH + WIDTH / 2],
+            Color::red().to_argb()
+        );
+        assert!(!frame.contains(&Color::blue().to_argb()));
+    }
+
+    #[test]
+    fn derivatives() {
+        const WIDTH: usize = 16;
+        const HEIGHT: usize = 16;
+
+        let mut rasterizer = Rasterizer::new(WIDTH, HEIGHT);
+        // Same w everywhere, the uvs are affine in screen space and span the viewport
+        let vertex = |x: f32, y: f32| {
+            let attr = VertexAttribute::from((Color::white(), [(x + 1.0) / 2.0, (y + 1.0) / 2.0]));
+            (Point4D::<ClipSpace>::new(x, y, 0.5, 1.0), attr)
+        };
+        let [(v0, a0), (v1, a1), (v2, a2)] =
+            [vertex(-1.0, -1.0), vertex(-1.0, 1.0), vertex(1.0, -1.0)];
+        let triangle = Triangle {
+            vertices: [v0, v1, v2],
+            vertex_attributes: [a0, a1, a2],
+        };
+
+        let fragment_shader = |_: &Uniforms, fc: &FragCoords, _: &VertexAttribute| {
+            let (dx, dy) = (fc.dfdx().uvs, fc.dfdy().uvs);
+            let pixel = 1.0 / WIDTH as f32;
+            let expected = (dx[0] - pixel).abs() < 0.0001
+                && dx[1].abs() < 0.0001
+                && dy[0].abs() < 0.0001
+                && (dy[1].abs() - pixel).abs() < 0.0001;
+            if expected {
+                Color::red()
+            } else {
+                Color::blue()
+            }
+        };
+        rasterizer.rasterize(
+            &[triangle],
+            &PipelineState::default(),
+            &Uniforms::new(),
+            fragment_shader,
+        );
+        let frame = rasterizer.framebuffer();
+        assert!(frame.contains(&Color::red().to_argb()));
+        assert!(!frame.contains(&Color::blue().to_argb()));
+    }
+
+    #[test]
+    fn threads_are_deterministic() {
+        const WIDTH: usize = 96;
+        const HEIGHT: usize = 160;
+
+        // Overlapping, blended triangles of different sizes spanning several tiles. The
+        // result depends on the order.
+        let triangles = (0..24)
+            .map(|i| {
+                let t = i as f32 * 0.37;
+                let (x, y, r) = (
+                    t.sin() * 0.5,
+                    (t * 1.3).cos() * 0.5,
+                    0.1 + (i % 7) as f32 * 0.06,
+                );
+                let color = Color {
+                    r: (i % 3) as f32 * 0.5,
+                    g: (i % 5) as f32 * 0.25,
+                    b: (i % 2) as f32,
+                    a: 0.5,
+                };
+                Triangle {
+                    vertices: [
+                        Point4D::<ClipSpace>::new(x - r, y - r, 0.0, 1.0),
+                        Point4D::<ClipSpace>::new(x, y + r, 0.0, 1.0),
+                        Point4D::<ClipSpace>::new(x + r, y - r, 0.0, 1.0),
+                    ],
+                    vertex_attributes: [(color, [0.0, 0.0]).into(); 3],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let state = PipelineState {
+            blend: Some(BlendState::alpha()),
+            ..Default::default()
+        };
+        let render = |n_threads: usize| {
+            let mut rasterizer = Rasterizer::new(WIDTH, HEIGHT);
+            rasterizer.set_thread_count(n_threads);
+            let fragment_shader = |_: &Uniforms, _: &FragCoords, attr: &VertexAttribute| attr.color;
+            rasterizer.rasterize(&triangles, &state, &Uniforms::new(), fragment_shader);
+            rasterizer.framebuffer().to_vec()
+        };
+
+        let single = render(1);
+        assert!(single.iter().any(|&p| p != buffers::CLEAR_COLOR));
+        assert_eq!(single, render(8));
+    }
+
+    // Returns the resolved color at the center of a clockwise or counter-clockwise triangle.
+    // Front-facing fragments are red and back-facing blue.
+    fn render_winding(state: &PipelineState, winding: Winding) -> u32 {
+        const WIDTH: usize = 16;
+        const HEIGHT: usize = 16;
+
+        let mut rasterizer = Rasterizer::new(WIDTH, HEIGHT);
+        let mut vertices = [
+            Point4D::<ClipSpace>::new(-0.5, -0.5, 0.0, 1.0),
+            Point4D::<ClipSpace>::new(0.0, 0.5, 0.0, 1.0),
+            Point4D::<ClipSpace>::new(0.5, -0.5, 0.0, 1.0),
+        ];
+        if winding == Winding::CounterClockwise {
+            vertices.swap(1, 2);
+        }
+        let triangle = Triangle {
+            vertices,
+            vertex_attributes: [VertexAttribute::default(); 3],
+        };
+
+        let fragment_shader = |_: &Uniforms, fc: &FragCoords, _: &VertexAttribute| {
+            if fc.front_facing {
+                Color::red()
+            } else {
+                Color::blue()
+            }
+        };
+        rasterizer.rasterize(&[triangle], state, &Uniforms::new(), fragment_shader);
+        rasterizer.framebuffer()[HEIGHT / 2 * WIDTH + WIDTH / 2]
+    }
+
+    #[test]
+    fn culling() {
+        let front = Color::red().to_argb();
+        let back = Color::blue().to_argb();
+        let culled = buffers::CLEAR_COLOR;
+
+        let mut state = PipelineState::default();
+        assert_eq!(render_winding(&state, Winding::Clockwise), front);
+        assert_eq!(render_winding(&state, Winding::CounterClockwise), culled);
+
+        state.cull_mode = CullMode::None;
+        assert_eq!(render_winding(&state, Winding::Clockwise), front);
+        assert_eq!(render_winding(&state, Winding::CounterClockwise), back);
+
+        state.cull_mode = CullMode::Front;
+        assert_eq!(render_winding(&state, Winding::Clockwise), culled);
+        assert_eq!(render_winding(&state, Winding::CounterClockwise), back);
+
+        state.cull_mode = CullMode::Back;
+        state.front_face = Winding::CounterClockwise;
+        assert_eq!(render_winding(&state, Winding::Clockwise), culled);
+        assert_eq!(render_winding(&state, Winding::CounterClockwise), front);
+    }
+
+    // A clockwise triangle covering the center of the 16x16 rasterizer, shaded with the vertex
+    // color. Lower z is closer.
+    fn draw_triangle(rasterizer: &mut Rasterizer, state: &PipelineState, z: f32, color: Color) {
+        let triangle = Triangle {
+            vertices: [
+                Point4D::<ClipSpace>::new(-0.5, -0.5, z, 1.0),
+                Point4D::<ClipSpace>::new(0.0, 0.5, z, 1.0),
+                Point4D::<ClipSpace>::new(0.5, -0.5, z, 1.0),
+            ],
+            vertex_attributes: [(color, [0.0, 0.0]).into(); 3],
+        };
+        let fragment_shader = |_: &Uniforms, _: &FragCoords, attr: &VertexAttribute| attr.color;
+        rasterizer.rasterize(&[triangle], state, &Uniforms::new(), fragment_shader);
+    }
+
+    #[test]
+    fn blending() {
+        let mut rasterizer = Rasterizer::new(16, 16);
+        let opaque = PipelineState::default();
+        let mut blended = PipelineState::default();
+        let half_blue = Color {
+            r: 0.0,
+            g: 0.0,
+            b: 1.0,
+            a: 0.5,
+        };
+
+        draw_triangle(&mut rasterizer, &opaque, 0.0, Color::red());
+        blended.blend = Some(BlendState::alpha());
+        draw_triangle(&mut rasterizer, &blended, -0.5, half_blue);
+        // Blended in linear space, the presented frame is sRGB encoded
+        assert_eq!(rasterizer.framebuffer()[8 * 16 + 8], 0xFFBC00BC);
+
+        draw_triangle(&mut rasterizer, &opaque, 0.0, Color::red());
+        blended.blend = Some(BlendState::additive());
+        draw_triangle(&mut rasterizer, &blended, -0.5, half_blue);
+        assert_eq!(rasterizer.framebuffer()[8 * 16 + 8], 0xFFFF00BC);
+
+        // Blends against the clear color, the left-most pixels are not covered
+        draw_triangle(&mut rasterizer, &blended, 0.0, half_blue);
+        let frame = rasterizer.framebuffer();
+        assert_eq!(frame[8 * 16 + 8], 0xFF1919BD);
+        assert_eq!(frame[8 * 16], buffers::CLEAR_COLOR);
+    }
+
+    #[test]
+    fn depth_state() {
+        let mut rasterizer = Rasterizer::new(16, 16);
+        let center = 8 * 16 + 8;
+        let red = Color::red().to_argb();
+        let blue = Color::blue().to_argb();
+        let green = Color::green().to_argb();
+
+        // Default is less with writes
+        let mut state = PipelineState::default();
+        draw_triangle(&mut rasterizer, &state, 0.0, Color::red());
+        draw_triangle(&mut rasterizer, &state, 0.5, Color::blue());
+        assert_eq!(rasterizer.framebuffer()[center], red);
+
+        state.depth.compare = CompareFunc::Always;
+        draw_triangle(&mut rasterizer, &state, 0.0, Color::red());
+        draw_triangle(&mut rasterizer, &state, 0.5, Color::blue());
+        assert_eq!(rasterizer.framebuffer()[center], blue);
+
+        // Decals, nothing is equal to the cleared depth buffer
+        state.depth.compare = CompareFunc::Equal;
+        draw_triangle(&mut rasterizer, &state, 0.0, Color::red());
+        assert_eq!(rasterizer.framebuffer()[center], buffers::CLEAR_COLOR);
+        state.depth.compare = CompareFunc::Always;
+        draw_triangle(&mut rasterizer, &state, 0.0, Color::red());
+        state.depth.compare = CompareFunc::Equal;
+        draw_triangle(&mut rasterizer, &state, 0.0, Color::blue());
+        assert_eq!(rasterizer.framebuffer()[center], blue);
+
+        // Without depth writes, the green triangle is only compared to the red one
+        state.depth.compare = CompareFunc::Less;
+        draw_triangle(&mut rasterizer, &state, 0.0, Color::red());
+        state.depth.write_enable = false;
+        draw_triangle(&mut rasterizer, &state, -0.5, Color::blue());
+        state.depth.write_enable = true;
+        draw_triangle(&mut rasterizer, &state, -0.25, Color::green());
+        assert_eq!(rasterizer.framebuffer()[center], green);
+
+        // No test => no writes either
+        draw_triangle(&mut rasterizer, &state, 0.0, Color::red());
+        state.depth.test_enable = false;
+        draw_triangle(&mut rasterizer, &state, -0.5, Color::blue());
+        state.depth.test_enable = true;
+        draw_triangle(&mut rasterizer, &state, -0.25, Color::green());
+        assert_eq!(rasterizer.framebuffer()[center], green);
+
+        state.depth.compare = CompareFunc::Never;
+        draw_triangle(&mut rasterizer, &state, 0.0, Color::red());
+        assert_eq!(rasterizer.framebuffer()[center], buffers::CLEAR_COLOR);
+    }
+
+    #[test]
+    fn multiple_render_targets() {
+        const WIDTH: usize = 24;
+        const HEIGHT: usize = 12;
+
+        let rasterizer = Rasterizer::new(16, 16);
+        let formats = [ColorFormat::Rgba8, ColorFormat::R32F, ColorFormat::Rgba8];
+        let mut framebuffer = Framebuffer::with_attachments(WIDTH, HEIGHT, &formats, false);
+        let triangle = |z: f32, color: Color| Triangle {
+            vertices: [
+                Point4D::<ClipSpace>::new(-1.0, -1.0, z, 1.0),
+                Point4D::<ClipSpace>::new(-1.0, 1.0, z, 1.0),
+                Point4D::<ClipSpace>::new(1.0, -1.0, z, 1.0),
+            ],
+            vertex_attributes: [(color, [0.0, 0.0]).into(); 3],
+        };
+        // Two outputs for three attachments, the depth goes to the float attachment
+        let fragment_shader = |_: &Uniforms, fc: &FragCoords, attr: &VertexAttribute| {
+            [attr.color, Color::grayscale(fc.depths[0] * 10.0)]
+        };
+
+        // Without a depth attachment the later triangle wins even though it is further away
+        let state = PipelineState::default();
+        for (z, color) in [(0.0, Color::red()), (0.5, Color::blue())] {
+            rasterizer.rasterize_to(
+                &mut framebuffer,
+                &[triangle(z, color)],
+                &state,
+                &Uniforms::new(),
+                fragment_shader,
+            );
+        }
+
+        // Upper left, covered by the triangle
+        assert_eq!(
+            framebuffer.read_color(0, 2, 2).to_argb(),
+            Color::blue().to_argb()
+        );
+        assert_eq!(framebuffer.read_color(1, 2, 2).r, 7.5);
+        let clear = ColorFormat::Rgba8.clear_color().to_argb();
+        assert_eq!(framebuffer.read_color(2, 2, 2).to_argb(), clear);
+        assert_eq!(framebuffer.read_color(1, WIDTH - 1, HEIGHT - 1).r, 0.0);
+
+        framebuffer.clear();
+        assert_eq!(framebuffer.read_color(1, 2, 2).r, 0.0);
+        assert_eq!(framebuffer.read_color(0, 2, 2).to_argb(), clear);
+    }
+
+    #[test]
+    fn float_formats() {
+        let mut rasterizer = Rasterizer::new(16, 16);
+        let formats = [
+            ColorFormat::Rgba16F,
+            ColorFormat::Rgba32F,
+            ColorFormat::Rgba8,
+        ];
+        let mut framebuffer = Framebuffer::with_attachments(4, 4, &formats, false);
+        let hdr = Color {
+            r: 4.0,
+            g: 0.1,
+            b: -1.0,
+            a: 1.0,
+        };
+        // Covers everything
+        let fill = |color: Color| {
+            [Triangle {
+                vertices: [
+                    Point4D::<ClipSpace>::new(-1.0, -1.0, 0.0, 1.0),
+                    Point4D::<ClipSpace>::new(-1.0, 3.0, 0.0, 1.0),
+                    Point4D::<ClipSpace>::new(3.0, -1.0, 0.0, 1.0),
+                ],
+                vertex_attributes: [(color, [0.0, 0.0]).into(); 3],
+            }]
+        };
+        let fragment_shader =
+            |_: &Uniforms, _: &FragCoords, attr: &VertexAttribute| [attr.color; 3];
+        let state = PipelineState::default();
+        rasterizer.rasterize_to(
+            &mut framebuffer,
+            &fill(hdr),
+            &state,
+            &Uniforms::new(),
+            fragment_shader,
+        );
+
+        // Out of range values are kept by the float formats, half floats round the rest
+        let half = framebuffer.read_color(0, 1, 1);
+        assert_eq!((half.r, half.b), (4.0, -1.0));
+        assert!((half.g - 0.1).abs() < 1e-4 && half.g != 0.1);
+        assert_eq!(framebuffer.read_color(1, 1, 1), hdr);
+        // Rgba8 clamps and stores the color channels sRGB-encoded
+        let srgb = framebuffer.read_color(2, 1, 1).encode_srgb();
+        assert_eq!(srgb.to_argb(), 0xFFFF5900);
+
+        // Additive blending accumulates above 1.0 before the window framebuffer is presented
+        let blended = PipelineState {
+            blend: Some(BlendState::additive()),
+            depth: DepthState {
+                test_enable: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        rasterizer.rasterize(&fill(hdr), &state, &Uniforms::new(), fragment_shader);
+        for _ in 0..4 {
+            rasterizer.rasterize(
+                &fill(Color::grayscale(0.25)),
+                &blended,
+                &Uniforms::new(),
+                fragment_shader,
+            );
+        }
+        let texture = rasterizer.framebuffer.resolve_color(0);
+        assert_eq!(texture.read_texel(8, 8).r, 5.0);
+        assert_eq!(rasterizer.framebuffer()[8 * 16 + 8], 0xFFFFFF00);
+    }
+
+    #[test]
+    fn render_to_texture() {
+        const WIDTH: usize = 8;
+        const HEIGHT: usize = 4;
+
+        let rasterizer = Rasterizer::new(16, 16);
+        let formats = [ColorFormat::Rgba8, ColorFormat::R32F];
+        let mut framebuffer = Framebuffer::with_attachments(WIDTH, HEIGHT, &formats, true);
+        // Left half of the framebuffer
+        let triangle = Triangle {
+            vertices: [
+                Point4D::<ClipSpace>::new(-2.0, -1.0, 0.0, 1.0),
+                Point4D::<ClipSpace>::new(0.0, 3.0, 0.0, 1.0),
+                Point4D::<ClipSpace>::new(0.0, -1.0, 0.0, 1.0),
+            ],
+            vertex_attributes: [(Color::red(), [0.0, 0.0]).into(); 3],
+        };
+        let fragment_shader = |_: &Uniforms, _: &FragCoords, attr: &VertexAttribute| {
+            [attr.color, Color::grayscale(2.0)]
+        };
+        rasterizer.rasterize_to(
+            &mut framebuffer,
+            &[triangle],
+            &PipelineState::default(),
+            &Uniforms::new(),
+            fragment_shader,
+        );
+
+        let color = framebuffer.resolve_color(0);
+        assert_eq!((color.width(), color.height()), (WIDTH, HEIGHT));
+        assert_eq!(color.read_texel(1, 1).to_argb(), Color::red().to_argb());
+        let clear = ColorFormat::Rgba8.clear_color().to_argb();
+        assert_eq!(color.read_texel(6, 1).to_argb(), clear);
+        let float = framebuffer.resolve_color(1);
+        assert_eq!(float.read_texel(1, 1).r, 2.0);
+        assert_eq!(float.read_texel(6, 1).r, 0.0);
+        let depth = framebuffer.resolve_depth();
+        assert_eq!(depth.read_texel(1, 1).r, 0.5);
+        assert_eq!(depth.read_texel(6, 1).r, 1.0);
+
+        // Directly, the edge is between pixel 3 and 4
+        let sampler = Sampler {
+            filter: FilterMode::Bilinear,
+            wrap_u: WrapMode::ClampToEdge,
+            wrap_v: WrapMode::ClampToEdge,
+            ..Default::default()
+        };
+        assert_eq!(framebuffer.sample_color(1, 0.25, 0.5, &sampler).r, 2.0);
+        assert_eq!(framebuffer.sample_color(1, 0.5, 0.5, &sampler).r, 1.0);
+        assert_eq!(framebuffer.sample_depth(0.5, 0.5, &sampler), 0.75);
+        let nearest = Sampler {
+            filter: FilterMode::Nearest,
+            ..sampler
+        };
+        assert_eq!(framebuffer.sample_depth(0.49, 0.5, &nearest), 0.5);
+    }
+
+    #[test]
+    fn sample_counts() {
+        const SIZE: usize = 16;
+
+        let rasterizer = Rasterizer::new(16, 16);
+        let mut framebuffer = Framebuffer::with_attachments(SIZE, SIZE, &[ColorFormat::R32F], true);
+        assert_eq!(framebuffer.sample_count(), SampleCount::X4);
+        let triangle = |corner: f32, z: f32, value: f32| Triangle {
+            vertices: [
+                Point4D::<ClipSpace>::new(-1.0, -1.0, z, 1.0),
+                Point4D::<ClipSpace>::new(-1.0, corner, z, 1.0),
+                Point4D::<ClipSpace>::new(corner, -1.0, z, 1.0),
+            ],
+            vertex_attributes: [(Color::grayscale(value), [0.0, 0.0]).into(); 3],
+        };
+        // The lower left half in front, everything else behind it
+        let triangles = [triangle(1.0, 0.0, 1.0), triangle(3.0, 0.5, 2.0)];
+        let fragment_shader = |_: &Uniforms, _: &FragCoords, attr: &VertexAttribute| [attr.color];
+
+        // Samples of the pixels on the diagonal in front of it
+        for (samples, in_front) in [
+            (SampleCount::X1, 0),
+            (SampleCount::X2, 0),
+            (SampleCount::X4, 2),
+            (SampleCount::X8, 4),
+            (SampleCount::X16, 7),
+        ] {
+            framebuffer.set_sample_count(samples);
+            assert_eq!(framebuffer.sample_count(), samples);
+            rasterizer.rasterize_to(
+                &mut framebuffer,
+                &triangles,
+                &PipelineState::default(),
+                &Uniforms::new(),
+                fragment_shader,
+            );
+
+            let n = samples.count() as f32;
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let expected = match x.cmp(&y) {
+                        Ordering::Less => 1.0,
+                        Ordering::Equal => 2.0 - in_front as f32 / n,
+                        Ordering::Greater => 2.0,
+                    };
+                    let value = framebuffer.read_color(0, x, y).r;
+                    assert_eq!(value, expected, "{:?} at ({}, {})", samples, x, y);
+                }
+            }
+        }
+    }
+
+    // Covers the whole 16x16 rasterizer
+    fn draw_fullscreen(rasterizer: &mut Rasterizer, state: &PipelineState, color: Color) {
+        let triangle = Triangle {
+            vertices: [
+                Point4D::<ClipSpace>::new(-1.0, -1.0, 0.0, 1.0),
+                Point4D::<ClipSpace>::new(-1.0, 3.0, 0.0, 1.0),
+                Point4D::<ClipSpace>::new(3.0, -1.0, 0.0, 1.0),
+            ],
+            vertex_attributes: [(color, [0.0, 0.0]).into(); 3],
+        };
+        let fragment_shader = |_: &Uniforms, _: &FragCoords, attr: &VertexAttribute| attr.color;
+        rasterizer.rasterize(&[triangle], state, &Uniforms::new(), fragment_shader);
+    }
+
+    #[test]
+    fn stencil() {
+        let mut rasterizer = Rasterizer::new(16, 16);
+        let center = 8 * 16 + 8;
+        let corner = 0;
+        let blue = Color::blue().to_argb();
+
+        // Mark the triangle in the stencil buffer, then only draw inside of it
+        let mut mark = PipelineState::default();
+        mark.stencil.enable = true;
+        mark.stencil.reference = 1;
+        mark.stencil.front.pass = StencilOp::Replace;
+        let mut masked = PipelineState::default();
+        masked.depth.compare = CompareFunc::Always;
+        masked.stencil.enable = true;
+        masked.stencil.reference = 1;
+        masked.stencil.front.compare = CompareFunc::Equal;
+
+        draw_triangle(&mut rasterizer, &mark, 0.0, Color::red());
+        draw_fullscreen(&mut rasterizer, &masked, Color::blue());
+        let frame = rasterizer.framebuffer();
+        assert_eq!(frame[center], blue);
+        assert_eq!(frame[corner], buffers::CLEAR_COLOR);
+
+        // Inverted
+        masked.stencil.front.compare = CompareFunc::NotEqual;
+        draw_triangle(&mut rasterizer, &mark, 0.0, Color::red());
+        draw_fullscreen(&mut rasterizer, &masked, Color::blue());
+        let frame = rasterizer.framebuffer();
+        assert_eq!(frame[center], Color::red().to_argb());
+        assert_eq!(frame[corner], blue);
+
+        // Stencil only writes (the depth test always fails) are cleared as well
+        masked.stencil.front.compare = CompareFunc::Equal;
+        let mut depth_fail = mark;
+        depth_fail.depth.compare = CompareFunc::Never;
+        depth_fail.stencil.front.depth_fail = StencilOp::Replace;
+        draw_triangle(&mut rasterizer, &depth_fail, 0.0, Color::red());
+        draw_fullscreen(&mut rasterizer, &masked, Color::blue());
+        let frame = rasterizer.framebuffer();
+        assert_eq!(frame[center], blue);
+        assert_eq!(frame[corner], buffers::CLEAR_COLOR);
+
+        draw_triangle(&mut rasterizer, &depth_fail, 0.0, Color::red());
+        assert_eq!(rasterizer.framebuffer()[center], buffers::CLEAR_COLOR);
+        draw_fullscreen(&mut rasterizer, &masked, Color::blue());
+        assert_eq!(rasterizer.framebuffer()[center], buffers::CLEAR_COLOR);
+    }
+
+    #[test]
+    fn stencil_back_faces() {
+        // Shadow volume style counting, +1 for front faces and -1 for back faces
+        let mut rasterizer = Rasterizer::new(16, 16);
+        let mut count = PipelineState {
+            cull_mode: CullMode::None,
+            ..Default::default()
+        };
+        count.depth.write_enable = false;
+        count.stencil.enable = true;
+        count.stencil.front.pass = StencilOp::IncrementWrap;
+        count.stencil.back.pass = StencilOp::DecrementWrap;
+
+        let mut test = PipelineState::default();
+        test.depth.compare = CompareFunc::Always;
+        test.stencil.enable = true;
+        test.stencil.reference = 1;
+        test.stencil.front.compare = CompareFunc::Equal;
+
+        // Two front faces
+        draw_triangle(&mut rasterizer, &count, 0.0, Color::red());
+        draw_triangle(&mut rasterizer, &count, 0.0, Color::red());
+        draw_fullscreen(&mut rasterizer, &test, Color::blue());
+        assert_eq!(rasterizer.framebuffer()[8 * 16 + 8], Color::red().to_argb());
+
+        // Two front faces and one back face
+        draw_triangle(&mut rasterizer, &count, 0.0, Color::red());
+        draw_triangle(&mut rasterizer, &count, 0.0, Color::red());
+        let mut back = count;
+        back.front_face = Winding::CounterClockwise;
+        draw_triangle(&mut rasterizer, &back, 0.0, Color::red());
+        draw_fullscreen(&mut rasterizer, &test, Color::blue());
+        assert_eq!(
+            rasterizer.framebuffer()[8 * 16 + 8],
+            Color::blue().to_argb()
+        );
     }
 }
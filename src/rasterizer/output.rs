@@ -0,0 +1,118 @@
+// What happens to the linear color of the framebuffer when a frame is presented: exposure, tone
+// mapping of HDR values into [0, 1] and the encoding for the display.
+
+use crate::color::{self, Color};
+
+/// Maps linear HDR color into [0, 1], per channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMap {
+    /// Values above 1 are clipped
+    Clamp,
+    /// x / (1 + x)
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, with a toe and a soft shoulder
+    AcesFilmic,
+}
+
+impl ToneMap {
+    pub fn apply(self, x: f32) -> f32 {
+        let x = x.max(0.0);
+        match self {
+            ToneMap::Clamp => x.min(1.0),
+            ToneMap::Reinhard => x / (1.0 + x),
+            ToneMap::AcesFilmic => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputTransform {
+    /// In stops, the color is scaled by 2^exposure before tone mapping
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+    /// Encode to sRGB, as expected by displays and image files. Without it the linear values are
+    /// written.
+    pub srgb: bool,
+}
+
+impl Default for OutputTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            srgb: true,
+        }
+    }
+}
+
+impl OutputTransform {
+    /// The presented pixel, opaque with 8 bits per channel
+    pub fn apply(&self, color: Color) -> u32 {
+        let scale = self.exposure.exp2();
+        let channel = |v: f32| {
+            let v = self.tone_map.apply(v * scale);
+            if self.srgb {
+                color::linear_to_srgb8(v) as u32
+            } else {
+                (v * 255.0).round() as u32
+            }
+        };
+        0xFF << 24 | channel(color.r) << 16 | channel(color.g) << 8 | channel(color.b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_maps() {
+        for tone_map in [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::AcesFilmic] {
+            assert_eq!(tone_map.apply(0.0), 0.0);
+            assert_eq!(tone_map.apply(-1.0), 0.0);
+            let mut prev = 0.0;
+            for x in [0.01, 0.1, 0.5, 1.0, 2.0, 10.0, 1000.0] {
+                let y = tone_map.apply(x);
+                assert!(y >= prev && y <= 1.0, "{:?}({}) = {}", tone_map, x, y);
+                prev = y;
+            }
+        }
+        assert_eq!(ToneMap::Clamp.apply(4.0), 1.0);
+        assert_eq!(ToneMap::Reinhard.apply(1.0), 0.5);
+        assert_eq!(ToneMap::Reinhard.apply(3.0), 0.75);
+        // Compresses the highlights instead of clipping them
+        assert!(ToneMap::AcesFilmic.apply(1.0) < 0.81);
+        assert!(ToneMap::AcesFilmic.apply(4.0) < 1.0);
+    }
+
+    #[test]
+    fn output_transform() {
+        let linear = OutputTransform {
+            srgb: false,
+            ..Default::default()
+        };
+        let hdr = Color {
+            r: 4.0,
+            g: 0.5,
+            b: -1.0,
+            a: 0.0,
+        };
+        assert_eq!(linear.apply(hdr), 0xFFFF8000);
+        // Mid gray in linear is brighter in sRGB
+        assert_eq!(OutputTransform::default().apply(hdr), 0xFFFFBC00);
+
+        let exposed = OutputTransform {
+            exposure: -1.0,
+            ..linear
+        };
+        assert_eq!(exposed.apply(hdr), 0xFFFF4000);
+        let reinhard = OutputTransform {
+            tone_map: ToneMap::Reinhard,
+            ..linear
+        };
+        assert_eq!(reinhard.apply(hdr), 0xFFCC5500);
+    }
+}
@@ -0,0 +1,197 @@
+// Worker threads that live as long as the rasterizer, so that a draw doesn't pay for spawning
+// them. A job runs on all workers and the calling thread at once, the threads share the work
+// through the job itself (e.g. a queue of tiles).
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+type Job = &'static (dyn Fn() + Sync);
+
+struct State {
+    job: Option<Job>,
+    // Incremented for every job, for the workers to tell a new one apart from the last one
+    generation: u64,
+    // Workers that haven't finished the current job yet
+    running: usize,
+    panicked: bool,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    start: Condvar,
+    done: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // Jobs run outside of the lock, it can't be poisoned by them
+        self.state.lock().unwrap()
+    }
+}
+
+pub(super) struct WorkerPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+    // Only one job at a time, `run` can be called from several threads through `&self`
+    running: Mutex<()>,
+}
+
+impl WorkerPool {
+    // `n_threads` includes the thread calling `run`, i.e. `n_threads - 1` workers are spawned
+    pub fn new(n_threads: usize) -> Self {
+        assert!(n_threads > 0);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                job: None,
+                generation: 0,
+                running: 0,
+                panicked: false,
+                shutdown: false,
+            }),
+            start: Condvar::new(),
+            done: Condvar::new(),
+        });
+        let workers = (1..n_threads)
+            .map(|i| {
+                let shared = Arc::clone(&shared);
+                std::thread::Builder::new()
+                    .name(format!("rasterizer-{}", i))
+                    .spawn(move || work(&shared))
+                    .expect("Failed to spawn a rasterizer thread")
+            })
+            .collect();
+
+        Self {
+            shared,
+            workers,
+            running: Mutex::new(()),
+        }
+    }
+
+    pub fn n_threads(&self) -> usize {
+        self.workers.len() + 1
+    }
+
+    // Runs `job` on every thread of the pool and returns once all of them are done with it.
+    // Panics if the job panicked on any of them.
+    pub fn run(&self, job: &(dyn Fn() + Sync)) {
+        if self.workers.is_empty() {
+            job();
+            return;
+        }
+
+        let _running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        // SAFETY: The workers only use the job until they have decremented `running`, and
+        // `Wait` doesn't return (nor unwind) before all of them have, so the job outlives every
+        // use of it.
+        let job: Job = unsafe { std::mem::transmute::<&(dyn Fn() + Sync), Job>(job) };
+        {
+            let mut state = self.shared.lock();
+            state.job = Some(job);
+            state.generation += 1;
+            state.running = self.workers.len();
+            state.panicked = false;
+        }
+        self.shared.start.notify_all();
+
+        let wait = Wait(&self.shared);
+        job();
+        drop(wait);
+
+        if self.shared.lock().panicked {
+            panic!("A rasterizer thread panicked");
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.start.notify_all();
+        for worker in self.workers.drain(..) {
+            // Panics of the jobs are caught, the workers themselves don't panic
+            let _ = worker.join();
+        }
+    }
+}
+
+// Waits for the workers to finish the current job when dropped, also when the job panicked on
+// the calling thread.
+struct Wait<'a>(&'a Shared);
+
+impl Drop for Wait<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        while state.running > 0 {
+            state = self.0.done.wait(state).unwrap();
+        }
+        state.job = None;
+    }
+}
+
+fn work(shared: &Shared) {
+    let mut generation = 0;
+    loop {
+        let job = {
+            let mut state = shared.lock();
+            while state.generation == generation && !state.shutdown {
+                state = shared.start.wait(state).unwrap();
+            }
+            if state.shutdown {
+                return;
+            }
+            generation = state.generation;
+            state.job.expect("A new generation without a job")
+        };
+
+        let panicked = catch_unwind(AssertUnwindSafe(job)).is_err();
+
+        let mut state = shared.lock();
+        state.panicked |= panicked;
+        state.running -= 1;
+        if state.running == 0 {
+            shared.done.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn runs_on_all_threads() {
+        let pool = WorkerPool::new(4);
+        for _ in 0..10 {
+            let calls = AtomicUsize::new(0);
+            pool.run(&|| {
+                calls.fetch_add(1, Ordering::Relaxed);
+            });
+            assert_eq!(calls.load(Ordering::Relaxed), 4);
+        }
+    }
+
+    #[test]
+    fn worker_panic() {
+        let pool = WorkerPool::new(2);
+        let main_thread = std::thread::current().id();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            pool.run(&|| {
+                if std::thread::current().id() != main_thread {
+                    panic!("Worker");
+                }
+            })
+        }));
+        assert!(result.is_err());
+
+        // Still usable afterwards
+        let calls = AtomicUsize::new(0);
+        pool.run(&|| {
+            calls.fetch_add(1, Ordering::Relaxed);
+        });
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}
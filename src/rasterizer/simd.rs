@@ -0,0 +1,340 @@
+// Four f32 lanes, used to process the pixels of a 2x2 quad or the samples of a pixel at once.
+// SSE on x86_64 (where it is always available), a plain array elsewhere. Comparisons return a
+// bitmask with bit i set if the comparison is true for lane i. The scalar implementation is built
+// everywhere to be tested against SSE.
+
+use core::ops::{Add, Div, Mul, Sub};
+
+#[cfg(target_arch = "x86_64")]
+mod sse {
+    use std::arch::x86_64::*;
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct F32x4(__m128);
+
+    // SAFETY (all unsafe blocks): SSE is part of the x86_64 baseline.
+    impl F32x4 {
+        #[inline]
+        pub fn splat(v: f32) -> Self {
+            Self(unsafe { _mm_set1_ps(v) })
+        }
+
+        #[inline]
+        pub fn from_array(v: [f32; 4]) -> Self {
+            Self(unsafe { _mm_loadu_ps(v.as_ptr()) })
+        }
+
+        #[inline]
+        pub fn to_array(self) -> [f32; 4] {
+            let mut out = [0.0; 4];
+            unsafe { _mm_storeu_ps(out.as_mut_ptr(), self.0) };
+            out
+        }
+
+        #[inline]
+        pub fn add(self, o: Self) -> Self {
+            Self(unsafe { _mm_add_ps(self.0, o.0) })
+        }
+
+        #[inline]
+        pub fn sub(self, o: Self) -> Self {
+            Self(unsafe { _mm_sub_ps(self.0, o.0) })
+        }
+
+        #[inline]
+        pub fn mul(self, o: Self) -> Self {
+            Self(unsafe { _mm_mul_ps(self.0, o.0) })
+        }
+
+        #[inline]
+        pub fn div(self, o: Self) -> Self {
+            Self(unsafe { _mm_div_ps(self.0, o.0) })
+        }
+
+        #[inline]
+        pub fn min(self, o: Self) -> Self {
+            Self(unsafe { _mm_min_ps(self.0, o.0) })
+        }
+
+        #[inline]
+        pub fn max(self, o: Self) -> Self {
+            Self(unsafe { _mm_max_ps(self.0, o.0) })
+        }
+
+        #[inline]
+        pub fn lt(self, o: Self) -> u8 {
+            unsafe { _mm_movemask_ps(_mm_cmplt_ps(self.0, o.0)) as u8 }
+        }
+
+        #[inline]
+        pub fn le(self, o: Self) -> u8 {
+            unsafe { _mm_movemask_ps(_mm_cmple_ps(self.0, o.0)) as u8 }
+        }
+
+        #[inline]
+        pub fn eq(self, o: Self) -> u8 {
+            unsafe { _mm_movemask_ps(_mm_cmpeq_ps(self.0, o.0)) as u8 }
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "x86_64", allow(dead_code))]
+mod scalar {
+    #[derive(Clone, Copy, Debug)]
+    pub struct F32x4([f32; 4]);
+
+    impl F32x4 {
+        #[inline]
+        pub fn splat(v: f32) -> Self {
+            Self([v; 4])
+        }
+
+        #[inline]
+        pub fn from_array(v: [f32; 4]) -> Self {
+            Self(v)
+        }
+
+        #[inline]
+        pub fn to_array(self) -> [f32; 4] {
+            self.0
+        }
+
+        #[inline]
+        fn map(self, o: Self, f: impl Fn(f32, f32) -> f32) -> Self {
+            Self(std::array::from_fn(|i| f(self.0[i], o.0[i])))
+        }
+
+        #[inline]
+        fn mask(self, o: Self, f: impl Fn(f32, f32) -> bool) -> u8 {
+            (0..4).fold(0, |m, i| m | (f(self.0[i], o.0[i]) as u8) << i)
+        }
+
+        #[inline]
+        pub fn add(self, o: Self) -> Self {
+            self.map(o, |a, b| a + b)
+        }
+
+        #[inline]
+        pub fn sub(self, o: Self) -> Self {
+            self.map(o, |a, b| a - b)
+        }
+
+        #[inline]
+        pub fn mul(self, o: Self) -> Self {
+            self.map(o, |a, b| a * b)
+        }
+
+        #[inline]
+        pub fn div(self, o: Self) -> Self {
+            self.map(o, |a, b| a / b)
+        }
+
+        // Same as SSE: the second operand if either is NaN
+        #[inline]
+        pub fn min(self, o: Self) -> Self {
+            self.map(o, |a, b| if a < b { a } else { b })
+        }
+
+        #[inline]
+        pub fn max(self, o: Self) -> Self {
+            self.map(o, |a, b| if a > b { a } else { b })
+        }
+
+        #[inline]
+        pub fn lt(self, o: Self) -> u8 {
+            self.mask(o, |a, b| a < b)
+        }
+
+        #[inline]
+        pub fn le(self, o: Self) -> u8 {
+            self.mask(o, |a, b| a <= b)
+        }
+
+        #[inline]
+        pub fn eq(self, o: Self) -> u8 {
+            self.mask(o, |a, b| a == b)
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub use scalar::F32x4;
+#[cfg(target_arch = "x86_64")]
+pub use sse::F32x4;
+
+pub const ALL_LANES: u8 = 0b1111;
+
+impl F32x4 {
+    #[inline]
+    pub fn new(a: f32, b: f32, c: f32, d: f32) -> Self {
+        Self::from_array([a, b, c, d])
+    }
+
+    #[inline]
+    pub fn gt(self, o: Self) -> u8 {
+        o.lt(self)
+    }
+
+    #[inline]
+    pub fn ge(self, o: Self) -> u8 {
+        o.le(self)
+    }
+
+    #[inline]
+    pub fn ne(self, o: Self) -> u8 {
+        !self.eq(o) & ALL_LANES
+    }
+
+    #[inline]
+    pub fn clamp(self, min: f32, max: f32) -> Self {
+        self.max(Self::splat(min)).min(Self::splat(max))
+    }
+}
+
+impl Add for F32x4 {
+    type Output = Self;
+    #[inline]
+    fn add(self, o: Self) -> Self {
+        F32x4::add(self, o)
+    }
+}
+
+impl Sub for F32x4 {
+    type Output = Self;
+    #[inline]
+    fn sub(self, o: Self) -> Self {
+        F32x4::sub(self, o)
+    }
+}
+
+impl Mul for F32x4 {
+    type Output = Self;
+    #[inline]
+    fn mul(self, o: Self) -> Self {
+        F32x4::mul(self, o)
+    }
+}
+
+impl Mul<f32> for F32x4 {
+    type Output = Self;
+    #[inline]
+    fn mul(self, o: f32) -> Self {
+        F32x4::mul(self, F32x4::splat(o))
+    }
+}
+
+impl Div for F32x4 {
+    type Output = Self;
+    #[inline]
+    fn div(self, o: Self) -> Self {
+        F32x4::div(self, o)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let a = F32x4::new(1.0, 2.0, 3.0, 4.0);
+        let b = F32x4::new(4.0, 3.0, 2.0, 1.0);
+        assert_eq!((a + b).to_array(), [5.0; 4]);
+        assert_eq!((a - b).to_array(), [-3.0, -1.0, 1.0, 3.0]);
+        assert_eq!((a * b).to_array(), [4.0, 6.0, 6.0, 4.0]);
+        assert_eq!((a * 2.0).to_array(), [2.0, 4.0, 6.0, 8.0]);
+        assert_eq!((a / b).to_array(), [0.25, 2.0 / 3.0, 1.5, 4.0]);
+        assert_eq!(a.min(b).to_array(), [1.0, 2.0, 2.0, 1.0]);
+        assert_eq!(a.max(b).to_array(), [4.0, 3.0, 3.0, 4.0]);
+        assert_eq!(a.clamp(1.5, 3.5).to_array(), [1.5, 2.0, 3.0, 3.5]);
+    }
+
+    #[test]
+    fn comparisons() {
+        let a = F32x4::new(1.0, 2.0, 3.0, 4.0);
+        let b = F32x4::new(4.0, 2.0, 2.0, 1.0);
+        assert_eq!(a.lt(b), 0b0001);
+        assert_eq!(a.le(b), 0b0011);
+        assert_eq!(a.eq(b), 0b0010);
+        assert_eq!(a.ne(b), 0b1101);
+        assert_eq!(a.gt(b), 0b1100);
+        assert_eq!(a.ge(b), 0b1110);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn scalar_matches_sse() {
+        let values = [
+            0.0,
+            -0.0,
+            1.0,
+            -2.5,
+            1e-40,
+            3.4e38,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+        ];
+        // The same result or both NaN
+        let same = |a: [f32; 4], b: [f32; 4]| {
+            (0..4).all(|i| a[i].to_bits() == b[i].to_bits() || (a[i].is_nan() && b[i].is_nan()))
+        };
+
+        // Every pair of values in every lane
+        for (i, &a) in values.iter().enumerate() {
+            for (j, &b) in values.iter().enumerate() {
+                let lhs = [a, b, values[(i + j) % values.len()], -a];
+                let rhs = [b, a, values[(i * j + 1) % values.len()], -b];
+                let (sa, sb) = (sse::F32x4::from_array(lhs), sse::F32x4::from_array(rhs));
+                let (ca, cb) = (
+                    scalar::F32x4::from_array(lhs),
+                    scalar::F32x4::from_array(rhs),
+                );
+                let msg = format!("{:?} {:?}", lhs, rhs);
+
+                assert!(same(sa.to_array(), ca.to_array()), "{}", msg);
+                assert!(
+                    same(
+                        sse::F32x4::splat(a).to_array(),
+                        scalar::F32x4::splat(a).to_array()
+                    ),
+                    "{}",
+                    msg
+                );
+                assert!(
+                    same(sa.add(sb).to_array(), ca.add(cb).to_array()),
+                    "{}",
+                    msg
+                );
+                assert!(
+                    same(sa.sub(sb).to_array(), ca.sub(cb).to_array()),
+                    "{}",
+                    msg
+                );
+                assert!(
+                    same(sa.mul(sb).to_array(), ca.mul(cb).to_array()),
+                    "{}",
+                    msg
+                );
+                assert!(
+                    same(sa.div(sb).to_array(), ca.div(cb).to_array()),
+                    "{}",
+                    msg
+                );
+                assert!(
+                    same(sa.min(sb).to_array(), ca.min(cb).to_array()),
+                    "{}",
+                    msg
+                );
+                assert!(
+                    same(sa.max(sb).to_array(), ca.max(cb).to_array()),
+                    "{}",
+                    msg
+                );
+                assert_eq!(sa.lt(sb), ca.lt(cb), "{}", msg);
+                assert_eq!(sa.le(sb), ca.le(cb), "{}", msg);
+                assert_eq!(sa.eq(sb), ca.eq(cb), "{}", msg);
+            }
+        }
+    }
+}
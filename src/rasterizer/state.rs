@@ -0,0 +1,530 @@
+// Fixed-function state that is not programmable through the shaders, set per draw through the
+// renderer. The defaults match what the rasterizer did before the state was configurable.
+
+use super::simd::{F32x4, ALL_LANES};
+use crate::color::Color;
+
+/// Winding order of a triangle in screen space (y pointing down), i.e. as seen on the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Winding {
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    /// Discard front-facing triangles
+    Front,
+    /// Discard back-facing triangles
+    Back,
+}
+
+/// What the source (fragment shader output) and destination (color buffer) colors are multiplied
+/// with before they are combined by the `BlendOp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+impl BlendFactor {
+    fn eval(self, src: Color, dst: Color) -> Color {
+        let one = Color {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+            a: 1.0,
+        };
+        match self {
+            BlendFactor::Zero => Color::default(),
+            BlendFactor::One => one,
+            BlendFactor::SrcColor => src,
+            BlendFactor::OneMinusSrcColor => one - src,
+            BlendFactor::DstColor => dst,
+            BlendFactor::OneMinusDstColor => one - dst,
+            BlendFactor::SrcAlpha => one * src.a,
+            BlendFactor::OneMinusSrcAlpha => one * (1.0 - src.a),
+            BlendFactor::DstAlpha => one * dst.a,
+            BlendFactor::OneMinusDstAlpha => one * (1.0 - dst.a),
+        }
+    }
+}
+
+/// How the weighted source and destination are combined. Min and max ignore the factors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    Add,
+    /// src - dst
+    Subtract,
+    /// dst - src
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+impl BlendOp {
+    fn eval(self, src: f32, src_factor: f32, dst: f32, dst_factor: f32) -> f32 {
+        match self {
+            BlendOp::Add => src * src_factor + dst * dst_factor,
+            BlendOp::Subtract => src * src_factor - dst * dst_factor,
+            BlendOp::ReverseSubtract => dst * dst_factor - src * src_factor,
+            BlendOp::Min => src.min(dst),
+            BlendOp::Max => src.max(dst),
+        }
+    }
+}
+
+/// Separate factors and ops for rgb and alpha, like in D3D/Vulkan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlendState {
+    pub src_color: BlendFactor,
+    pub dst_color: BlendFactor,
+    pub color_op: BlendOp,
+    pub src_alpha: BlendFactor,
+    pub dst_alpha: BlendFactor,
+    pub alpha_op: BlendOp,
+}
+
+impl BlendState {
+    /// Classic transparency: src * src.a + dst * (1 - src.a)
+    pub fn alpha() -> Self {
+        BlendState {
+            src_color: BlendFactor::SrcAlpha,
+            dst_color: BlendFactor::OneMinusSrcAlpha,
+            color_op: BlendOp::Add,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::OneMinusSrcAlpha,
+            alpha_op: BlendOp::Add,
+        }
+    }
+
+    /// src * src.a + dst, e.g. for particles and light accumulation
+    pub fn additive() -> Self {
+        BlendState {
+            src_color: BlendFactor::SrcAlpha,
+            dst_color: BlendFactor::One,
+            color_op: BlendOp::Add,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::One,
+            alpha_op: BlendOp::Add,
+        }
+    }
+
+    /// src * dst, e.g. for tinting or light maps
+    pub fn multiply() -> Self {
+        BlendState {
+            src_color: BlendFactor::DstColor,
+            dst_color: BlendFactor::Zero,
+            color_op: BlendOp::Add,
+            src_alpha: BlendFactor::DstAlpha,
+            dst_alpha: BlendFactor::Zero,
+            alpha_op: BlendOp::Add,
+        }
+    }
+
+    /// Not clamped, float color formats keep values outside of [0, 1] while `Rgba8` clamps them
+    /// when they are stored.
+    pub fn blend(&self, src: Color, dst: Color) -> Color {
+        let src_color = self.src_color.eval(src, dst);
+        let dst_color = self.dst_color.eval(src, dst);
+        let src_alpha = self.src_alpha.eval(src, dst);
+        let dst_alpha = self.dst_alpha.eval(src, dst);
+        Color {
+            r: self.color_op.eval(src.r, src_color.r, dst.r, dst_color.r),
+            g: self.color_op.eval(src.g, src_color.g, dst.g, dst_color.g),
+            b: self.color_op.eval(src.b, src_color.b, dst.b, dst_color.b),
+            a: self.alpha_op.eval(src.a, src_alpha.a, dst.a, dst_alpha.a),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareFunc {
+    Never,
+    Less,
+    LessEqual,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Always,
+    NotEqual,
+}
+
+impl CompareFunc {
+    /// Whether `new` (e.g. the depth of the fragment) passes when compared to `stored`
+    pub fn passes<T: PartialOrd>(self, new: T, stored: T) -> bool {
+        match self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => new < stored,
+            CompareFunc::LessEqual => new <= stored,
+            CompareFunc::Equal => new == stored,
+            CompareFunc::Greater => new > stored,
+            CompareFunc::GreaterEqual => new >= stored,
+            CompareFunc::Always => true,
+            CompareFunc::NotEqual => new != stored,
+        }
+    }
+
+    // `passes` for each lane, as a bitmask
+    pub(super) fn passes_x4(self, new: F32x4, stored: F32x4) -> u8 {
+        match self {
+            CompareFunc::Never => 0,
+            CompareFunc::Less => new.lt(stored),
+            CompareFunc::LessEqual => new.le(stored),
+            CompareFunc::Equal => new.eq(stored),
+            CompareFunc::Greater => new.gt(stored),
+            CompareFunc::GreaterEqual => new.ge(stored),
+            CompareFunc::Always => ALL_LANES,
+            CompareFunc::NotEqual => new.ne(stored),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthState {
+    /// If disabled, all fragments pass and the depth buffer is not written, regardless of
+    /// `write_enable`.
+    pub test_enable: bool,
+    pub write_enable: bool,
+    pub compare: CompareFunc,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        DepthState {
+            test_enable: true,
+            write_enable: true,
+            compare: CompareFunc::Less,
+        }
+    }
+}
+
+/// What happens to the stored stencil value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    /// Set to the reference value
+    Replace,
+    IncrementClamp,
+    DecrementClamp,
+    Invert,
+    IncrementWrap,
+    DecrementWrap,
+}
+
+impl StencilOp {
+    fn apply(self, stored: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => stored,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::IncrementClamp => stored.saturating_add(1),
+            StencilOp::DecrementClamp => stored.saturating_sub(1),
+            StencilOp::Invert => !stored,
+            StencilOp::IncrementWrap => stored.wrapping_add(1),
+            StencilOp::DecrementWrap => stored.wrapping_sub(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StencilFaceState {
+    /// The stencil test failed
+    pub fail: StencilOp,
+    /// The stencil test passed but the depth test failed
+    pub depth_fail: StencilOp,
+    /// Both tests passed
+    pub pass: StencilOp,
+    /// Compares the reference to the stored value, both masked with the read mask
+    pub compare: CompareFunc,
+}
+
+impl Default for StencilFaceState {
+    fn default() -> Self {
+        StencilFaceState {
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+            compare: CompareFunc::Always,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StencilState {
+    pub enable: bool,
+    pub reference: u8,
+    pub read_mask: u8,
+    /// Only these bits are modified by the stencil ops
+    pub write_mask: u8,
+    pub front: StencilFaceState,
+    pub back: StencilFaceState,
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        StencilState {
+            enable: false,
+            reference: 0,
+            read_mask: 0xFF,
+            write_mask: 0xFF,
+            front: StencilFaceState::default(),
+            back: StencilFaceState::default(),
+        }
+    }
+}
+
+impl StencilState {
+    pub fn face(&self, front_facing: bool) -> &StencilFaceState {
+        if front_facing {
+            &self.front
+        } else {
+            &self.back
+        }
+    }
+
+    pub fn test(&self, face: &StencilFaceState, stored: u8) -> bool {
+        face.compare
+            .passes(self.reference & self.read_mask, stored & self.read_mask)
+    }
+
+    /// The new stored value after applying `op`
+    pub fn update(&self, op: StencilOp, stored: u8) -> u8 {
+        let new = op.apply(stored, self.reference);
+        (stored & !self.write_mask) | (new & self.write_mask)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineState {
+    pub cull_mode: CullMode,
+    /// Triangles with this winding are front-facing
+    pub front_face: Winding,
+    /// None overwrites the color buffer with the fragment shader output
+    pub blend: Option<BlendState>,
+    pub depth: DepthState,
+    pub stencil: StencilState,
+}
+
+impl Default for PipelineState {
+    fn default() -> Self {
+        PipelineState {
+            cull_mode: CullMode::Back,
+            front_face: Winding::Clockwise,
+            blend: None,
+            depth: DepthState::default(),
+            stencil: StencilState::default(),
+        }
+    }
+}
+
+impl PipelineState {
+    pub fn is_front_facing(&self, winding: Winding) -> bool {
+        winding == self.front_face
+    }
+
+    pub fn writes_depth(&self) -> bool {
+        self.depth.test_enable && self.depth.write_enable
+    }
+
+    pub fn culls(&self, winding: Winding) -> bool {
+        match self.cull_mode {
+            CullMode::None => false,
+            CullMode::Front => self.is_front_facing(winding),
+            CullMode::Back => !self.is_front_facing(winding),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn culling() {
+        let mut state = PipelineState::default();
+        assert!(!state.culls(Winding::Clockwise));
+        assert!(state.culls(Winding::CounterClockwise));
+
+        state.front_face = Winding::CounterClockwise;
+        assert!(state.culls(Winding::Clockwise));
+        assert!(!state.culls(Winding::CounterClockwise));
+
+        state.cull_mode = CullMode::Front;
+        assert!(!state.culls(Winding::Clockwise));
+        assert!(state.culls(Winding::CounterClockwise));
+
+        state.cull_mode = CullMode::None;
+        assert!(!state.culls(Winding::Clockwise));
+        assert!(!state.culls(Winding::CounterClockwise));
+    }
+
+    fn assert_color_eq(actual: Color, expected: Color) {
+        let eq = |a: f32, b: f32| (a - b).abs() < 1e-6;
+        assert!(
+            eq(actual.r, expected.r)
+                && eq(actual.g, expected.g)
+                && eq(actual.b, expected.b)
+                && eq(actual.a, expected.a),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    fn rgba(r: f32, g: f32, b: f32, a: f32) -> Color {
+        Color { r, g, b, a }
+    }
+
+    #[test]
+    fn blend_alpha() {
+        let src = rgba(1.0, 0.0, 0.0, 0.25);
+        let dst = rgba(0.0, 0.0, 1.0, 1.0);
+        assert_color_eq(
+            BlendState::alpha().blend(src, dst),
+            rgba(0.25, 0.0, 0.75, 1.0),
+        );
+
+        // Fully opaque and fully transparent sources
+        let opaque = rgba(0.2, 0.4, 0.6, 1.0);
+        assert_color_eq(BlendState::alpha().blend(opaque, dst), opaque);
+        let transparent = rgba(0.2, 0.4, 0.6, 0.0);
+        assert_color_eq(BlendState::alpha().blend(transparent, dst), dst);
+    }
+
+    #[test]
+    fn blend_additive() {
+        let src = rgba(0.5, 0.5, 0.0, 0.5);
+        let dst = rgba(0.9, 0.1, 0.2, 1.0);
+        // Not clamped, the color format of the attachment decides
+        assert_color_eq(
+            BlendState::additive().blend(src, dst),
+            rgba(1.15, 0.35, 0.2, 1.5),
+        );
+    }
+
+    #[test]
+    fn blend_multiply() {
+        let src = rgba(0.5, 1.0, 0.0, 1.0);
+        let dst = rgba(0.5, 0.5, 0.5, 0.5);
+        assert_color_eq(
+            BlendState::multiply().blend(src, dst),
+            rgba(0.25, 0.5, 0.0, 0.5),
+        );
+    }
+
+    #[test]
+    fn blend_ops() {
+        let src = rgba(0.75, 0.25, 0.5, 1.0);
+        let dst = rgba(0.25, 0.5, 0.5, 0.5);
+        let mut state = BlendState {
+            src_color: BlendFactor::One,
+            dst_color: BlendFactor::One,
+            color_op: BlendOp::Subtract,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::Zero,
+            alpha_op: BlendOp::Add,
+        };
+        assert_color_eq(state.blend(src, dst), rgba(0.5, -0.25, 0.0, 1.0));
+
+        state.color_op = BlendOp::ReverseSubtract;
+        assert_color_eq(state.blend(src, dst), rgba(-0.5, 0.25, 0.0, 1.0));
+
+        state.color_op = BlendOp::Min;
+        assert_color_eq(state.blend(src, dst), rgba(0.25, 0.25, 0.5, 1.0));
+
+        state.color_op = BlendOp::Max;
+        state.alpha_op = BlendOp::Min;
+        assert_color_eq(state.blend(src, dst), rgba(0.75, 0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn compare_funcs() {
+        let all = [
+            CompareFunc::Never,
+            CompareFunc::Less,
+            CompareFunc::LessEqual,
+            CompareFunc::Equal,
+            CompareFunc::Greater,
+            CompareFunc::GreaterEqual,
+            CompareFunc::Always,
+            CompareFunc::NotEqual,
+        ];
+        let passing = |new: f32, stored: f32| {
+            all.iter()
+                .filter(|f| f.passes(new, stored))
+                .copied()
+                .collect::<Vec<_>>()
+        };
+
+        use CompareFunc::*;
+        assert_eq!(passing(0.25, 0.5), vec![Less, LessEqual, Always, NotEqual]);
+        assert_eq!(
+            passing(0.5, 0.5),
+            vec![LessEqual, Equal, GreaterEqual, Always]
+        );
+        assert_eq!(
+            passing(0.75, 0.5),
+            vec![Greater, GreaterEqual, Always, NotEqual]
+        );
+        assert!(CompareFunc::Less.passes(1u8, 2u8));
+    }
+
+    #[test]
+    fn stencil_ops() {
+        let ops = [
+            (StencilOp::Keep, 5, 255, 0),
+            (StencilOp::Zero, 0, 0, 0),
+            (StencilOp::Replace, 3, 3, 3),
+            (StencilOp::IncrementClamp, 6, 255, 1),
+            (StencilOp::DecrementClamp, 4, 254, 0),
+            (StencilOp::Invert, 250, 0, 255),
+            (StencilOp::IncrementWrap, 6, 0, 1),
+            (StencilOp::DecrementWrap, 4, 254, 255),
+        ];
+        for (op, from_5, from_255, from_0) in ops {
+            assert_eq!(op.apply(5, 3), from_5, "{:?}", op);
+            assert_eq!(op.apply(255, 3), from_255, "{:?}", op);
+            assert_eq!(op.apply(0, 3), from_0, "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn stencil_masks() {
+        let mut state = StencilState {
+            enable: true,
+            reference: 0b1010_0001,
+            read_mask: 0x0F,
+            write_mask: 0xF0,
+            front: StencilFaceState {
+                compare: CompareFunc::Equal,
+                ..Default::default()
+            },
+            back: StencilFaceState {
+                compare: CompareFunc::Never,
+                ..Default::default()
+            },
+        };
+
+        // Only the low bits are compared
+        assert!(state.test(state.face(true), 0b0101_0001));
+        assert!(!state.test(state.face(true), 0b1010_0011));
+        assert!(!state.test(state.face(false), 0b1010_0001));
+
+        // Only the high bits are written
+        assert_eq!(state.update(StencilOp::Replace, 0b0101_0101), 0b1010_0101);
+        assert_eq!(state.update(StencilOp::Zero, 0xFF), 0x0F);
+        state.write_mask = 0;
+        assert_eq!(state.update(StencilOp::Invert, 0x12), 0x12);
+    }
+}
@@ -3,9 +3,12 @@ use crate::graphics_primitives::*;
 use crate::math;
 use crate::mesh::Mesh;
 use crate::rasterizer::*;
+use crate::render_target::*;
+use crate::screenshot;
 use crate::uniform::Uniforms;
 
 // Debug
+#[allow(unused)]
 fn dump_vertices<CS: math::CoordinateSystem, const N: usize>(
     vertices: &[math::Point<CS, { N }>],
     fname: &str,
@@ -18,6 +21,7 @@ fn dump_vertices<CS: math::CoordinateSystem, const N: usize>(
     }
 }
 
+#[allow(unused)]
 fn dump_indices(indices: &[usize]) {
     use std::io::Write;
     let mut file = std::fs::File::create("index.txt").expect("failed to open file");
@@ -35,32 +39,104 @@ pub type VertexShader =
 
 pub type FragmentShader = fn(&Uniforms, &FragCoords, &VertexAttribute) -> Color;
 
-pub struct Renderer {
+/// A vertex and fragment shader pair that can carry state, e.g. material parameters or a light,
+/// and declares its own vertex input, varying and uniform types. Fragments are shaded in parallel,
+/// hence `Sync`.
+pub trait ShaderProgram: Sync {
+    /// Per-vertex data besides the position, see `Mesh::attributes`.
+    type VertexInput;
+    /// Output of the vertex shader that is interpolated for each fragment.
+    type Varying: Varying;
+    type Uniforms: Sync;
+    /// Output of the fragment shader, `Color` or one color per attachment of the framebuffer.
+    type Output: FragmentOutput;
+
+    fn vertex(
+        &self,
+        uniforms: &Self::Uniforms,
+        position: &math::Point3D<math::WorldSpace>,
+        input: &Self::VertexInput,
+    ) -> (math::Point4D<math::ClipSpace>, Self::Varying);
+
+    fn fragment(
+        &self,
+        uniforms: &Self::Uniforms,
+        frag_coords: &FragCoords<Self::Varying>,
+        varying: &Self::Varying,
+    ) -> Self::Output;
+}
+
+// Adapts a vertex and fragment shader closure to a program where the vertex attributes are passed
+// through to the fragment shader as is.
+struct FnProgram<VS, FS> {
+    vertex_shader: VS,
+    fragment_shader: FS,
+}
+
+impl<VS, FS, O> ShaderProgram for FnProgram<VS, FS>
+where
+    VS: Fn(&Uniforms, &math::Point3D<math::WorldSpace>) -> math::Point4D<math::ClipSpace> + Sync,
+    FS: Fn(&Uniforms, &FragCoords, &VertexAttribute) -> O + Sync,
+    O: FragmentOutput,
+{
+    type VertexInput = VertexAttribute;
+    type Varying = VertexAttribute;
+    type Uniforms = Uniforms;
+    type Output = O;
+
+    fn vertex(
+        &self,
+        uniforms: &Uniforms,
+        position: &math::Point3D<math::WorldSpace>,
+        input: &VertexAttribute,
+    ) -> (math::Point4D<math::ClipSpace>, VertexAttribute) {
+        ((self.vertex_shader)(uniforms, position), *input)
+    }
+
+    fn fragment(
+        &self,
+        uniforms: &Uniforms,
+        frag_coords: &FragCoords,
+        varying: &VertexAttribute,
+    ) -> O {
+        (self.fragment_shader)(uniforms, frag_coords, varying)
+    }
+}
+
+pub struct Renderer<T: RenderTarget = WindowTarget> {
     rasterizer: Rasterizer,
-    window: minifb::Window,
+    target: T,
     uniforms: Uniforms,
     frame_time_idx: usize,
     width: usize,
     height: usize,
 }
 
-impl Renderer {
-    pub fn new(width: usize, height: usize) -> Renderer {
-        let window = minifb::Window::new(
-            "Rusterizer",
-            width,
-            height,
-            minifb::WindowOptions::default(),
-        )
-        .unwrap_or_else(|e| {
+impl Renderer<WindowTarget> {
+    pub fn new(width: usize, height: usize) -> Self {
+        let window = WindowTarget::new("Rusterizer", width, height).unwrap_or_else(|e| {
             panic!("{}", e);
         });
 
+        Self::with_target(window, width, height)
+    }
+}
+
+impl Renderer<OffscreenTarget> {
+    /// A renderer that does not need a windowing system, the presented frames are available
+    /// through `target().pixels()`.
+    pub fn headless(width: usize, height: usize) -> Self {
+        Self::with_target(OffscreenTarget::new(width, height), width, height)
+    }
+}
+
+impl<T: RenderTarget> Renderer<T> {
+    pub fn with_target(target: T, width: usize, height: usize) -> Self {
         let rasterizer = Rasterizer::new(width, height);
 
         Self {
             rasterizer,
-            window,
+            target,
             uniforms: Uniforms::new(),
             frame_time_idx: 0,
             width,
@@ -68,15 +144,33 @@ impl Renderer {
         }
     }
 
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+
+    pub fn target_mut(&mut self) -> &mut T {
+        &mut self.target
+    }
+
     pub fn uniforms(&mut self) -> &mut Uniforms {
         &mut self.uniforms
     }
 
-    fn primitive_assembly(
+    /// MSAA of the window, see `Rasterizer::set_sample_count`. Framebuffers have their own.
+    pub fn set_sample_count(&mut self, samples: SampleCount) {
+        self.rasterizer.set_sample_count(samples);
+    }
+
+    /// Exposure, tone mapping and encoding of the presented frames
+    pub fn output_transform(&mut self) -> &mut OutputTransform {
+        self.rasterizer.output_transform()
+    }
+
+    fn primitive_assembly<V: Varying>(
         vertex_buf: &[math::Point4D<math::ClipSpace>],
-        attr_buf: &[VertexAttribute],
+        attr_buf: &[V],
         idx_buf: &[usize],
-    ) -> Vec<Triangle<math::ClipSpace>> {
+    ) -> Vec<Triangle<math::ClipSpace, V>> {
         let mut triangles = Vec::with_capacity(idx_buf.len() / 3);
         for idxs in idx_buf.chunks(3) {
             let vertices = [
@@ -95,41 +189,142 @@ impl Renderer {
         triangles
     }
 
-    pub fn render(
-        &mut self,
-        mesh: &Mesh<math::WorldSpace>,
-        vertex_shader: VertexShader,
-        fragment_shader: FragmentShader,
+    // Not a method, as the uniforms might be borrowed from the renderer. Into the framebuffer of
+    // the window if there is no other.
+    fn draw<P: ShaderProgram>(
+        rasterizer: &mut Rasterizer,
+        framebuffer: Option<&mut Framebuffer>,
+        state: &PipelineState,
+        mesh: &Mesh<math::WorldSpace, P::VertexInput>,
+        program: &P,
+        uniforms: &P::Uniforms,
     ) {
-        let vertices: Vec<math::Point4D<math::ClipSpace>> = mesh
+        assert_eq!(
+            mesh.vertices.len(),
+            mesh.attributes.len(),
+            "Every vertex needs its attributes"
+        );
+        let (vertices, varyings): (Vec<_>, Vec<_>) = mesh
             .vertices
             .iter()
-            .map(|v| vertex_shader(&self.uniforms, v))
-            .collect::<Vec<_>>();
+            .zip(mesh.attributes.iter())
+            .map(|(v, a)| program.vertex(uniforms, v, a))
+            .unzip();
+
+        let tris = Self::primitive_assembly(&vertices, &varyings, &mesh.indices);
 
-        let tris = Renderer::primitive_assembly(&vertices, &mesh.attributes, &mesh.indices);
+        let fragment_shader = |u: &P::Uniforms, fc: &FragCoords<P::Varying>, v: &P::Varying| {
+            program.fragment(u, fc, v)
+        };
+        match framebuffer {
+            Some(framebuffer) => {
+                rasterizer.rasterize_to(framebuffer, &tris, state, uniforms, fragment_shader)
+            }
+            None => rasterizer.rasterize(&tris, state, uniforms, fragment_shader),
+        }
+    }
 
-        self.rasterizer
-            .rasterize(&tris, &self.uniforms, fragment_shader);
+    /// Draw with the uniforms of the renderer, the vertex attributes are passed to the fragment
+    /// shader.
+    pub fn render<VS, FS, O>(
+        &mut self,
+        mesh: &Mesh<math::WorldSpace>,
+        state: &PipelineState,
+        vertex_shader: VS,
+        fragment_shader: FS,
+    ) where
+        VS: Fn(&Uniforms, &math::Point3D<math::WorldSpace>) -> math::Point4D<math::ClipSpace>
+            + Sync,
+        FS: Fn(&Uniforms, &FragCoords, &VertexAttribute) -> O + Sync,
+        O: FragmentOutput,
+    {
+        let program = FnProgram {
+            vertex_shader,
+            fragment_shader,
+        };
+        Self::draw(
+            &mut self.rasterizer,
+            None,
+            state,
+            mesh,
+            &program,
+            &self.uniforms,
+        );
     }
 
-    pub fn display(&mut self) -> minifb::Result<bool> {
-        if !self.window.is_open() || self.window.is_key_down(minifb::Key::Escape) {
-            return Ok(false);
-        }
+    /// Same as `render`, into a framebuffer instead of the window.
+    pub fn render_to<VS, FS, O>(
+        &mut self,
+        framebuffer: &mut Framebuffer,
+        mesh: &Mesh<math::WorldSpace>,
+        state: &PipelineState,
+        vertex_shader: VS,
+        fragment_shader: FS,
+    ) where
+        VS: Fn(&Uniforms, &math::Point3D<math::WorldSpace>) -> math::Point4D<math::ClipSpace>
+            + Sync,
+        FS: Fn(&Uniforms, &FragCoords, &VertexAttribute) -> O + Sync,
+        O: FragmentOutput,
+    {
+        let program = FnProgram {
+            vertex_shader,
+            fragment_shader,
+        };
+        Self::draw(
+            &mut self.rasterizer,
+            Some(framebuffer),
+            state,
+            mesh,
+            &program,
+            &self.uniforms,
+        );
+    }
 
+    /// Draw with a shader program and its uniforms, the uniforms of the renderer are not used.
+    pub fn render_with<P: ShaderProgram>(
+        &mut self,
+        mesh: &Mesh<math::WorldSpace, P::VertexInput>,
+        state: &PipelineState,
+        program: &P,
+        uniforms: &P::Uniforms,
+    ) {
+        Self::draw(&mut self.rasterizer, None, state, mesh, program, uniforms);
+    }
+
+    /// Same as `render_with`, into a framebuffer instead of the window.
+    pub fn render_with_to<P: ShaderProgram>(
+        &mut self,
+        framebuffer: &mut Framebuffer,
+        mesh: &Mesh<math::WorldSpace, P::VertexInput>,
+        state: &PipelineState,
+        program: &P,
+        uniforms: &P::Uniforms,
+    ) {
+        Self::draw(
+            &mut self.rasterizer,
+            Some(framebuffer),
+            state,
+            mesh,
+            program,
+            uniforms,
+        );
+    }
+
+    pub fn display(&mut self) -> Result<bool, T::Error> {
         let color_buffer = self.rasterizer.framebuffer();
 
-        self.window
-            .update_with_buffer(color_buffer, self.width, self.height)?;
+        self.target.present(color_buffer, self.width, self.height)
+    }
 
-        Ok(true)
+    /// Save the last displayed frame to `path` as png or ppm, depending on the file extension.
+    pub fn save_frame(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        screenshot::save(path, self.rasterizer.last_frame(), self.width, self.height)
     }
 
     pub fn display_frame_time(&mut self, d: &std::time::Duration) {
         if self.frame_time_idx == 10 {
             let t = d.as_secs_f32();
-            self.window.set_title(
+            self.target.set_title(
                 format!(
                     "Rusterizer FPS: {:.2}, ({:.2} ms)",
                     1.0f32 / t,
@@ -143,3 +338,180 @@ impl Renderer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::mesh;
+    use crate::shaders;
+
+    #[test]
+    fn headless_render() {
+        const WIDTH: usize = 64;
+        const HEIGHT: usize = 64;
+
+        let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+        let block = renderer.uniforms().write_block();
+        block.view = Camera::default().get_view_matrix();
+        block.projection = math::project(1.0, 200.0, 1.0, std::f32::consts::FRAC_PI_2);
+
+        let fragment_shader = |_: &Uniforms, _: &FragCoords, _: &VertexAttribute| Color::red();
+
+        renderer.render(
+            &mesh::triangle(),
+            &PipelineState::default(),
+            shaders::mvp_vs,
+            fragment_shader,
+        );
+        assert!(matches!(renderer.display(), Ok(true)));
+
+        let target = renderer.target();
+        assert_eq!(target.pixels().len(), WIDTH * HEIGHT);
+        assert_eq!(target.pixel(WIDTH / 2, HEIGHT / 2), Color::red().to_argb());
+        let clear_color = target.pixel(0, 0);
+        assert_ne!(clear_color, Color::red().to_argb());
+
+        // Nothing rendered => the previous frame should have been cleared
+        assert!(matches!(renderer.display(), Ok(true)));
+        assert!(renderer.target().pixels().iter().all(|&p| p == clear_color));
+    }
+
+    #[test]
+    fn pipeline_state_per_draw() {
+        let mut renderer = Renderer::headless(32, 32);
+        let block = renderer.uniforms().write_block();
+        block.view = Camera::default().get_view_matrix();
+        block.projection = math::project(1.0, 200.0, 1.0, std::f32::consts::FRAC_PI_2);
+        let shader = |color: Color| move |_: &Uniforms, _: &FragCoords, _: &VertexAttribute| color;
+
+        // The same triangle twice, the second draw only passes the depth test with `LessEqual`
+        let less = PipelineState::default();
+        let less_equal = PipelineState {
+            depth: DepthState {
+                compare: CompareFunc::LessEqual,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for (state, expected) in [(&less, Color::red()), (&less_equal, Color::blue())] {
+            let triangle = mesh::triangle();
+            renderer.render(&triangle, &less, shaders::mvp_vs, shader(Color::red()));
+            renderer.render(&triangle, state, shaders::mvp_vs, shader(Color::blue()));
+            renderer.display().unwrap();
+            assert_eq!(renderer.target().pixel(16, 16), expected.to_argb());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Every vertex needs its attributes")]
+    fn missing_attributes() {
+        let mut mesh = mesh::triangle();
+        mesh.attributes.pop();
+        let mut renderer = Renderer::headless(8, 8);
+        renderer.render(
+            &mesh,
+            &PipelineState::default(),
+            shaders::mvp_vs,
+            shaders::color_fs,
+        );
+    }
+
+    #[test]
+    fn render_to_framebuffer() {
+        let mut renderer = Renderer::headless(64, 64);
+        let block = renderer.uniforms().write_block();
+        block.view = Camera::default().get_view_matrix();
+        block.projection = math::project(1.0, 200.0, 0.5, std::f32::consts::FRAC_PI_2);
+
+        // A G-buffer at a different resolution than the window
+        let formats = [ColorFormat::Rgba8, ColorFormat::R32F];
+        let mut gbuffer = Framebuffer::with_attachments(32, 16, &formats, true);
+        let fragment_shader = |_: &Uniforms, fc: &FragCoords, attr: &VertexAttribute| {
+            [attr.color, Color::grayscale(fc.depths[0])]
+        };
+        renderer.render_to(
+            &mut gbuffer,
+            &mesh::triangle(),
+            &PipelineState::default(),
+            shaders::mvp_vs,
+            fragment_shader,
+        );
+
+        let albedo = gbuffer.read_color(0, 16, 8);
+        let depth = gbuffer.read_color(1, 16, 8).r;
+        assert_ne!(albedo.to_argb(), gbuffer.read_color(0, 0, 0).to_argb());
+        assert!(depth > 0.0 && depth < 1.0, "{}", depth);
+
+        // The window is not drawn to
+        assert!(matches!(renderer.display(), Ok(true)));
+        let pixels = renderer.target().pixels();
+        assert!(pixels.iter().all(|&p| p == pixels[0]));
+    }
+
+    // Diffuse lighting with normals as vertex input and the light and albedo as program state.
+    struct Lambert {
+        light_dir: math::Vec3<math::WorldSpace>,
+        albedo: Color,
+    }
+
+    impl ShaderProgram for Lambert {
+        type VertexInput = math::Vec3<math::WorldSpace>;
+        type Varying = math::Vec3<math::WorldSpace>;
+        type Uniforms = math::Mat4<math::WorldSpace, math::ClipSpace>;
+        type Output = Color;
+
+        fn vertex(
+            &self,
+            view_projection: &Self::Uniforms,
+            position: &math::Point3D<math::WorldSpace>,
+            normal: &Self::VertexInput,
+        ) -> (math::Point4D<math::ClipSpace>, Self::Varying) {
+            (*view_projection * position.extend(1.0), *normal)
+        }
+
+        fn fragment(
+            &self,
+            _: &Self::Uniforms,
+            _: &FragCoords<Self::Varying>,
+            normal: &Self::Varying,
+        ) -> Color {
+            let n_dot_l = normal.normalized().dot(self.light_dir * -1.0).max(0.0);
+            self.albedo * n_dot_l
+        }
+    }
+
+    #[test]
+    fn shader_program() {
+        const WIDTH: usize = 64;
+        const HEIGHT: usize = 64;
+
+        let triangle = mesh::triangle();
+        let mesh = Mesh {
+            vertices: triangle.vertices,
+            indices: triangle.indices,
+            attributes: vec![math::vec3(0.0, 0.0, -1.0); 3],
+        };
+        let view_projection = math::project(1.0, 200.0, 1.0, std::f32::consts::FRAC_PI_2)
+            * Camera::default().get_view_matrix();
+
+        let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+        let lit = Lambert {
+            light_dir: math::vec3(0.0, 0.0, 1.0),
+            albedo: Color::red(),
+        };
+        renderer.render_with(&mesh, &PipelineState::default(), &lit, &view_projection);
+        assert!(matches!(renderer.display(), Ok(true)));
+        let center = renderer.target().pixel(WIDTH / 2, HEIGHT / 2);
+        assert_eq!(center, Color::red().to_argb());
+
+        let unlit = Lambert {
+            light_dir: math::vec3(0.0, 0.0, -1.0),
+            ..lit
+        };
+        renderer.render_with(&mesh, &PipelineState::default(), &unlit, &view_projection);
+        assert!(matches!(renderer.display(), Ok(true)));
+        let center = renderer.target().pixel(WIDTH / 2, HEIGHT / 2);
+        assert_eq!(center & 0x00FF_FFFF, 0);
+    }
+}
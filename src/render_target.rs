@@ -0,0 +1,97 @@
+// A render target is where the resolved frame ends up after the rasterizer is done with it.
+// The rasterizer itself never talks to a windowing system, it only produces a resolved ARGB
+// buffer, so the target decides what "presenting" that buffer means: blitting it to a window or
+// just keeping a copy in memory (e.g. for tests or rendering on a machine without a display).
+
+pub trait RenderTarget {
+    type Error: std::fmt::Display;
+
+    /// Present a resolved frame with `width` * `height` pixels in ARGB format.
+    /// Returns Ok(false) if the target does not want any more frames, e.g. the window was closed.
+    fn present(&mut self, frame: &[u32], width: usize, height: usize) -> Result<bool, Self::Error>;
+
+    /// Optional, only meaningful for targets that have a title, i.e. windows.
+    fn set_title(&mut self, _title: &str) {}
+}
+
+pub struct WindowTarget {
+    window: minifb::Window,
+}
+
+impl WindowTarget {
+    pub fn new(title: &str, width: usize, height: usize) -> minifb::Result<Self> {
+        let window = minifb::Window::new(title, width, height, minifb::WindowOptions::default())?;
+        Ok(Self { window })
+    }
+
+    pub fn window(&self) -> &minifb::Window {
+        &self.window
+    }
+}
+
+impl RenderTarget for WindowTarget {
+    type Error = minifb::Error;
+
+    fn present(&mut self, frame: &[u32], width: usize, height: usize) -> minifb::Result<bool> {
+        if !self.window.is_open() || self.window.is_key_down(minifb::Key::Escape) {
+            return Ok(false);
+        }
+
+        self.window.update_with_buffer(frame, width, height)?;
+
+        Ok(true)
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+}
+
+/// Keeps the last presented frame in memory, no windowing system needed.
+pub struct OffscreenTarget {
+    buffer: Vec<u32>,
+    width: usize,
+    height: usize,
+}
+
+impl OffscreenTarget {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            buffer: vec![0; width * height],
+            width,
+            height,
+        }
+    }
+
+    /// The last presented frame, row-major ARGB with (0, 0) in the upper left corner.
+    pub fn pixels(&self) -> &[u32] {
+        &self.buffer
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        debug_assert!(x < self.width, "x: {}", x);
+        debug_assert!(y < self.height, "y: {}", y);
+        self.buffer[y * self.width + x]
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl RenderTarget for OffscreenTarget {
+    type Error = std::convert::Infallible;
+
+    fn present(&mut self, frame: &[u32], width: usize, height: usize) -> Result<bool, Self::Error> {
+        debug_assert_eq!(frame.len(), width * height);
+        self.width = width;
+        self.height = height;
+        self.buffer.clear();
+        self.buffer.extend_from_slice(frame);
+        Ok(true)
+    }
+}
@@ -0,0 +1,125 @@
+// Sampler state: how a `Texture` is filtered and addressed when it is sampled. Kept separate from
+// the texture so the same image can be sampled in different ways.
+
+use crate::color::Color;
+
+/// How texels are filtered within a mip level and between the two closest mip levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Nearest texel of the nearest mip level
+    Nearest,
+    /// Bilinear within the nearest mip level
+    Bilinear,
+    /// Bilinear within the two closest mip levels, linear between them
+    Trilinear,
+    /// Nearest texel within the two closest mip levels, linear between them
+    NearestMipLinear,
+}
+
+/// How texture coordinates outside of [0, 1] are mapped into the texture, per axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    /// Repeated, every other tile mirrored
+    MirroredRepeat,
+    ClampToEdge,
+    /// `Sampler::border_color` outside of the texture
+    ClampToBorder,
+    /// Mirrored once around 0, then clamped to the edge
+    MirrorOnce,
+}
+
+impl WrapMode {
+    // Texel index along an axis of `size` texels, None for the border
+    pub(crate) fn wrap(self, i: i64, size: usize) -> Option<usize> {
+        let n = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::MirroredRepeat => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+            WrapMode::ClampToEdge => i.clamp(0, n - 1),
+            WrapMode::ClampToBorder => return (0..n).contains(&i).then_some(i as usize),
+            WrapMode::MirrorOnce => {
+                let i = if i < 0 { -1 - i } else { i };
+                i.min(n - 1)
+            }
+        };
+        Some(i as usize)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sampler {
+    pub filter: FilterMode,
+    /// Upper limit of the probes taken along the major axis of elongated footprints, 1 disables
+    /// anisotropic filtering. Each probe is filtered with `filter`.
+    pub max_anisotropy: u32,
+    /// Added to the level of detail, positive values select blurrier mip levels
+    pub lod_bias: f32,
+    /// The biased level of detail is clamped to [min_lod, max_lod]
+    pub min_lod: f32,
+    pub max_lod: f32,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+    /// For `WrapMode::ClampToBorder`
+    pub border_color: Color,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self {
+            filter: FilterMode::Trilinear,
+            max_anisotropy: 1,
+            lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: f32::MAX,
+            wrap_u: WrapMode::ClampToEdge,
+            wrap_v: WrapMode::ClampToEdge,
+            border_color: Color::default(),
+        }
+    }
+}
+
+impl Sampler {
+    /// Apply `lod_bias`, then clamp to [min_lod, max_lod]
+    pub(crate) fn adjust_lod(&self, lod: f32) -> f32 {
+        (lod + self.lod_bias).max(self.min_lod).min(self.max_lod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_modes() {
+        let wrapped = |mode: WrapMode| (-6..10).map(|i| mode.wrap(i, 4)).collect::<Vec<_>>();
+        let all = |v: &[usize]| v.iter().map(|&i| Some(i)).collect::<Vec<_>>();
+
+        assert_eq!(
+            wrapped(WrapMode::Repeat),
+            all(&[2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1])
+        );
+        assert_eq!(
+            wrapped(WrapMode::MirroredRepeat),
+            all(&[2, 3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0, 1])
+        );
+        assert_eq!(
+            wrapped(WrapMode::ClampToEdge),
+            all(&[0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3, 3])
+        );
+        assert_eq!(
+            wrapped(WrapMode::MirrorOnce),
+            all(&[3, 3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3, 3])
+        );
+        let border = wrapped(WrapMode::ClampToBorder);
+        assert_eq!(border[6..10], all(&[0, 1, 2, 3]));
+        assert!(border[..6].iter().chain(&border[10..]).all(Option::is_none));
+    }
+}
@@ -0,0 +1,171 @@
+// Export of resolved frames (row-major ARGB, as produced by the rasterizer) to image files.
+// The alpha channel is dropped as resolved frames are always opaque.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+fn argb_to_rgb(pixel: u32) -> [u8; 3] {
+    [
+        ((pixel >> 16) & 0xFF) as u8,
+        ((pixel >> 8) & 0xFF) as u8,
+        (pixel & 0xFF) as u8,
+    ]
+}
+
+fn check_size(pixels: &[u32], width: usize, height: usize) -> std::io::Result<()> {
+    if pixels.len() != width * height {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} pixels for a {}x{} image", pixels.len(), width, height),
+        ));
+    }
+    Ok(())
+}
+
+pub fn write_png<W: Write>(
+    mut w: W,
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+) -> std::io::Result<()> {
+    check_size(pixels, width, height)?;
+    // Encoded in memory first: the png writer writes the end of the image when it is dropped and
+    // ignores the errors of that write.
+    let mut buf = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buf, width as u32, height as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+
+    let data: Vec<u8> = pixels.iter().flat_map(|&p| argb_to_rgb(p)).collect();
+    writer.write_image_data(&data)?;
+    drop(writer);
+
+    w.write_all(&buf)?;
+    w.flush()
+}
+
+/// Binary PPM (P6), see http://netpbm.sourceforge.net/doc/ppm.html
+pub fn write_ppm<W: Write>(
+    mut w: W,
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+) -> std::io::Result<()> {
+    check_size(pixels, width, height)?;
+    write!(w, "P6\n{} {}\n255\n", width, height)?;
+    for &p in pixels {
+        w.write_all(&argb_to_rgb(p))?;
+    }
+    w.flush()
+}
+
+/// Save the pixels to `path`, the format is chosen based on the file extension (png or ppm).
+pub fn save(
+    path: impl AsRef<Path>,
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("png") => write_png(BufWriter::new(File::create(path)?), pixels, width, height),
+        Some("ppm") => write_ppm(BufWriter::new(File::create(path)?), pixels, width, height),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unsupported image format: {}", path.display()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXELS: [u32; 6] = [
+        0xFFFF0000, 0xFF00FF00, 0xFF0000FF, 0xFF000000, 0xFFFFFFFF, 0xFF123456,
+    ];
+
+    #[test]
+    fn ppm() {
+        let mut out = Vec::new();
+        write_ppm(&mut out, &PIXELS, 3, 2).unwrap();
+
+        let header = b"P6\n3 2\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(
+            &out[header.len()..],
+            &[255, 0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 0x12, 0x34, 0x56]
+        );
+    }
+
+    #[test]
+    fn png_roundtrip() {
+        let mut out = Vec::new();
+        write_png(&mut out, &PIXELS, 3, 2).unwrap();
+
+        let decoder = png::Decoder::new(out.as_slice());
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!(info.width, 3);
+        assert_eq!(info.height, 2);
+        assert_eq!(info.color_type, png::ColorType::RGB);
+
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        let rgb: Vec<u8> = PIXELS.iter().flat_map(|&p| argb_to_rgb(p)).collect();
+        assert_eq!(buf, rgb);
+    }
+
+    #[test]
+    fn wrong_size() {
+        for write in [write_png::<Vec<u8>>, write_ppm::<Vec<u8>>] {
+            let err = write(Vec::new(), &PIXELS, 2, 2).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    // Fails once `limit` bytes have been written
+    struct LimitedWriter {
+        written: usize,
+        limit: usize,
+    }
+
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.written + buf.len() > self.limit {
+                return Err(std::io::Error::other("Disk full"));
+            }
+            self.written += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn png_truncated() {
+        let mut out = Vec::new();
+        write_png(&mut out, &PIXELS, 3, 2).unwrap();
+
+        // The last chunk doesn't fit
+        let w = LimitedWriter {
+            written: 0,
+            limit: out.len() - 1,
+        };
+        assert!(write_png(w, &PIXELS, 3, 2).is_err());
+    }
+
+    #[test]
+    fn unsupported_extension() {
+        let err = save("frame.bmp", &PIXELS, 3, 2).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,35 @@
+// A few basic shaders that are useful for most scenes and for debugging.
+
+use crate::color::Color;
+use crate::graphics_primitives::VertexAttribute;
+use crate::math::{ClipSpace, Point3D, Point4D, WorldSpace};
+use crate::rasterizer::FragCoords;
+use crate::uniform::Uniforms;
+
+/// Transforms the vertex with the world, view and projection matrices of the uniform block.
+pub fn mvp_vs(uniforms: &Uniforms, vertex: &Point3D<WorldSpace>) -> Point4D<ClipSpace> {
+    uniforms.read_block().projection
+        * uniforms.read_block().view
+        * uniforms.read_block().world
+        * vertex.extend(1.0)
+}
+
+/// Samples the texture bound at index 0 with the sampler at index 0 and the interpolated uvs,
+/// filtered by their screen-space derivatives.
+pub fn texture_fs(uniforms: &Uniforms, frag_coords: &FragCoords, attr: &VertexAttribute) -> Color {
+    let sampler = uniforms.get_sampler(0);
+    let (dx, dy) = (frag_coords.dfdx(), frag_coords.dfdy());
+    uniforms
+        .get_texture(0)
+        .sample_grad(attr.uvs[0], attr.uvs[1], dx.uvs, dy.uvs, &sampler)
+}
+
+/// Outputs the interpolated vertex color.
+pub fn color_fs(_: &Uniforms, _: &FragCoords, attr: &VertexAttribute) -> Color {
+    attr.color
+}
+
+/// Outputs the depth of the first sample as grayscale.
+pub fn debug_fs(_: &Uniforms, frag_coords: &FragCoords, _: &VertexAttribute) -> Color {
+    Color::grayscale(frag_coords.depths[0])
+}
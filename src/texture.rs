@@ -1,84 +1,1041 @@
 use std::fs::File;
+use std::io::Read;
 use std::path::Path;
+use std::sync::OnceLock;
 
-use crate::color::Color;
+use crate::color::{self, Color};
+use crate::sampler::{FilterMode, Sampler};
 
-// (0, 0) is upper left corner
+// Channels of the texels, interleaved. 8-bit unsigned normalized or 32-bit float.
 #[derive(Clone)]
-pub struct Texture {
-    buf: Vec<u8>,
+enum Texels {
+    Unorm8(Vec<u8>),
+    Float(Vec<f32>),
+}
+
+impl Texels {
+    fn get(&self, i: usize) -> f32 {
+        match self {
+            Texels::Unorm8(buf) => buf[i] as f32 / 255.0,
+            Texels::Float(buf) => buf[i],
+        }
+    }
+
+    // An sRGB encoded channel, decoded to linear
+    fn get_srgb(&self, i: usize) -> f32 {
+        match self {
+            Texels::Unorm8(buf) => color::srgb8_to_linear(buf[i]),
+            Texels::Float(buf) => color::srgb_to_linear(buf[i]),
+        }
+    }
+
+    fn to_f32(&self) -> Vec<f32> {
+        (0..self.len()).map(|i| self.get(i)).collect()
+    }
+
+    // Same format as self
+    fn with_values(&self, values: &[f32]) -> Self {
+        match self {
+            Texels::Unorm8(_) => Texels::Unorm8(
+                values
+                    .iter()
+                    .map(|v| (v * 255.0).round().clamp(0.0, 255.0) as u8)
+                    .collect(),
+            ),
+            Texels::Float(_) => Texels::Float(values.to_vec()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Texels::Unorm8(buf) => buf.len(),
+            Texels::Float(buf) => buf.len(),
+        }
+    }
+}
+
+// One image of the mip chain, level 0 is the full resolution image
+#[derive(Clone)]
+struct MipLevel {
+    texels: Texels,
     width: usize,
     height: usize,
+}
+
+impl MipLevel {
+    // Unquantized, for downsampling
+    fn to_f32(&self) -> (Vec<f32>, usize, usize) {
+        (self.texels.to_f32(), self.width, self.height)
+    }
+
+    // Same format as self
+    fn with_values(&self, (values, width, height): &(Vec<f32>, usize, usize)) -> Self {
+        MipLevel {
+            texels: self.texels.with_values(values),
+            width: *width,
+            height: *height,
+        }
+    }
+}
+
+// The mip pyramid of anisotropic filtering (a ripmap): the full resolution image downsampled
+// along each axis independently. Level (i, j) has the width of mip level i and the height of mip
+// level j, footprints elongated along u or v are not blurred along the other axis.
+#[derive(Clone)]
+struct Ripmap {
+    // Row-major, (i, j) is at i + j * n_x
+    levels: Vec<MipLevel>,
+    n_x: usize,
+    n_y: usize,
+}
+
+impl Ripmap {
+    fn level(&self, i: usize, j: usize) -> &MipLevel {
+        &self.levels[i + j * self.n_x]
+    }
+}
+
+// (0, 0) is upper left corner. 1 (red), 3 (RGB) or 4 (RGBA) channels.
+#[derive(Clone)]
+pub struct Texture {
+    levels: Vec<MipLevel>,
     texel_width: usize,
+    mip_filter: MipFilter,
+    // The color channels are sRGB encoded and decoded when fetched, alpha is always linear
+    srgb: bool,
+    // Built on the first anisotropic sample, it takes about three times the memory of the mip
+    // chain
+    ripmap: OnceLock<Ripmap>,
 }
 
 impl std::fmt::Debug for Texture {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Texture ({} channels), w: {}, h: {}",
-            self.texel_width, self.width, self.height
+            "Texture ({} channels{}), w: {}, h: {}, mips: {}",
+            self.texel_width,
+            if self.srgb { ", sRGB" } else { "" },
+            self.levels[0].width,
+            self.levels[0].height,
+            self.levels.len()
         )
     }
 }
 
 impl Texture {
-    pub fn from_png_file(path: impl AsRef<Path>) -> Self {
+    pub fn from_png_file(path: impl AsRef<Path>, mip_filter: MipFilter) -> Self {
         let file = File::open(path).expect("Failed to read file");
-        let decoder = png::Decoder::new(file);
-        let (info, mut reader) = decoder.read_info().expect("Failed to read info");
+        Self::from_png_reader(file, mip_filter).expect("Failed to decode png")
+    }
+
+    /// Palette, grayscale and 16-bit pngs are converted to 8-bit RGB(A). The color channels are
+    /// treated as sRGB, see `from_srgb8`. The mip chain is built with `mip_filter`.
+    pub fn from_png_reader(
+        r: impl Read,
+        mip_filter: MipFilter,
+    ) -> Result<Self, png::DecodingError> {
+        let mut decoder = png::Decoder::new(r);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
         debug_assert!(!reader.info().interlaced);
         // Allocate the output buffer.
         let mut buf = vec![0; info.buffer_size()];
         // Read the next frame. Currently this function should only called once.
         // The default options
-        reader.next_frame(&mut buf).unwrap();
-        debug_assert_eq!(info.color_type, png::ColorType::RGBA);
+        reader.next_frame(&mut buf)?;
         debug_assert_eq!(info.bit_depth, png::BitDepth::Eight);
 
-        Texture {
+        let (buf, texel_width) = match info.color_type {
+            png::ColorType::RGBA => (buf, 4),
+            png::ColorType::RGB => (buf, 3),
+            png::ColorType::Grayscale => (buf.iter().flat_map(|&v| [v, v, v]).collect(), 3),
+            png::ColorType::GrayscaleAlpha => (
+                buf.chunks(2)
+                    .flat_map(|va| [va[0], va[0], va[0], va[1]])
+                    .collect(),
+                4,
+            ),
+            png::ColorType::Indexed => unreachable!("Palettes are expanded by the decoder"),
+        };
+
+        Ok(Self::from_srgb8(
             buf,
-            width: info.width as usize,
-            height: info.height as usize,
-            texel_width: 4,
+            info.width as usize,
+            info.height as usize,
+            texel_width,
+            mip_filter,
+        ))
+    }
+
+    /// From 8-bit channels, interleaved and row by row. The full mip chain is built with
+    /// `mip_filter`, see `generate_mips`.
+    pub fn from_unorm8(
+        buf: Vec<u8>,
+        width: usize,
+        height: usize,
+        channels: usize,
+        mip_filter: MipFilter,
+    ) -> Self {
+        let texels = Texels::Unorm8(buf);
+        Self::from_texels(texels, width, height, channels, false, mip_filter)
+    }
+
+    /// Same as `from_unorm8` with sRGB encoded color channels, as in most images. They are
+    /// decoded to linear when fetched and the mips are filtered in linear space.
+    pub fn from_srgb8(
+        buf: Vec<u8>,
+        width: usize,
+        height: usize,
+        channels: usize,
+        mip_filter: MipFilter,
+    ) -> Self {
+        let texels = Texels::Unorm8(buf);
+        Self::from_texels(texels, width, height, channels, true, mip_filter)
+    }
+
+    /// Same as `from_unorm8` with float channels, which are not clamped.
+    pub fn from_f32(
+        buf: Vec<f32>,
+        width: usize,
+        height: usize,
+        channels: usize,
+        mip_filter: MipFilter,
+    ) -> Self {
+        let texels = Texels::Float(buf);
+        Self::from_texels(texels, width, height, channels, false, mip_filter)
+    }
+
+    fn from_texels(
+        texels: Texels,
+        width: usize,
+        height: usize,
+        channels: usize,
+        srgb: bool,
+        mip_filter: MipFilter,
+    ) -> Self {
+        assert!([1, 3, 4].contains(&channels), "{} channels", channels);
+        assert_eq!(texels.len(), width * height * channels);
+        let mut tex = Self {
+            levels: vec![MipLevel {
+                texels,
+                width,
+                height,
+            }],
+            texel_width: channels,
+            mip_filter,
+            srgb,
+            ripmap: OnceLock::new(),
+        };
+        tex.generate_mips(mip_filter);
+        tex
+    }
+
+    /// Rebuilds the mip chain from the full resolution image, down to 1x1, e.g. with another
+    /// filter than the one the texture was loaded with. Each level is downsampled from the
+    /// (unquantized) one above it.
+    pub fn generate_mips(&mut self, filter: MipFilter) {
+        self.levels.truncate(1);
+        self.mip_filter = filter;
+        self.ripmap = OnceLock::new();
+        let mut level = self.linear_values(&self.levels[0]);
+        while level.1 > 1 || level.2 > 1 {
+            level = downsample(&level.0, level.1, level.2, self.texel_width, filter);
+            self.levels.push(self.encoded_level(&level));
         }
     }
 
-    pub fn read_texel(&self, x: usize, y: usize) -> Color {
-        debug_assert!(self.texel_width == 3 || self.texel_width == 4);
-        debug_assert!(x < self.width, "x: {}", x);
-        debug_assert!(y < self.height, "y: {}", y);
-        let texel_start = x * self.texel_width + y * self.texel_width * self.width;
-        let mut rgba: [u8; 4] = [
-            self.buf[texel_start],
-            self.buf[texel_start + 1],
-            self.buf[texel_start + 2],
-            255,
-        ];
-        if self.texel_width == 4 {
-            rgba[3] = self.buf[texel_start + 3];
+    // Filtered in linear space, like the mip chain
+    fn ripmap(&self) -> &Ripmap {
+        self.ripmap.get_or_init(|| {
+            let (channels, filter) = (self.texel_width, self.mip_filter);
+            let mut levels = Vec::new();
+            let mut n_y = 0;
+            // Each row of the pyramid halves the width of its first level, which is the full
+            // resolution image with the height halved n_y times
+            let mut row_start = self.linear_values(&self.levels[0]);
+            loop {
+                let mut level = row_start.clone();
+                levels.push(self.encoded_level(&level));
+                while level.1 > 1 {
+                    level = downsample_x(&level.0, level.1, level.2, channels, filter);
+                    levels.push(self.encoded_level(&level));
+                }
+                n_y += 1;
+                if row_start.2 == 1 {
+                    break;
+                }
+                row_start = downsample_y(&row_start.0, row_start.1, row_start.2, channels, filter);
+            }
+            Ripmap {
+                n_x: levels.len() / n_y,
+                n_y,
+                levels,
+            }
+        })
+    }
+
+    // Unquantized and decoded to linear, for downsampling
+    fn linear_values(&self, level: &MipLevel) -> (Vec<f32>, usize, usize) {
+        let (values, width, height) = level.to_f32();
+        (self.to_linear(values), width, height)
+    }
+
+    // Linear values encoded in the format of the full resolution image
+    fn encoded_level(&self, (values, width, height): &(Vec<f32>, usize, usize)) -> MipLevel {
+        self.levels[0].with_values(&(self.to_encoded(values.clone()), *width, *height))
+    }
+
+    // The color channels of interleaved texels decoded from sRGB, if the texture is sRGB
+    fn to_linear(&self, values: Vec<f32>) -> Vec<f32> {
+        self.map_color_channels(values, color::srgb_to_linear)
+    }
+
+    fn to_encoded(&self, values: Vec<f32>) -> Vec<f32> {
+        self.map_color_channels(values, color::linear_to_srgb)
+    }
+
+    fn map_color_channels(&self, mut values: Vec<f32>, f: fn(f32) -> f32) -> Vec<f32> {
+        if self.srgb {
+            for texel in values.chunks_exact_mut(self.texel_width) {
+                texel.iter_mut().take(3).for_each(|v| *v = f(*v));
+            }
         }
+        values
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    pub fn mip_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        self.srgb
+    }
 
-        Color::from_rgba(rgba)
+    pub fn read_texel(&self, x: usize, y: usize) -> Color {
+        self.read_level_texel(&self.levels[0], x, y)
     }
 
+    fn read_level_texel(&self, level: &MipLevel, x: usize, y: usize) -> Color {
+        let MipLevel {
+            texels,
+            width,
+            height,
+        } = level;
+        debug_assert!(x < *width, "x: {}", x);
+        debug_assert!(y < *height, "y: {}", y);
+        let texel_start = x * self.texel_width + y * self.texel_width * width;
+        let channel = |i: usize| {
+            // Not alpha
+            if self.srgb && i < 3 {
+                texels.get_srgb(texel_start + i)
+            } else {
+                texels.get(texel_start + i)
+            }
+        };
+        match self.texel_width {
+            1 => Color {
+                r: channel(0),
+                g: 0.0,
+                b: 0.0,
+                a: 1.0,
+            },
+            3 => Color {
+                r: channel(0),
+                g: channel(1),
+                b: channel(2),
+                a: 1.0,
+            },
+            _ => Color {
+                r: channel(0),
+                g: channel(1),
+                b: channel(2),
+                a: channel(3),
+            },
+        }
+    }
+
+    /// Bilinear sample of the full resolution image, clamped to the edge outside of [0, 1].
     pub fn sample(&self, u: f32, v: f32) -> Color {
-        debug_assert!((0.0..=1.0).contains(&u), "Incorrect u coordinate: {}", u);
-        debug_assert!((0.0..=1.0).contains(&v), "Incorrect v coordinate: {}", v);
-        let x = u * (self.width - 1) as f32;
-        let y = v * (self.height - 1) as f32;
+        self.sample_bilinear(&self.levels[0], u, v, &Sampler::default())
+    }
+
+    /// Level of detail for a footprint given by the screen-space derivatives of the texture
+    /// coordinates, e.g. from `FragCoords::dfdx`/`dfdy`. 0 is the full resolution image, each
+    /// level above halves it. Not clamped to the mip chain.
+    pub fn lod(&self, duv_dx: [f32; 2], duv_dy: [f32; 2]) -> f32 {
+        let (w, h) = (self.width() as f32, self.height() as f32);
+        let len_sq = |d: [f32; 2]| (d[0] * w).powi(2) + (d[1] * h).powi(2);
+        // log2(sqrt(x)) == log2(x) / 2
+        0.5 * len_sq(duv_dx).max(len_sq(duv_dy)).log2()
+    }
+
+    /// Sample at an explicit level of detail, see `lod`. Biased and clamped by the sampler, then
+    /// clamped to the mip chain.
+    pub fn sample_level(&self, u: f32, v: f32, lod: f32, sampler: &Sampler) -> Color {
+        let lod = sampler
+            .adjust_lod(lod)
+            .clamp(0.0, (self.levels.len() - 1) as f32);
+        match sampler.filter {
+            FilterMode::Nearest => {
+                self.sample_nearest(&self.levels[lod.round() as usize], u, v, sampler)
+            }
+            FilterMode::Bilinear => {
+                self.sample_bilinear(&self.levels[lod.round() as usize], u, v, sampler)
+            }
+            FilterMode::Trilinear => {
+                self.sample_between_levels(lod, u, v, sampler, Self::sample_bilinear)
+            }
+            FilterMode::NearestMipLinear => {
+                self.sample_between_levels(lod, u, v, sampler, Self::sample_nearest)
+            }
+        }
+    }
+
+    /// Sample with the level of detail selected by the footprint, see `lod`. Anisotropic if
+    /// enabled in the sampler.
+    pub fn sample_grad(
+        &self,
+        u: f32,
+        v: f32,
+        duv_dx: [f32; 2],
+        duv_dy: [f32; 2],
+        sampler: &Sampler,
+    ) -> Color {
+        if sampler.max_anisotropy > 1 {
+            return self.sample_anisotropic(u, v, duv_dx, duv_dy, sampler);
+        }
+        self.sample_level(u, v, self.lod(duv_dx, duv_dy), sampler)
+    }
+
+    // Probes spread along the major axis of the footprint. Each probe covers its share of the
+    // major axis and the minor axis, it is sampled from the ripmap with the extent of that
+    // footprint along u and v. If the probes are limited by `max_anisotropy`, only the axis along
+    // which the footprint is longer is blurred.
+    fn sample_anisotropic(
+        &self,
+        u: f32,
+        v: f32,
+        duv_dx: [f32; 2],
+        duv_dy: [f32; 2],
+        sampler: &Sampler,
+    ) -> Color {
+        let (w, h) = (self.width() as f32, self.height() as f32);
+        let texels = |d: [f32; 2]| ((d[0] * w).powi(2) + (d[1] * h).powi(2)).sqrt();
+        let (len_x, len_y) = (texels(duv_dx), texels(duv_dy));
+        let (major, minor, major_len, minor_len) = if len_x >= len_y {
+            (duv_dx, duv_dy, len_x, len_y)
+        } else {
+            (duv_dy, duv_dx, len_y, len_x)
+        };
+
+        let n_probes = (major_len / minor_len.max(f32::MIN_POSITIVE))
+            .ceil()
+            .clamp(1.0, sampler.max_anisotropy as f32);
+        let extent = |axis: usize, size: f32| {
+            let probe = (major[axis] / n_probes).abs().max(minor[axis].abs());
+            (probe * size).log2()
+        };
+        let (lod_x, lod_y) = (extent(0, w), extent(1, h));
+
+        let mut sum = Color::default();
+        for i in 0..n_probes as usize {
+            let t = (i as f32 + 0.5) / n_probes - 0.5;
+            let (probe_u, probe_v) = (u + major[0] * t, v + major[1] * t);
+            sum = sum + self.sample_ripmap(probe_u, probe_v, lod_x, lod_y, sampler);
+        }
+        sum / n_probes
+    }
+
+    // Same as `sample_level` with separate levels of detail along u and v. The mip linear filter
+    // modes blend the four closest levels.
+    fn sample_ripmap(&self, u: f32, v: f32, lod_x: f32, lod_y: f32, sampler: &Sampler) -> Color {
+        let ripmap = self.ripmap();
+        let lod_x = sampler
+            .adjust_lod(lod_x)
+            .clamp(0.0, (ripmap.n_x - 1) as f32);
+        let lod_y = sampler
+            .adjust_lod(lod_y)
+            .clamp(0.0, (ripmap.n_y - 1) as f32);
+        let sample: fn(&Self, &MipLevel, f32, f32, &Sampler) -> Color = match sampler.filter {
+            FilterMode::Nearest | FilterMode::NearestMipLinear => Self::sample_nearest,
+            FilterMode::Bilinear | FilterMode::Trilinear => Self::sample_bilinear,
+        };
+        let mip_linear = matches!(
+            sampler.filter,
+            FilterMode::Trilinear | FilterMode::NearestMipLinear
+        );
+        if !mip_linear {
+            let level = ripmap.level(lod_x.round() as usize, lod_y.round() as usize);
+            return sample(self, level, u, v, sampler);
+        }
+
+        let (i, j) = (lod_x.floor() as usize, lod_y.floor() as usize);
+        let (t_x, t_y) = (lod_x - i as f32, lod_y - j as f32);
+        let next_i = (i + 1).min(ripmap.n_x - 1);
+        let next_j = (j + 1).min(ripmap.n_y - 1);
+        let mut sum = Color::default();
+        for (i, w_x) in [(i, 1.0 - t_x), (next_i, t_x)] {
+            for (j, w_y) in [(j, 1.0 - t_y), (next_j, t_y)] {
+                if w_x * w_y > 0.0 {
+                    sum = sum + sample(self, ripmap.level(i, j), u, v, sampler) * (w_x * w_y);
+                }
+            }
+        }
+        sum
+    }
+
+    fn sample_between_levels(
+        &self,
+        lod: f32,
+        u: f32,
+        v: f32,
+        sampler: &Sampler,
+        sample: fn(&Self, &MipLevel, f32, f32, &Sampler) -> Color,
+    ) -> Color {
+        let lower = lod.floor() as usize;
+        let upper = (lower + 1).min(self.levels.len() - 1);
+        let t = lod - lower as f32;
+        let (lower, upper) = (&self.levels[lower], &self.levels[upper]);
+        if t == 0.0 {
+            return sample(self, lower, u, v, sampler);
+        }
+
+        sample(self, lower, u, v, sampler) * (1.0 - t) + sample(self, upper, u, v, sampler) * t
+    }
+
+    fn sample_nearest(&self, level: &MipLevel, u: f32, v: f32, sampler: &Sampler) -> Color {
+        sample_image(level.width, level.height, u, v, sampler, false, |x, y| {
+            self.read_level_texel(level, x, y)
+        })
+    }
+
+    fn sample_bilinear(&self, level: &MipLevel, u: f32, v: f32, sampler: &Sampler) -> Color {
+        sample_image(level.width, level.height, u, v, sampler, true, |x, y| {
+            self.read_level_texel(level, x, y)
+        })
+    }
+}
+
+/// Nearest or bilinear sample of a single image with `width` x `height` texels, e.g. a mip level,
+/// addressed according to the wrap modes of the sampler. Texel centers are at (i + 0.5) / size.
+pub(crate) fn sample_image(
+    width: usize,
+    height: usize,
+    u: f32,
+    v: f32,
+    sampler: &Sampler,
+    bilinear: bool,
+    read_texel: impl Fn(usize, usize) -> Color,
+) -> Color {
+    let fetch = |x: i64, y: i64| match (
+        sampler.wrap_u.wrap(x, width),
+        sampler.wrap_v.wrap(y, height),
+    ) {
+        (Some(x), Some(y)) => read_texel(x, y),
+        _ => sampler.border_color,
+    };
+
+    if !bilinear {
+        let x = (u * width as f32).floor() as i64;
+        let y = (v * height as f32).floor() as i64;
+        return fetch(x, y);
+    }
+
+    // Far outside of the image an f32 can't tell the texels apart anyway. Clamped so that the
+    // indices of the neighbors don't overflow.
+    let x = (u * width as f32 - 0.5).clamp(-MAX_TEXEL_COORD, MAX_TEXEL_COORD);
+    let y = (v * height as f32 - 0.5).clamp(-MAX_TEXEL_COORD, MAX_TEXEL_COORD);
+    let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+
+    let topleft = fetch(x0, y0);
+    let topright = fetch(x0 + 1, y0);
+    let botleft = fetch(x0, y0 + 1);
+    let botright = fetch(x0 + 1, y0 + 1);
+
+    let x_f = x - x.floor();
+    let y_f = y - y.floor();
+
+    let y0 = topleft * (1.0 - x_f) + topright * x_f;
+    let y1 = botleft * (1.0 - x_f) + botright * x_f;
+
+    y0 * (1.0 - y_f) + y1 * y_f
+}
+
+// 2^24, above which f32 has no fractional part
+const MAX_TEXEL_COORD: f32 = 16_777_216.0;
+
+/// Filter used to downsample each mip level from the one above it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipFilter {
+    /// Average of the texels covered by the destination texel
+    Box,
+    /// Kaiser windowed sinc. Sharper than the box filter and with less aliasing, the ringing is
+    /// clamped.
+    Kaiser,
+}
+
+const KAISER_WIDTH: f32 = 3.0;
+const KAISER_ALPHA: f32 = 4.0;
+
+impl MipFilter {
+    // Half width of the kernel, in destination texels
+    fn support(self) -> f32 {
+        match self {
+            MipFilter::Box => 0.5,
+            MipFilter::Kaiser => KAISER_WIDTH,
+        }
+    }
+
+    fn weight(self, x: f32) -> f32 {
+        match self {
+            MipFilter::Box => (x.abs() <= 0.5) as u8 as f32,
+            MipFilter::Kaiser => sinc(x) * kaiser_window(x),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        return 1.0;
+    }
+    let px = std::f32::consts::PI * x;
+    px.sin() / px
+}
+
+// Zeroth order modified Bessel function of the first kind, from its power series
+fn bessel_i0(x: f32) -> f32 {
+    let q = x * x / 4.0;
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..32 {
+        term *= q / (k * k) as f32;
+        sum += term;
+        if term < sum * 1e-8 {
+            break;
+        }
+    }
+    sum
+}
+
+fn kaiser_window(x: f32) -> f32 {
+    let t = x / KAISER_WIDTH;
+    if t.abs() > 1.0 {
+        return 0.0;
+    }
+    bessel_i0(KAISER_ALPHA * (1.0 - t * t).sqrt()) / bessel_i0(KAISER_ALPHA)
+}
+
+// Normalized weights of the source texels for each destination texel along one axis. Texels
+// outside of the image are clamped to the edge.
+fn downsample_weights(src_len: usize, dst_len: usize, filter: MipFilter) -> Vec<Vec<(usize, f32)>> {
+    let scale = src_len as f32 / dst_len as f32;
+    let radius = filter.support() * scale;
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            let first = (center - radius).floor() as isize;
+            let last = (center + radius).ceil() as isize;
+            let mut weights: Vec<(usize, f32)> = Vec::new();
+            for j in first..=last {
+                let w = filter.weight((j as f32 + 0.5 - center) / scale);
+                if w == 0.0 {
+                    continue;
+                }
+                let j = j.clamp(0, src_len as isize - 1) as usize;
+                match weights.iter_mut().find(|(k, _)| *k == j) {
+                    Some((_, sum)) => *sum += w,
+                    None => weights.push((j, w)),
+                }
+            }
+            let sum: f32 = weights.iter().map(|(_, w)| w).sum();
+            weights.iter_mut().for_each(|(_, w)| *w /= sum);
+            weights
+        })
+        .collect()
+}
+
+// Halves both dimensions (down to 1) of an image with interleaved channels, separably
+fn downsample(
+    src: &[f32],
+    width: usize,
+    height: usize,
+    channels: usize,
+    filter: MipFilter,
+) -> (Vec<f32>, usize, usize) {
+    let (horizontal, dst_width, _) = downsample_x(src, width, height, channels, filter);
+    downsample_y(&horizontal, dst_width, height, channels, filter)
+}
+
+// Halves the width (down to 1)
+fn downsample_x(
+    src: &[f32],
+    width: usize,
+    height: usize,
+    channels: usize,
+    filter: MipFilter,
+) -> (Vec<f32>, usize, usize) {
+    let dst_width = (width / 2).max(1);
+    let mut out = vec![0.0; dst_width * height * channels];
+    for (x, weights) in downsample_weights(width, dst_width, filter)
+        .iter()
+        .enumerate()
+    {
+        for y in 0..height {
+            let dst = (x + y * dst_width) * channels;
+            for &(src_x, w) in weights {
+                let i = (src_x + y * width) * channels;
+                for c in 0..channels {
+                    out[dst + c] += w * src[i + c];
+                }
+            }
+        }
+    }
+    (out, dst_width, height)
+}
+
+// Halves the height (down to 1)
+fn downsample_y(
+    src: &[f32],
+    width: usize,
+    height: usize,
+    channels: usize,
+    filter: MipFilter,
+) -> (Vec<f32>, usize, usize) {
+    let dst_height = (height / 2).max(1);
+    let mut out = vec![0.0; width * dst_height * channels];
+    for (y, weights) in downsample_weights(height, dst_height, filter)
+        .iter()
+        .enumerate()
+    {
+        for &(src_y, w) in weights {
+            for x in 0..width {
+                let dst = (x + y * width) * channels;
+                let i = (x + src_y * width) * channels;
+                for c in 0..channels {
+                    out[dst + c] += w * src[i + c];
+                }
+            }
+        }
+    }
+    (out, width, dst_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::WrapMode;
+
+    const CLAMP: Sampler = Sampler {
+        filter: FilterMode::Trilinear,
+        max_anisotropy: 1,
+        lod_bias: 0.0,
+        min_lod: 0.0,
+        max_lod: f32::MAX,
+        wrap_u: WrapMode::ClampToEdge,
+        wrap_v: WrapMode::ClampToEdge,
+        border_color: Color::red(),
+    };
+
+    fn bytes(level: &MipLevel) -> Vec<u8> {
+        match &level.texels {
+            Texels::Unorm8(buf) => buf.clone(),
+            Texels::Float(_) => panic!("Not an 8-bit texture"),
+        }
+    }
+
+    // 4x2 RGB, left half black and right half white
+    fn halves() -> Texture {
+        let row = [0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255];
+        Texture::from_unorm8([row, row].concat(), 4, 2, 3, MipFilter::Box)
+    }
+
+    #[test]
+    fn mip_chain() {
+        let tex = halves();
+        assert_eq!(tex.mip_levels(), 3);
+        let sizes: Vec<_> = tex.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(4, 2), (2, 1), (1, 1)]);
+        assert_eq!(bytes(&tex.levels[1]), [0, 0, 0, 255, 255, 255]);
+        assert_eq!(bytes(&tex.levels[2]), [128, 128, 128]);
+
+        let odd = Texture::from_unorm8(
+            vec![0, 40, 80, 120, 160, 200, 240, 250, 255],
+            3,
+            3,
+            1,
+            MipFilter::Box,
+        );
+        let sizes: Vec<_> = odd.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(3, 3), (1, 1)]);
+        // All nine texels are averaged
+        assert_eq!(bytes(&odd.levels[1]), [149]);
+    }
+
+    #[test]
+    fn lod() {
+        let tex = halves();
+        // One texel per pixel
+        assert_eq!(tex.lod([0.25, 0.0], [0.0, 0.5]), 0.0);
+        // The larger axis of the footprint decides
+        assert_eq!(tex.lod([0.5, 0.0], [0.0, 0.5]), 1.0);
+        assert_eq!(tex.lod([0.0, 0.0], [0.0, 2.0]), 2.0);
+        assert!(tex.lod([0.0, 0.0], [0.0, 0.0]) < 0.0);
+    }
+
+    #[test]
+    fn sample_grad() {
+        let tex = halves();
+        let at = |lod: f32| {
+            let d = 0.25 * 2.0f32.powf(lod);
+            // Center of the upper left texel
+            tex.sample_grad(0.125, 0.25, [d, 0.0], [0.0, 0.0], &CLAMP).r
+        };
+        assert_eq!(at(-1.0), 0.0);
+        assert_eq!(at(0.0), 0.0);
+        assert_eq!(at(1.0), 0.0);
+        // Halfway between black and the 1x1 grey level
+        let grey = 128.0 / 255.0;
+        assert!((at(1.5) - grey * 0.5).abs() < 0.0001);
+        assert!((at(2.0) - grey).abs() < 0.0001);
+        assert!((at(5.0) - grey).abs() < 0.0001);
+    }
+
+    #[test]
+    fn filter_modes() {
+        let tex = halves();
+        let at = |filter: FilterMode, lod: f32| {
+            let sampler = Sampler { filter, ..CLAMP };
+            tex.sample_level(0.6, 0.25, lod, &sampler).r
+        };
+        let grey = 128.0 / 255.0;
+        let close = |a: f32, b: f32| (a - b).abs() < 0.0001;
 
-        let topleft = self.read_texel(x.floor() as usize, y.floor() as usize);
-        let topright = self.read_texel(x.ceil() as usize, y.floor() as usize);
-        let botleft = self.read_texel(x.floor() as usize, y.ceil() as usize);
-        let botright = self.read_texel(x.ceil() as usize, y.ceil() as usize);
+        // Level 0 at x = 1.9, level 1 at x = 0.7 (relative to the texel centers)
+        assert_eq!(at(FilterMode::Nearest, 0.25), 1.0);
+        assert!(close(at(FilterMode::Bilinear, 0.25), 0.9));
+        assert!(close(
+            at(FilterMode::Trilinear, 0.25),
+            0.75 * 0.9 + 0.25 * 0.7
+        ));
+        assert_eq!(at(FilterMode::NearestMipLinear, 0.25), 1.0);
+
+        assert!(close(at(FilterMode::Nearest, 1.5), grey));
+        assert!(close(at(FilterMode::Bilinear, 1.25), 0.7));
+        assert!(close(
+            at(FilterMode::Trilinear, 1.5),
+            0.5 * 0.7 + 0.5 * grey
+        ));
+        assert!(close(
+            at(FilterMode::NearestMipLinear, 1.5),
+            0.5 + 0.5 * grey
+        ));
+    }
+
+    #[test]
+    fn kaiser_mips() {
+        let flat = Texture::from_unorm8(vec![200; 5 * 3 * 4], 5, 3, 4, MipFilter::Kaiser);
+        assert_eq!(flat.mip_levels(), 3);
+        assert!(flat
+            .levels
+            .iter()
+            .all(|l| bytes(l).iter().all(|&v| v == 200)));
+
+        let row = [0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255];
+        let tex = Texture::from_unorm8([row, row].concat(), 4, 2, 3, MipFilter::Kaiser);
+        // The wider kernel reaches across the edge, symmetrically
+        let [left, right] = [bytes(&tex.levels[1])[0], bytes(&tex.levels[1])[3]];
+        assert!(left > 0 && left < 32);
+        assert_eq!(left, 255 - right);
+        assert_eq!(bytes(&tex.levels[2]), [128, 128, 128]);
+
+        // Same as rebuilding the mips of a texture loaded with another filter
+        let mut rebuilt = halves();
+        rebuilt.generate_mips(MipFilter::Kaiser);
+        let bufs = |t: &Texture| t.levels.iter().map(bytes).collect::<Vec<_>>();
+        assert_eq!(bufs(&rebuilt), bufs(&tex));
+    }
+
+    #[test]
+    fn anisotropic() {
+        // 8x8, white even rows and black odd rows
+        let buf = (0..8 * 8 * 3)
+            .map(|i| if (i / (8 * 3)) % 2 == 0 { 255 } else { 0 })
+            .collect();
+        let tex = Texture::from_unorm8(buf, 8, 8, 3, MipFilter::Box);
+        let at = |max_anisotropy: u32| {
+            let sampler = Sampler {
+                max_anisotropy,
+                ..Default::default()
+            };
+            // Four texels wide and one texel high, centered on a white row
+            tex.sample_grad(0.5, 2.5 / 8.0, [0.5, 0.0], [0.0, 0.125], &sampler)
+                .r
+        };
+
+        // Isotropic filtering blurs the rows together
+        let grey = 128.0 / 255.0;
+        assert!((at(1) - grey).abs() < 0.0001);
+        // Four probes along the rows at full resolution
+        assert_eq!(at(4), 1.0);
+        assert_eq!(at(16), 1.0);
+        // Two probes don't cover the footprint at full resolution, the ripmap level with half the
+        // width still keeps the rows apart
+        assert!((at(2) - 1.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn ripmap() {
+        let tex = halves();
+        let ripmap = tex.ripmap();
+        assert_eq!((ripmap.n_x, ripmap.n_y), (3, 2));
+        let sizes: Vec<_> = ripmap.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(4, 2), (2, 2), (1, 2), (4, 1), (2, 1), (1, 1)]);
+        // Only downsampled horizontally
+        assert_eq!(
+            bytes(ripmap.level(1, 0)),
+            [[0, 0, 0, 255, 255, 255]; 2].concat()
+        );
+        assert_eq!(bytes(ripmap.level(2, 0)), [128; 6]);
+        // The diagonal is the mip chain
+        assert_eq!(bytes(ripmap.level(0, 0)), bytes(&tex.levels[0]));
+        assert_eq!(bytes(ripmap.level(1, 1)), bytes(&tex.levels[1]));
+    }
+
+    #[test]
+    fn wrapped_sampling() {
+        let tex = halves();
+        let at = |u: f32, v: f32, sampler: &Sampler| tex.sample_level(u, v, 0.0, sampler).r;
+        let repeat = Sampler {
+            filter: FilterMode::Bilinear,
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+            ..Default::default()
+        };
+        let mirror = Sampler {
+            wrap_u: WrapMode::MirroredRepeat,
+            ..repeat
+        };
+        let border = Sampler {
+            wrap_v: WrapMode::ClampToBorder,
+            ..CLAMP
+        };
+
+        // Halfway between the last and the first texel
+        assert_eq!(at(0.0, 0.25, &repeat), 0.5);
+        assert_eq!(at(1.0, 0.25, &repeat), 0.5);
+        assert_eq!(at(-3.875, 0.25, &repeat), 0.0);
+        // The mirrored tile starts with the last column
+        assert_eq!(at(1.125, 0.25, &mirror), 1.0);
+        assert_eq!(at(2.125, 0.25, &mirror), 0.0);
+        assert_eq!(at(0.0, 0.25, &mirror), 0.0);
+        // Only v is clamped to the border
+        assert_eq!(at(-5.0, 0.25, &border), 0.0);
+        assert_eq!(at(0.125, 1.5, &border), 1.0);
+        assert_eq!(tex.sample_level(0.125, -1.5, 0.0, &border), Color::red());
+    }
+
+    #[test]
+    fn far_outside() {
+        let tex = halves();
+        let clamp = Sampler {
+            filter: FilterMode::Bilinear,
+            ..Default::default()
+        };
+        let at = |u: f32, sampler: &Sampler| tex.sample_level(u, 0.25, 0.0, sampler).r;
+        assert_eq!(at(1e30, &clamp), 1.0);
+        assert_eq!(at(f32::INFINITY, &clamp), 1.0);
+        assert_eq!(at(-1e30, &clamp), 0.0);
+        assert_eq!(at(f32::NEG_INFINITY, &clamp), 0.0);
+
+        for wrap_u in [
+            WrapMode::Repeat,
+            WrapMode::MirroredRepeat,
+            WrapMode::ClampToBorder,
+            WrapMode::MirrorOnce,
+        ] {
+            let sampler = Sampler { wrap_u, ..clamp };
+            for u in [1e30, -1e30, f32::INFINITY, f32::NEG_INFINITY] {
+                assert!((0.0..=1.0).contains(&at(u, &sampler)), "{:?} {}", wrap_u, u);
+            }
+        }
+    }
+
+    #[test]
+    fn lod_bias_and_clamp() {
+        let tex = halves();
+        let at = |lod: f32, sampler: Sampler| tex.sample_level(0.6, 0.25, lod, &sampler).r;
+        let grey = 128.0 / 255.0;
+
+        let biased = Sampler {
+            lod_bias: 2.0,
+            ..CLAMP
+        };
+        assert!((at(0.0, biased) - grey).abs() < 0.0001);
+        let clamped = Sampler {
+            max_lod: 1.0,
+            ..biased
+        };
+        assert!((at(0.0, clamped) - 0.7).abs() < 0.0001);
+        let min_lod = Sampler {
+            min_lod: 1.0,
+            ..CLAMP
+        };
+        assert!((at(0.0, min_lod) - 0.7).abs() < 0.0001);
+    }
+
+    #[test]
+    fn float_texels() {
+        // Single channel, not clamped
+        let tex = Texture::from_f32(vec![-1.0, 3.0, 0.5, 2.0], 2, 2, 1, MipFilter::Box);
+        assert_eq!(tex.read_texel(1, 0).r, 3.0);
+        assert_eq!(tex.read_texel(0, 1).a, 1.0);
+        assert_eq!(tex.mip_levels(), 2);
+        let sampler = Sampler {
+            filter: FilterMode::Bilinear,
+            ..CLAMP
+        };
+        assert_eq!(tex.sample_level(0.5, 0.5, 1.0, &sampler).r, 1.125);
+        assert_eq!(tex.sample_level(0.5, 0.25, 0.0, &sampler).r, 1.0);
+    }
 
-        let x_f = x.fract();
-        let y_f = y.fract();
+    #[test]
+    fn srgb_texels() {
+        // Mid gray in sRGB next to black, alpha is not decoded
+        let tex = Texture::from_srgb8(
+            vec![188, 188, 188, 128, 0, 0, 0, 128],
+            2,
+            1,
+            4,
+            MipFilter::Box,
+        );
+        assert!(tex.is_srgb());
+        let gray = tex.read_texel(0, 0);
+        assert!((gray.r - 0.5).abs() < 0.005, "{:?}", gray);
+        assert_eq!(gray.a, 128.0 / 255.0);
 
-        let y0 = topleft * (1.0 - x_f) + topright * x_f;
-        let y1 = botleft * (1.0 - x_f) + botright * x_f;
+        // The mip is filtered in linear space, not from the average of the encoded values
+        let mip = tex.sample_level(0.5, 0.5, 1.0, &CLAMP);
+        assert!((mip.r - 0.25).abs() < 0.005, "{:?}", mip);
+        assert_eq!(mip.a, 128.0 / 255.0);
+        // So is the ripmap, its level with half the width is the same 1x1 image
+        assert_eq!(bytes(tex.ripmap().level(1, 0)), bytes(&tex.levels[1]));
 
-        y0 * (1.0 - y_f) + y1 * y_f
+        let linear = Texture::from_unorm8(
+            vec![188, 188, 188, 128, 0, 0, 0, 128],
+            2,
+            1,
+            4,
+            MipFilter::Box,
+        );
+        assert!(!linear.is_srgb());
+        assert_eq!(linear.read_texel(0, 0).r, 188.0 / 255.0);
     }
 }
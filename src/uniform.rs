@@ -1,4 +1,5 @@
 use crate::math::{CameraSpace, ClipSpace, Mat4, WorldSpace};
+use crate::sampler::Sampler;
 use crate::texture::Texture;
 
 #[derive(Clone, Debug)]
@@ -8,16 +9,25 @@ pub struct UniformBlock {
     pub projection: Mat4<CameraSpace, ClipSpace>,
 }
 
+/// Textures and samplers are bound to independent slots, a shader combines them as it likes.
 #[derive(Clone, Debug)]
 pub struct Uniforms {
-    textures: Vec<Texture>,
+    textures: Vec<Option<Texture>>,
+    samplers: Vec<Option<Sampler>>,
     uniform_block: UniformBlock,
 }
 
+impl Default for Uniforms {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Uniforms {
     pub fn new() -> Self {
         Uniforms {
             textures: Vec::new(),
+            samplers: Vec::new(),
             uniform_block: UniformBlock {
                 world: Mat4::<WorldSpace>::identity(),
                 view: Mat4::<WorldSpace, CameraSpace>::identity(),
@@ -26,14 +36,39 @@ impl Uniforms {
         }
     }
 
-    pub fn bind_texture(&mut self, index: usize, tex: Texture) {
-        // TODO: Proper support for arbitrary (needs remapping vec)
-        assert!(self.textures.len() == index);
-        self.textures.push(tex);
+    /// Returns the texture that was bound to the slot before, if any.
+    pub fn bind_texture(&mut self, index: usize, tex: Texture) -> Option<Texture> {
+        bind(&mut self.textures, index, Some(tex))
     }
 
+    pub fn unbind_texture(&mut self, index: usize) -> Option<Texture> {
+        bind(&mut self.textures, index, None)
+    }
+
+    /// Panics if no texture is bound to the slot.
     pub fn get_texture(&self, index: usize) -> &Texture {
-        &self.textures[index]
+        self.textures
+            .get(index)
+            .and_then(Option::as_ref)
+            .unwrap_or_else(|| panic!("No texture bound at index {}", index))
+    }
+
+    /// Returns the sampler that was bound to the slot before, if any.
+    pub fn bind_sampler(&mut self, index: usize, sampler: Sampler) -> Option<Sampler> {
+        bind(&mut self.samplers, index, Some(sampler))
+    }
+
+    pub fn unbind_sampler(&mut self, index: usize) -> Option<Sampler> {
+        bind(&mut self.samplers, index, None)
+    }
+
+    /// `Sampler::default()` if no sampler is bound to the slot.
+    pub fn get_sampler(&self, index: usize) -> Sampler {
+        self.samplers
+            .get(index)
+            .copied()
+            .flatten()
+            .unwrap_or_default()
     }
 
     pub fn read_block(&self) -> &UniformBlock {
@@ -44,3 +79,68 @@ impl Uniforms {
         &mut self.uniform_block
     }
 }
+
+fn bind<T>(slots: &mut Vec<Option<T>>, index: usize, value: Option<T>) -> Option<T> {
+    if index >= slots.len() && value.is_some() {
+        slots.resize_with(index + 1, || None);
+    }
+    slots
+        .get_mut(index)
+        .and_then(|slot| std::mem::replace(slot, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::FilterMode;
+    use crate::screenshot::write_png;
+    use crate::texture::MipFilter;
+
+    fn texture(width: usize) -> Texture {
+        let mut png = Vec::new();
+        write_png(&mut png, &vec![0xFF000000; width], width, 1).unwrap();
+        Texture::from_png_reader(png.as_slice(), MipFilter::Box).unwrap()
+    }
+
+    #[test]
+    fn texture_slots() {
+        let mut uniforms = Uniforms::new();
+        assert!(uniforms.bind_texture(3, texture(1)).is_none());
+        assert!(uniforms.bind_texture(1, texture(2)).is_none());
+        assert_eq!(uniforms.get_texture(3).width(), 1);
+        assert_eq!(uniforms.get_texture(1).width(), 2);
+
+        let old = uniforms.bind_texture(3, texture(4));
+        assert_eq!(old.map(|t| t.width()), Some(1));
+        assert_eq!(uniforms.get_texture(3).width(), 4);
+
+        assert_eq!(uniforms.unbind_texture(1).map(|t| t.width()), Some(2));
+        assert!(uniforms.unbind_texture(1).is_none());
+        assert!(uniforms.unbind_texture(7).is_none());
+        assert_eq!(uniforms.get_texture(3).width(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "No texture bound at index 1")]
+    fn unbound_texture() {
+        let mut uniforms = Uniforms::new();
+        uniforms.bind_texture(0, texture(1));
+        uniforms.bind_texture(1, texture(1));
+        uniforms.unbind_texture(1);
+        uniforms.get_texture(1);
+    }
+
+    #[test]
+    fn sampler_slots() {
+        let mut uniforms = Uniforms::new();
+        let nearest = Sampler {
+            filter: FilterMode::Nearest,
+            ..Default::default()
+        };
+        assert!(uniforms.bind_sampler(2, nearest).is_none());
+        assert_eq!(uniforms.get_sampler(2), nearest);
+        assert_eq!(uniforms.get_sampler(0), Sampler::default());
+        assert_eq!(uniforms.unbind_sampler(2), Some(nearest));
+        assert_eq!(uniforms.get_sampler(2), Sampler::default());
+    }
+}
@@ -0,0 +1,226 @@
+// Golden image tests: render known scenes headless and compare them to the reference images in
+// tests/golden/. If a change to the rasterizer intentionally changes the output, regenerate the
+// references with:
+//
+//   RUSTERIZER_BLESS=1 cargo test --test golden
+//
+// and inspect the new images before committing them. On failure, the rendered image and a diff
+// image (mismatching pixels in red) are written to the cargo test tmp dir, the paths are part of
+// the panic message.
+
+use std::path::{Path, PathBuf};
+
+use rusterizer::math::{self, Mat4, WorldSpace};
+use rusterizer::{
+    mesh, screenshot, shaders, Camera, FragmentShader, Mesh, MipFilter, PipelineState, Renderer,
+    Sampler, Texture,
+};
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 120;
+
+// Max allowed difference per color channel
+const TOLERANCE: u8 = 2;
+
+enum Scene {
+    Triangle,
+    Cube,
+    Sphere,
+}
+
+impl Scene {
+    fn mesh(&self) -> Mesh<WorldSpace> {
+        match self {
+            Scene::Triangle => mesh::triangle(),
+            Scene::Cube => mesh::cube(2.0),
+            // Radius 1 to keep the vertex colors (abs of the position) in range
+            Scene::Sphere => mesh::sphere(1.0),
+        }
+    }
+
+    fn world(&self) -> Mat4<WorldSpace> {
+        match self {
+            Scene::Triangle => math::translate(0.0, 0.0, -4.0),
+            Scene::Cube => math::translate(0.0, 0.0, -1.5) * math::rotate(0.6, 0.8, 0.0),
+            Scene::Sphere => math::translate(0.0, 0.0, -2.5) * math::rotate(0.3, 0.4, 0.0),
+        }
+    }
+}
+
+fn render(scene: Scene, fragment_shader: FragmentShader) -> Vec<u32> {
+    let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+
+    let block = renderer.uniforms().write_block();
+    block.world = scene.world();
+    block.view = Camera::default().get_view_matrix();
+    block.projection = math::project(
+        1.0,
+        200.0,
+        HEIGHT as f32 / WIDTH as f32,
+        std::f32::consts::FRAC_PI_2,
+    );
+
+    let tex = Texture::from_png_file(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("images/checkerboard.png"),
+        MipFilter::Box,
+    );
+    renderer.uniforms().bind_texture(0, tex);
+    renderer.uniforms().bind_sampler(
+        0,
+        Sampler {
+            max_anisotropy: 16,
+            ..Default::default()
+        },
+    );
+
+    renderer.render(
+        &scene.mesh(),
+        &PipelineState::default(),
+        shaders::mvp_vs,
+        fragment_shader,
+    );
+    renderer.display().expect("Offscreen targets can't fail");
+    renderer.target().pixels().to_vec()
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn output_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("golden")
+        .join(format!("{name}.png"))
+}
+
+fn read_png(path: &Path) -> (Vec<u32>, usize, usize) {
+    let file = std::fs::File::open(path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to open {}: {}. Run with RUSTERIZER_BLESS=1 to create it.",
+            path.display(),
+            e
+        )
+    });
+    let decoder = png::Decoder::new(file);
+    let (info, mut reader) = decoder.read_info().expect("Failed to read png info");
+    assert_eq!(info.color_type, png::ColorType::RGB);
+    assert_eq!(info.bit_depth, png::BitDepth::Eight);
+
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf).expect("Failed to read png");
+
+    let pixels = buf
+        .chunks(3)
+        .map(|rgb| 0xFF << 24 | (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32)
+        .collect();
+    (pixels, info.width as usize, info.height as usize)
+}
+
+fn channel_diff(a: u32, b: u32) -> u8 {
+    (0..3)
+        .map(|i| {
+            let a = (a >> (i * 8)) as u8;
+            let b = (b >> (i * 8)) as u8;
+            a.abs_diff(b)
+        })
+        .max()
+        .unwrap()
+}
+
+// Mismatching pixels are red (brighter => larger difference), the rest is the reference dimmed
+fn diff_image(actual: &[u32], expected: &[u32]) -> Vec<u32> {
+    actual
+        .iter()
+        .zip(expected.iter())
+        .map(|(&a, &e)| {
+            let diff = channel_diff(a, e);
+            if diff > TOLERANCE {
+                let red = 128 + diff as u32 / 2;
+                0xFF << 24 | red << 16
+            } else {
+                0xFF << 24 | (e >> 2) & 0x3F3F3F
+            }
+        })
+        .collect()
+}
+
+fn golden(name: &str, scene: Scene, fragment_shader: FragmentShader) {
+    let actual = render(scene, fragment_shader);
+    let reference = reference_path(name);
+
+    if std::env::var_os("RUSTERIZER_BLESS").is_some() {
+        std::fs::create_dir_all(reference.parent().unwrap()).unwrap();
+        screenshot::save(&reference, &actual, WIDTH, HEIGHT).unwrap();
+        return;
+    }
+
+    let (expected, width, height) = read_png(&reference);
+    assert_eq!((width, height), (WIDTH, HEIGHT), "Size mismatch for {name}");
+
+    let n_mismatches = actual
+        .iter()
+        .zip(expected.iter())
+        .filter(|(&a, &e)| channel_diff(a, e) > TOLERANCE)
+        .count();
+
+    if n_mismatches > 0 {
+        let actual_path = output_path(&format!("{name}_actual"));
+        let diff_path = output_path(&format!("{name}_diff"));
+        std::fs::create_dir_all(actual_path.parent().unwrap()).unwrap();
+        screenshot::save(&actual_path, &actual, WIDTH, HEIGHT).unwrap();
+        screenshot::save(&diff_path, &diff_image(&actual, &expected), WIDTH, HEIGHT).unwrap();
+        panic!(
+            "{name}: {n_mismatches} pixels differ by more than {TOLERANCE} from {}\nactual: {}\ndiff: {}",
+            reference.display(),
+            actual_path.display(),
+            diff_path.display(),
+        );
+    }
+}
+
+#[test]
+fn triangle_texture() {
+    golden("triangle_texture", Scene::Triangle, shaders::texture_fs);
+}
+
+#[test]
+fn triangle_color() {
+    golden("triangle_color", Scene::Triangle, shaders::color_fs);
+}
+
+#[test]
+fn triangle_debug() {
+    golden("triangle_debug", Scene::Triangle, shaders::debug_fs);
+}
+
+#[test]
+fn cube_texture() {
+    golden("cube_texture", Scene::Cube, shaders::texture_fs);
+}
+
+#[test]
+fn cube_color() {
+    golden("cube_color", Scene::Cube, shaders::color_fs);
+}
+
+#[test]
+fn cube_debug() {
+    golden("cube_debug", Scene::Cube, shaders::debug_fs);
+}
+
+#[test]
+fn sphere_texture() {
+    golden("sphere_texture", Scene::Sphere, shaders::texture_fs);
+}
+
+#[test]
+fn sphere_color() {
+    golden("sphere_color", Scene::Sphere, shaders::color_fs);
+}
+
+#[test]
+fn sphere_debug() {
+    golden("sphere_debug", Scene::Sphere, shaders::debug_fs);
+}